@@ -0,0 +1,520 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::tokenizer::Token;
+
+/// Byte range of the source a token or node was read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+/// Index of a node in its `Ast`, stable for the lifetime of the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NodeKind {
+    /// Number or constant such as `pi`, `true` or `ans`.
+    Literal(Token),
+    Identifier(String),
+    /// Prefix or postfix operator such as `not` or `!`.
+    Unary(Token, NodeId),
+    Binary(Token, NodeId, NodeId),
+    /// Built-in or user defined function, or a matrix, with its arguments
+    /// in order.
+    Call(Token, Vec<NodeId>),
+}
+
+impl NodeKind {
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            Self::Literal(_) | Self::Identifier(_) => Vec::new(),
+            Self::Unary(_, arg) => vec![*arg],
+            Self::Binary(_, left, right) => vec![*left, *right],
+            Self::Call(_, args) => args.clone(),
+        }
+    }
+
+    /// The same node with `children` in place of its own, in order.
+    pub fn with_children(&self, children: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut children = children.into_iter();
+        let mut next = || children.next().unwrap_or(NodeId(0));
+        match self {
+            Self::Literal(_) | Self::Identifier(_) => self.clone(),
+            Self::Unary(token, _) => Self::Unary(token.clone(), next()),
+            Self::Binary(token, _, _) => Self::Binary(token.clone(), next(), next()),
+            Self::Call(token, args) => {
+                Self::Call(token.clone(), args.iter().map(|_| next()).collect())
+            }
+        }
+    }
+
+    /// Whether the parser could have given this node its token and number
+    /// of children.
+    fn is_valid(&self) -> bool {
+        match self {
+            Self::Literal(token) => token.is_value() && !matches!(token, Token::Identifier(_)),
+            Self::Identifier(_) => true,
+            Self::Unary(token, _) => {
+                matches!(
+                    token,
+                    Token::Factorial | Token::DoubleFactorial | Token::Not
+                )
+            }
+            Self::Binary(token, _, _) => matches!(
+                token,
+                Token::Add
+                    | Token::Sub
+                    | Token::PlusMinus
+                    | Token::ImplMul
+                    | Token::Mul
+                    | Token::Div
+                    | Token::Pow
+                    | Token::Less
+                    | Token::LessEqual
+                    | Token::Equal
+                    | Token::NotEqual
+                    | Token::GreaterEqual
+                    | Token::Greater
+                    | Token::And
+                    | Token::Or
+                    | Token::Assign
+            ),
+            Self::Call(Token::Matrix | Token::Call(_), args) => !args.is_empty(),
+            Self::Call(token, args) => {
+                let operator = Self::Unary(token.clone(), NodeId(0)).is_valid()
+                    || Self::Binary(token.clone(), NodeId(0), NodeId(0)).is_valid();
+                let punctuation = matches!(
+                    token,
+                    Token::OpenParenthesis
+                        | Token::CloseParenthesis
+                        | Token::OpenBracket
+                        | Token::CloseBracket
+                        | Token::Comma
+                        | Token::Semicolon
+                );
+                let (min, max) = token.arity();
+                !token.is_value() && !operator && !punctuation && (min..=max).contains(&args.len())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+    pub parent: Option<NodeId>,
+}
+
+/// Expression stored as a flat list of nodes. Children always come before
+/// their parent and every node but the last, the root, has one, so the
+/// nodes of a subtree are found by walking down from it. Nodes keep where
+/// they were read to locate errors and show values on hover, trees built
+/// by symbolic operations have empty spans.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "Vec<NodeKind>")]
+pub struct Ast {
+    nodes: Vec<Node>,
+}
+
+/// Trees are saved as their nodes in order, the spans are not kept.
+impl Serialize for Ast {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.nodes.iter().map(|node| &node.kind))
+    }
+}
+
+/// Nodes as read from a saved session, which must form a tree the parser
+/// could have built.
+impl TryFrom<Vec<NodeKind>> for Ast {
+    type Error = String;
+
+    fn try_from(nodes: Vec<NodeKind>) -> Result<Self, String> {
+        if nodes.is_empty() {
+            return Err("empty tree".to_string());
+        }
+        let mut ast = Self::default();
+        for kind in nodes {
+            let children = kind.children();
+            let unused = |child: &NodeId| {
+                ast.nodes
+                    .get(child.0)
+                    .is_some_and(|node| node.parent.is_none())
+            };
+            let distinct = (1..children.len()).all(|i| !children[..i].contains(&children[i]));
+            if !distinct || !children.iter().all(unused) {
+                return Err("nodes do not form a tree".to_string());
+            }
+            if !kind.is_valid() {
+                let token = match &kind {
+                    NodeKind::Identifier(name) => name.clone(),
+                    NodeKind::Literal(token)
+                    | NodeKind::Unary(token, _)
+                    | NodeKind::Binary(token, _, _)
+                    | NodeKind::Call(token, _) => token.to_string(),
+                };
+                return Err(format!("malformed {token} node"));
+            }
+            ast.push(kind, Span::default());
+        }
+        match ast
+            .nodes
+            .iter()
+            .filter(|node| node.parent.is_none())
+            .count()
+        {
+            1 => Ok(ast),
+            _ => Err("nodes do not form a tree".to_string()),
+        }
+    }
+}
+
+/// Trees are equal when they have the same nodes in the same places,
+/// wherever they were read from.
+impl PartialEq for Ast {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.nodes.is_empty(), other.nodes.is_empty()) {
+            (false, false) => self.same(self.root(), other, other.root()),
+            (empty, other_empty) => empty == other_empty,
+        }
+    }
+}
+
+impl Ast {
+    /// Tree of a single node for a token that takes no arguments.
+    pub fn new(token: Token) -> Self {
+        let mut ast = Self::default();
+        ast.leaf(token, Span::default());
+        ast
+    }
+
+    pub fn binary(token: Token, left: Ast, right: Ast) -> Self {
+        Self::compose(
+            &NodeKind::Binary(token, NodeId(0), NodeId(0)),
+            vec![left, right],
+        )
+    }
+
+    pub fn call(token: Token, args: Vec<Ast>) -> Self {
+        let kind = NodeKind::Call(token, args.iter().map(|_| NodeId(0)).collect());
+        Self::compose(&kind, args)
+    }
+
+    /// Tree with a node like `kind` at the root and `children` below it in
+    /// place of the children of `kind`.
+    pub fn compose(kind: &NodeKind, children: Vec<Ast>) -> Self {
+        let mut ast = Self {
+            nodes: Vec::with_capacity(
+                children
+                    .iter()
+                    .map(|child| child.nodes.len())
+                    .sum::<usize>()
+                    + 1,
+            ),
+        };
+        let ids: Vec<_> = children
+            .into_iter()
+            .map(|child| ast.append(child))
+            .collect();
+        ast.push(kind.with_children(ids), Span::default());
+        ast
+    }
+
+    /// Adds the nodes of `tree` after those of `self`, giving the id of its
+    /// root.
+    fn append(&mut self, tree: Ast) -> NodeId {
+        let offset = self.nodes.len();
+        let moved = |id: NodeId| NodeId(id.0 + offset);
+        self.nodes.extend(tree.nodes.into_iter().map(|node| {
+            Node {
+                kind: node
+                    .kind
+                    .with_children(node.kind.children().into_iter().map(moved)),
+                span: node.span,
+                parent: node.parent.map(moved),
+            }
+        }));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Copy of the subtree at `id` as a tree of its own.
+    pub fn subtree(&self, id: NodeId) -> Ast {
+        if id == self.root() {
+            return self.clone();
+        }
+        let mut ids = vec![id];
+        let mut i = 0;
+        while let Some(next) = ids.get(i) {
+            ids.extend(self.node(*next).kind.children());
+            i += 1;
+        }
+        // Kept in the order they are stored, so children still come first
+        ids.sort_unstable_by_key(|id| id.0);
+        let mut moved = vec![NodeId(0); id.0 + 1];
+        let mut ast = Self {
+            nodes: Vec::with_capacity(ids.len()),
+        };
+        for old in ids {
+            let node = self.node(old);
+            let children = node.kind.children().into_iter().map(|child| moved[child.0]);
+            moved[old.0] = ast.push(node.kind.with_children(children), node.span);
+        }
+        ast
+    }
+
+    /// Adds a node whose children are already in the tree.
+    pub fn push(&mut self, kind: NodeKind, span: Span) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        for child in kind.children() {
+            self.nodes[child.0].parent = Some(id);
+        }
+        self.nodes.push(Node {
+            kind,
+            span,
+            parent: None,
+        });
+        id
+    }
+
+    /// Adds a node for a token that takes no arguments.
+    pub fn leaf(&mut self, token: Token, span: Span) -> NodeId {
+        let kind = match token {
+            Token::Identifier(name) => NodeKind::Identifier(name),
+            token => NodeKind::Literal(token),
+        };
+        self.push(kind, span)
+    }
+
+    /// Grows the span of `id` to also cover `span`, such as the parentheses
+    /// around it.
+    pub fn extend(&mut self, id: NodeId, span: Span) {
+        let node = &mut self.nodes[id.0];
+        node.span = node.span.to(span);
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(self.nodes.len().saturating_sub(1))
+    }
+
+    /// Number of nodes on the longest path from the root to a leaf.
+    pub fn depth(&self) -> usize {
+        let mut depths: Vec<usize> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let children = node.kind.children().into_iter();
+            depths.push(children.map(|child| depths[child.0]).max().unwrap_or(0) + 1);
+        }
+        depths.into_iter().max().unwrap_or(0)
+    }
+
+    /// Innermost node below the root whose span contains `offset`.
+    pub fn node_at(&self, offset: usize) -> Option<NodeId> {
+        let mut id = self.root();
+        if !self.nodes.get(id.0)?.span.contains(offset) {
+            return None;
+        }
+        while let Some(child) = self
+            .node(id)
+            .kind
+            .children()
+            .into_iter()
+            .find(|child| self.node(*child).span.contains(offset))
+        {
+            id = child;
+        }
+        Some(id)
+    }
+
+    /// Value of the number at `id`, if it is one.
+    pub fn literal(&self, id: NodeId) -> Option<rust_decimal::Decimal> {
+        match self.node(id).kind {
+            NodeKind::Literal(Token::Literal(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Whether the subtrees at `id` and at `other_id` in `other` are equal.
+    fn same(&self, id: NodeId, other: &Ast, other_id: NodeId) -> bool {
+        let all = |ids: &[NodeId], other_ids: &[NodeId]| {
+            ids.len() == other_ids.len()
+                && ids
+                    .iter()
+                    .zip(other_ids)
+                    .all(|(id, other_id)| self.same(*id, other, *other_id))
+        };
+        match (&self.node(id).kind, &other.node(other_id).kind) {
+            (NodeKind::Literal(a), NodeKind::Literal(b)) => a == b,
+            (NodeKind::Identifier(a), NodeKind::Identifier(b)) => a == b,
+            (NodeKind::Unary(a, x), NodeKind::Unary(b, y)) => a == b && all(&[*x], &[*y]),
+            (NodeKind::Binary(a, x1, x2), NodeKind::Binary(b, y1, y2)) => {
+                a == b && all(&[*x1, *x2], &[*y1, *y2])
+            }
+            (NodeKind::Call(a, xs), NodeKind::Call(b, ys)) => a == b && all(xs, ys),
+            _ => false,
+        }
+    }
+
+    /// Whether the variable `var` appears anywhere in the subtree at `id`.
+    pub fn contains(&self, id: NodeId, var: &str) -> bool {
+        match &self.node(id).kind {
+            NodeKind::Identifier(name) => name == var,
+            kind => kind
+                .children()
+                .into_iter()
+                .any(|child| self.contains(child, var)),
+        }
+    }
+
+    /// Position and name of the variable bound by operators such as
+    /// `sum(expression, i, 1, n)`. The arguments before it are in its scope.
+    pub fn bound_variable(&self, id: NodeId) -> Option<(usize, &String)> {
+        let NodeKind::Call(token, args) = &self.node(id).kind else {
+            return None;
+        };
+        let position = match token {
+            Token::Diff
+            | Token::Root
+            | Token::Integrate
+            | Token::Sum
+            | Token::Prod
+            | Token::Table => 1,
+            Token::Solve
+                if args.first().is_some_and(|arg| {
+                    matches!(self.node(*arg).kind, NodeKind::Binary(Token::Assign, _, _))
+                }) =>
+            {
+                1
+            }
+            // plot(f, g, ..., x, start, end)
+            Token::Plot => args.len().checked_sub(3)?,
+            _ => return None,
+        };
+        match &self.node(*args.get(position)?).kind {
+            NodeKind::Identifier(var) => Some((position, var)),
+            _ => None,
+        }
+    }
+
+    /// Replaces every free occurrence of the variables in `bindings` with a
+    /// tree.
+    pub fn substitute(&self, bindings: &[(&String, &Ast)]) -> Ast {
+        self.substitute_at(self.root(), bindings)
+    }
+
+    fn substitute_at(&self, id: NodeId, bindings: &[(&String, &Ast)]) -> Ast {
+        let kind = &self.node(id).kind;
+        if let NodeKind::Identifier(name) = kind {
+            if let Some((_, value)) = bindings.iter().find(|(param, _)| *param == name) {
+                return (*value).clone();
+            }
+        }
+        let children = kind.children().into_iter();
+        let children = match self.bound_variable(id) {
+            Some((position, var)) => {
+                let inner: Vec<_> = bindings
+                    .iter()
+                    .filter(|(name, _)| *name != var)
+                    .copied()
+                    .collect();
+                children
+                    .enumerate()
+                    .map(|(i, arg)| match i.cmp(&position) {
+                        Ordering::Less => self.substitute_at(arg, &inner),
+                        Ordering::Equal => self.subtree(arg),
+                        Ordering::Greater => self.substitute_at(arg, bindings),
+                    })
+                    .collect()
+            }
+            None => children
+                .map(|child| self.substitute_at(child, bindings))
+                .collect(),
+        };
+        Self::compose(kind, children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_statements, Statement};
+    use crate::tokenizer::tokenize;
+
+    fn parse(input: &str) -> Ast {
+        let statements = parse_statements(tokenize(input.to_string()).unwrap());
+        let Ok([Statement::Expression(ast)]) = <[Statement; 1]>::try_from(statements.unwrap())
+        else {
+            panic!("{input} is not an expression");
+        };
+        ast
+    }
+
+    #[test]
+    fn saved_trees_read_back_equal() {
+        for input in [
+            "-x! + 2",
+            "sum(k^2, k, 1, n) * log(2, 8)",
+            "[[1, 2], [3, 4]]",
+        ] {
+            let ast = parse(input);
+            let saved = serde_json::to_string(&ast).unwrap();
+            let read: Ast = serde_json::from_str(&saved).unwrap();
+            assert_eq!(read, ast, "{input}");
+            assert_eq!(read.to_string(), ast.to_string());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_trees() {
+        for saved in [
+            r#"[]"#,
+            r#"[{"Identifier": "x"}, {"Identifier": "y"}]"#,
+            r#"[{"Identifier": "x"}, {"Binary": ["Add", 0, 0]}]"#,
+            r#"[{"Unary": ["Factorial", 1]}, {"Identifier": "x"}]"#,
+            r#"[{"Identifier": "x"}, {"Call": ["Sin", [0, 0]]}]"#,
+            r#"[{"Identifier": "x"}, {"Call": ["Add", [0]]}]"#,
+            r#"[{"Identifier": "x"}, {"Unary": ["Sin", 0]}]"#,
+            r#"[{"Literal": {"Identifier": "x"}}]"#,
+        ] {
+            assert!(serde_json::from_str::<Ast>(saved).is_err(), "{saved}");
+        }
+    }
+
+    #[test]
+    fn substitutes_free_variables() {
+        let ast = parse("x + sum(x * k, x, 1, k)");
+        let (x, k) = ("x".to_string(), "k".to_string());
+        let (two, y) = (parse("2"), parse("y + 1"));
+        let substituted = ast.substitute(&[(&x, &two), (&k, &y)]);
+        assert_eq!(substituted.to_string(), "2 + sum(x * (y + 1), x, 1, y + 1)");
+        let sum = substituted.root();
+        let NodeKind::Binary(_, _, sum) = substituted.node(sum).kind else {
+            panic!("not a sum");
+        };
+        assert_eq!(
+            substituted.subtree(sum),
+            parse("sum(x * (y + 1), x, 1, y + 1)")
+        );
+    }
+}
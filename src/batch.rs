@@ -0,0 +1,104 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::compute::{self, Cancel, Context, Environment};
+
+/// Number of threads to evaluate on when none is asked for.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Evaluates every one of `inputs` with `f` on up to `jobs` threads. Each
+/// input gets an environment of its own made from `context`, so inputs do
+/// not see each other's assignments, and raising `cancel` stops them all.
+/// Results are in the order of `inputs` whichever finishes first.
+pub fn evaluate<T: Send>(
+    context: &Context,
+    cancel: &Cancel,
+    inputs: &[String],
+    jobs: usize,
+    f: impl Fn(&str, &mut Environment) -> T + Sync,
+) -> Vec<T> {
+    let depth = context.depth();
+    let run = |input: &str| {
+        let mut env = context.environment();
+        env.cancel = cancel.clone();
+        env.max_depth = Some(depth);
+        f(input, &mut env)
+    };
+    // Workers take the next input until there are none left
+    let next = AtomicUsize::new(0);
+    let work = || {
+        let mut done = Vec::new();
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(input) = inputs.get(i) else {
+                return done;
+            };
+            done.push((i, run(input)));
+        }
+    };
+    let mut results: Vec<Option<T>> = inputs.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, inputs.len().max(1)))
+            .filter_map(|_| {
+                std::thread::Builder::new()
+                    .stack_size(compute::stack_size(depth))
+                    .spawn_scoped(scope, work)
+                    .ok()
+            })
+            .collect();
+        for worker in workers {
+            for (i, result) in worker.join().unwrap_or_default() {
+                results[i] = Some(result);
+            }
+        }
+    });
+    // Inputs of a worker that could not start or that panicked are
+    // evaluated on this thread
+    results
+        .into_iter()
+        .zip(inputs)
+        .map(|(result, input)| result.unwrap_or_else(|| run(input)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{execute, ComputeError};
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn run(input: &str, env: &mut Environment) -> Result<String, ComputeError> {
+        let mut result = Ok(String::new());
+        for statement in parse_statements(tokenize(input.to_string()).unwrap()).unwrap() {
+            result = execute(&statement, env)
+                .map(|value| value.map(|value| value.to_string()).unwrap_or_default());
+        }
+        result
+    }
+
+    #[test]
+    fn keeps_inputs_in_order_and_apart() {
+        let mut env = Environment::default();
+        run("x = 10", &mut env).ok();
+        let inputs: Vec<String> = (0..20).map(|i| format!("x = x + {i}; x")).collect();
+        let results = evaluate(&env.context(), &env.cancel, &inputs, 4, |input, env| {
+            run(input, env).ok()
+        });
+        let expected: Vec<_> = (0..20).map(|i| Some((10 + i).to_string())).collect();
+        assert_eq!(results, expected);
+        assert_eq!(run("x", &mut env).ok().as_deref(), Some("10"));
+    }
+
+    #[test]
+    fn nests_no_deeper_than_the_stack_allows() {
+        let env = Environment::default();
+        let inputs = ["set depth 10000; f(x) = if(x < 1, 0, 1 + f(x - 1)); f(5000)".to_string()];
+        let results = evaluate(&env.context(), &env.cancel, &inputs, 2, |input, env| {
+            matches!(run(input, env), Err(ComputeError::DepthLimit))
+        });
+        assert_eq!(results, [true]);
+    }
+}
@@ -0,0 +1,221 @@
+use rust_decimal::prelude::*;
+
+use crate::ast::{Ast, NodeId, NodeKind};
+use crate::compute::{degree, elementary, ComputeError, Environment};
+use crate::tokenizer::Token;
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Constant(Decimal),
+    /// Value of the variable the program is a function of.
+    Variable,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    /// Function of one number such as `Sin` or `Sqrt`.
+    Function(Token),
+}
+
+/// Expression of a single number, lowered to instructions for a stack
+/// machine so that it can be evaluated many times without walking the
+/// parse tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+fn checked(x: Option<Decimal>) -> Result<Decimal, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+impl Program {
+    /// Compiles `tree` as a function of `var`. Other variables are read
+    /// from `env` once. Gives `None` if the tree uses anything but numbers,
+    /// arithmetic and elementary functions, which is left to `compute`.
+    pub fn compile(tree: &Ast, var: &str, env: &Environment) -> Option<Self> {
+        let mut instructions = Vec::new();
+        lower(tree, tree.root(), var, env, &mut instructions)?;
+        Some(Self { instructions })
+    }
+
+    /// Value at `var = x`, using `stack` as scratch space.
+    pub fn run(&self, x: Decimal, stack: &mut Vec<Decimal>) -> Result<Decimal, ComputeError> {
+        stack.clear();
+        for instruction in &self.instructions {
+            let value = match instruction {
+                Instruction::Constant(c) => *c,
+                Instruction::Variable => x,
+                Instruction::Function(token) => {
+                    let a = stack.pop().ok_or(ComputeError::Unknown)?;
+                    elementary(token, a)?
+                }
+                operation => {
+                    let b = stack.pop().ok_or(ComputeError::Unknown)?;
+                    let a = stack.pop().ok_or(ComputeError::Unknown)?;
+                    match operation {
+                        Instruction::Add => checked(a.checked_add(b))?,
+                        Instruction::Sub => checked(a.checked_sub(b))?,
+                        Instruction::Mul => checked(a.checked_mul(b))?,
+                        Instruction::Div if b.is_zero() => return Err(ComputeError::DivByZero),
+                        Instruction::Div => checked(a.checked_div(b))?,
+                        _ if a.is_zero() && b < Decimal::ZERO => {
+                            return Err(ComputeError::DivByZero)
+                        }
+                        _ => checked(a.checked_powd(b))?,
+                    }
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().ok_or(ComputeError::Unknown)
+    }
+}
+
+/// Appends the instructions computing the node `id` in postfix order.
+fn lower(
+    ast: &Ast,
+    id: NodeId,
+    var: &str,
+    env: &Environment,
+    instructions: &mut Vec<Instruction>,
+) -> Option<()> {
+    let instruction = match &ast.node(id).kind {
+        NodeKind::Literal(Token::Literal(x)) => Instruction::Constant(*x),
+        NodeKind::Literal(Token::PI) => Instruction::Constant(Decimal::PI),
+        NodeKind::Literal(Token::E) => Instruction::Constant(Decimal::E),
+        NodeKind::Identifier(name) if name == var => Instruction::Variable,
+        NodeKind::Identifier(name) => match env.variables.get(name)? {
+            Value::Number(x) => Instruction::Constant(*x),
+            _ => return None,
+        },
+        NodeKind::Literal(Token::Ans) => match env.ans.as_ref()? {
+            Value::Number(x) => Instruction::Constant(*x),
+            _ => return None,
+        },
+        NodeKind::Binary(
+            token @ (Token::Add
+            | Token::Sub
+            | Token::Mul
+            | Token::ImplMul
+            | Token::Div
+            | Token::Pow),
+            left,
+            right,
+        ) => {
+            lower(ast, *left, var, env, instructions)?;
+            lower(ast, *right, var, env, instructions)?;
+            match token {
+                Token::Add => Instruction::Add,
+                Token::Sub => Instruction::Sub,
+                Token::Div => Instruction::Div,
+                Token::Pow => Instruction::Pow,
+                _ => Instruction::Mul,
+            }
+        }
+        NodeKind::Call(
+            token @ (Token::Sin
+            | Token::Cos
+            | Token::Tan
+            | Token::Exp
+            | Token::Ln
+            | Token::Log
+            | Token::Sqrt),
+            args,
+        ) if args.len() == 1 => {
+            lower(ast, args[0], var, env, instructions)?;
+            if env.settings.degrees && matches!(token, Token::Sin | Token::Cos | Token::Tan) {
+                instructions.push(Instruction::Constant(degree()));
+                instructions.push(Instruction::Mul);
+            }
+            Instruction::Function(token.clone())
+        }
+        _ => return None,
+    };
+    instructions.push(instruction);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{evaluate_at, execute};
+    use crate::parser::{parse_statements, Statement};
+    use crate::tokenizer::tokenize;
+
+    fn tree(input: &str) -> Ast {
+        let Ok(tokens) = tokenize(input.to_string()) else {
+            panic!("invalid token in {input}");
+        };
+        match parse_statements(tokens).ok().and_then(|mut s| s.pop()) {
+            Some(Statement::Expression(ast)) => ast,
+            _ => panic!("{input} is not an expression"),
+        }
+    }
+
+    fn define(input: &str, env: &mut Environment) {
+        let Ok(tokens) = tokenize(input.to_string()) else {
+            panic!("invalid token in {input}");
+        };
+        let Ok(statements) = parse_statements(tokens) else {
+            panic!("{input} does not parse");
+        };
+        for statement in statements {
+            assert!(execute(&statement, env).is_ok(), "{input} failed");
+        }
+    }
+
+    /// Value or error kind of a result, for comparing the two evaluators.
+    fn outcome(result: Result<Decimal, ComputeError>) -> Result<Decimal, &'static str> {
+        result.map(|x| x.normalize()).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn runs_as_the_tree_evaluates() {
+        let mut env = Environment::default();
+        define("a = 2.5", &mut env);
+        env.settings.degrees = true;
+        let mut stack = Vec::new();
+        for input in [
+            "3x^2 - 2x + 1",
+            "a x / (x - 1)",
+            "sqrt(x) + ln(x) - log(x)",
+            "sin(x)^2 + cos(x)^2 + tan(x)",
+            "exp(x / 10) * pi - e",
+            "0^x + x^(-1)",
+        ] {
+            let ast = tree(input);
+            let Some(program) = Program::compile(&ast, "x", &env) else {
+                panic!("{input} did not compile");
+            };
+            for x in [-2, -1, 0, 1, 3, 90].map(Decimal::from) {
+                assert_eq!(
+                    outcome(program.run(x, &mut stack)),
+                    outcome(evaluate_at(&ast, "x", x, &mut env)),
+                    "{input} at {x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_other_trees_to_compute() {
+        let mut env = Environment::default();
+        define("m = [[1, 2]]; f(t) = t + 1", &mut env);
+        for input in [
+            "if(x > 0, x, -x)",
+            "f(x)",
+            "m * x",
+            "y + x",
+            "x!",
+            "det([[x]])",
+        ] {
+            assert!(
+                Program::compile(&tree(input), "x", &env).is_none(),
+                "{input}"
+            );
+        }
+    }
+}
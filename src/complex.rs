@@ -0,0 +1,185 @@
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+/// Complex number with checked arithmetic, every operation returns `None`
+/// on overflow or division by zero.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Complex {
+    pub re: Decimal,
+    pub im: Decimal,
+}
+
+impl Complex {
+    pub const ZERO: Self = Self::real(Decimal::ZERO);
+    pub const ONE: Self = Self::real(Decimal::ONE);
+
+    pub const fn new(re: Decimal, im: Decimal) -> Self {
+        Self { re, im }
+    }
+
+    pub const fn real(re: Decimal) -> Self {
+        Self::new(re, Decimal::ZERO)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.re.is_zero() && self.im.is_zero()
+    }
+
+    /// max(|re|, |im|), which is within a factor of √2 of the modulus and
+    /// cheaper to compare.
+    pub fn magnitude(&self) -> Decimal {
+        self.re.abs().max(self.im.abs())
+    }
+
+    pub fn conj(&self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn checked_add(&self, other: Self) -> Option<Self> {
+        Some(Self::new(
+            self.re.checked_add(other.re)?,
+            self.im.checked_add(other.im)?,
+        ))
+    }
+
+    pub fn checked_sub(&self, other: Self) -> Option<Self> {
+        Some(Self::new(
+            self.re.checked_sub(other.re)?,
+            self.im.checked_sub(other.im)?,
+        ))
+    }
+
+    pub fn checked_mul(&self, other: Self) -> Option<Self> {
+        let re = self
+            .re
+            .checked_mul(other.re)?
+            .checked_sub(self.im.checked_mul(other.im)?)?;
+        let im = self
+            .re
+            .checked_mul(other.im)?
+            .checked_add(self.im.checked_mul(other.re)?)?;
+        Some(Self::new(re, im))
+    }
+
+    pub fn checked_scale(&self, x: Decimal) -> Option<Self> {
+        Some(Self::new(self.re.checked_mul(x)?, self.im.checked_mul(x)?))
+    }
+
+    pub fn checked_div(&self, other: Self) -> Option<Self> {
+        // Smith's algorithm, dividing by the larger component first
+        if other.re.abs() >= other.im.abs() {
+            let ratio = other.im.checked_div(other.re)?;
+            let denominator = other.re.checked_add(other.im.checked_mul(ratio)?)?;
+            Some(Self::new(
+                self.re
+                    .checked_add(self.im.checked_mul(ratio)?)?
+                    .checked_div(denominator)?,
+                self.im
+                    .checked_sub(self.re.checked_mul(ratio)?)?
+                    .checked_div(denominator)?,
+            ))
+        } else {
+            let ratio = other.re.checked_div(other.im)?;
+            let denominator = other.re.checked_mul(ratio)?.checked_add(other.im)?;
+            Some(Self::new(
+                self.re
+                    .checked_mul(ratio)?
+                    .checked_add(self.im)?
+                    .checked_div(denominator)?,
+                self.im
+                    .checked_mul(ratio)?
+                    .checked_sub(self.re)?
+                    .checked_div(denominator)?,
+            ))
+        }
+    }
+
+    pub fn checked_abs(&self) -> Option<Decimal> {
+        // Scaled to keep the squares representable
+        let scale = self.magnitude();
+        if scale.is_zero() {
+            return Some(Decimal::ZERO);
+        }
+        let re = self.re.checked_div(scale)?;
+        let im = self.im.checked_div(scale)?;
+        (re * re + im * im).sqrt()?.checked_mul(scale)
+    }
+
+    /// Principal square root.
+    pub fn checked_sqrt(&self) -> Option<Self> {
+        if self.im.is_zero() {
+            return Some(if self.re.is_sign_negative() {
+                Self::new(Decimal::ZERO, (-self.re).sqrt()?)
+            } else {
+                Self::real(self.re.sqrt()?)
+            });
+        }
+        let modulus = self.checked_abs()?;
+        let re = (modulus.checked_add(self.re)? / Decimal::TWO).sqrt()?;
+        let im = (modulus.checked_sub(self.re)? / Decimal::TWO).sqrt()?;
+        Some(Self::new(
+            re,
+            if self.im.is_sign_negative() { -im } else { im },
+        ))
+    }
+
+    /// One of the cube roots, the real one for positive real numbers.
+    pub fn checked_cbrt(&self) -> Option<Self> {
+        if self.is_zero() {
+            return Some(Self::ZERO);
+        }
+        let modulus = self.checked_abs()?;
+        let estimate = modulus
+            .checked_powd(dec!(0.3333333333333333333333333333))
+            .unwrap_or(Decimal::ONE);
+        // Newton's method for w^3 = z
+        let mut w = Self::new(estimate, estimate / Decimal::TEN);
+        for _ in 0..100 {
+            let next = self
+                .checked_div(w.checked_mul(w)?)?
+                .checked_add(w.checked_scale(Decimal::TWO)?)?
+                .checked_div(Self::real(Decimal::from(3)))?;
+            let step = next.checked_sub(w)?.magnitude();
+            w = next;
+            if step <= w.magnitude() * dec!(0.000000000000000000000000001) {
+                break;
+            }
+        }
+        Some(w)
+    }
+
+    pub fn round_dp_with_strategy(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+        Self::new(
+            self.re.round_dp_with_strategy(dp, strategy),
+            self.im.round_dp_with_strategy(dp, strategy),
+        )
+    }
+}
+
+impl Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let imaginary = |f: &mut std::fmt::Formatter<'_>, im: Decimal| {
+            if im == Decimal::ONE {
+                write!(f, "i")
+            } else {
+                write!(f, "{im}i")
+            }
+        };
+        match (self.re.is_zero(), self.im.is_zero()) {
+            (_, true) => write!(f, "{}", self.re),
+            (true, false) if self.im.is_sign_negative() => {
+                write!(f, "-")?;
+                imaginary(f, -self.im)
+            }
+            (true, false) => imaginary(f, self.im),
+            (false, false) => {
+                let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+                write!(f, "{} {sign} ", self.re)?;
+                imaginary(f, self.im.abs())
+            }
+        }
+    }
+}
@@ -1,10 +1,289 @@
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 
-use crate::parser::ParseTree;
+use crate::ast::{Ast, NodeId, NodeKind};
+use crate::bytecode::Program;
+use crate::diff;
+use crate::gamma;
+use crate::interval::Interval;
+use crate::matrix::Matrix;
+use crate::number_theory;
+use crate::optimize;
+use crate::parser::Statement;
+use crate::plot::{Curve, Plot};
+use crate::polynomial;
+use crate::quadrature;
+use crate::roots;
+use crate::simplify;
+use crate::table::Table;
 use crate::tokenizer::Token;
+use crate::uncertain::Uncertain;
+use crate::value::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub params: Vec<String>,
+    /// Shared so that calling the function does not copy its body.
+    pub body: Arc<Ast>,
+}
+
+/// Options changed with the `set` command. Settings missing from a saved
+/// session keep their default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Relative accuracy at which iterative methods stop.
+    pub tolerance: Decimal,
+    /// Number of steps after which iterative methods give up.
+    pub max_iterations: usize,
+    /// Largest number of terms a `sum` or `prod` may evaluate.
+    pub max_terms: usize,
+    /// Number of points at which `plot` samples every curve.
+    pub samples: usize,
+    /// Size of exported plots in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Whether intervals are shown as `[low, high]` instead of
+    /// `center ± radius`. Either way they are entered as
+    /// `interval(low, high)`, `[low, high]` is a matrix.
+    pub bounds: bool,
+    /// Whether `±` gives a standard deviation instead of hard bounds, and
+    /// `9.81(2)` a measurement instead of a product.
+    pub sigma: bool,
+    /// Whether constant subtrees are folded before repeated evaluation and
+    /// values of pure functions are remembered. Disabled for debugging.
+    pub optimize: bool,
+    /// Deepest nesting of operations and function calls an evaluation may
+    /// reach, at most `MAX_DEPTH`.
+    pub max_depth: usize,
+    /// Milliseconds a statement may take, or zero for no limit.
+    pub timeout: usize,
+    /// Largest number of elements of a computed matrix, and of the points
+    /// sampled for a plot.
+    pub max_elements: usize,
+    /// Number of decimal places results are rounded to when shown.
+    pub precision: u32,
+    /// Whether trigonometric functions take their argument in degrees
+    /// instead of radians.
+    pub degrees: bool,
+}
+
+impl Settings {
+    /// Fails on the first setting `set` would not accept, for settings read
+    /// from a file.
+    pub fn check(&self) -> Result<(), ComputeError> {
+        if self.tolerance.is_sign_negative() {
+            return Err(ComputeError::InvalidSetting("tolerance".to_string()));
+        }
+        [
+            ("samples", self.samples),
+            ("width", self.width),
+            ("height", self.height),
+            ("depth", self.max_depth),
+            ("timeout", self.timeout),
+            ("elements", self.max_elements),
+            ("precision", self.precision as usize),
+        ]
+        .into_iter()
+        .try_for_each(|(name, value)| check_setting(name, value))
+    }
+}
+
+/// Fails unless `value` is in the range of the integer setting `name`.
+fn check_setting(name: &str, value: usize) -> Result<(), ComputeError> {
+    let range = match name {
+        "samples" => 2..=usize::MAX,
+        "depth" => 1..=MAX_DEPTH,
+        "timeout" => 0..=usize::MAX,
+        // Decimals have at most 28 digits after the point
+        "precision" => 0..=28,
+        _ => 1..=usize::MAX,
+    };
+    match range.contains(&value) {
+        true => Ok(()),
+        false => Err(ComputeError::InvalidSetting(name.to_string())),
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tolerance: dec!(0.00000000000000000001),
+            max_iterations: 100,
+            max_terms: 100000,
+            samples: 200,
+            width: 640,
+            height: 400,
+            bounds: false,
+            sigma: false,
+            optimize: true,
+            max_depth: 1000,
+            timeout: 0,
+            max_elements: 1000000,
+            precision: 10,
+            degrees: false,
+        }
+    }
+}
+
+/// Largest value of the `depth` setting, recursion this deep fits within
+/// `STACK_SIZE`.
+pub const MAX_DEPTH: usize = 10000;
+
+/// Stack of the thread the REPL evaluates on, which may be set to any
+/// depth.
+pub const STACK_SIZE: usize = 512 << 20;
+
+/// Stack for a thread evaluating at most `depth` levels deep, with as much
+/// room per level as `STACK_SIZE`. It is never smaller than for the
+/// default depth, which expressions are also parsed to.
+pub fn stack_size(depth: usize) -> usize {
+    let depth = depth.clamp(Settings::default().max_depth, MAX_DEPTH);
+    STACK_SIZE / MAX_DEPTH * depth
+}
+
+/// Flag that stops the evaluations of an environment and its clones when
+/// raised, for instance from a Ctrl-C handler.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Raises the flag, giving whether it was already raised.
+    pub fn cancel(&self) -> bool {
+        self.0.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Variables, functions and settings of a session, without the state of
+/// an evaluation in progress. Clones share their contents, so a context is
+/// cheap to clone and can be sent to the threads evaluating from it.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    variables: Arc<HashMap<String, Value>>,
+    functions: Arc<HashMap<String, Function>>,
+    settings: Arc<Settings>,
+}
+
+impl Context {
+    /// Deepest an evaluation from this context nests with its settings.
+    pub fn depth(&self) -> usize {
+        self.settings.max_depth
+    }
+
+    /// Fresh environment to evaluate in, starting from this context.
+    pub fn environment(&self) -> Environment {
+        Environment {
+            variables: Arc::clone(&self.variables),
+            functions: Arc::clone(&self.functions),
+            settings: Settings::clone(&self.settings),
+            ..Default::default()
+        }
+    }
+}
+
+/// State shared between the statements of a session. The variables and
+/// functions are copied on the first write, clones and contexts share them
+/// until then.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    pub ans: Option<Value>,
+    pub variables: Arc<HashMap<String, Value>>,
+    pub functions: Arc<HashMap<String, Function>>,
+    pub settings: Settings,
+    /// Lines entered so far, kept when the session is saved.
+    pub history: Vec<String>,
+    /// Values of pure functions of one number already computed.
+    pub cache: HashMap<(Token, Decimal), Decimal>,
+    pub cancel: Cancel,
+    /// Longest any statement may take whatever the `timeout` setting, for
+    /// evaluating as the user types.
+    pub limit: Option<Duration>,
+    /// Deepest any statement may nest whatever the `depth` setting, for
+    /// threads with a stack of `stack_size` of it.
+    pub max_depth: Option<usize>,
+    /// Nesting of the evaluation in progress.
+    depth: usize,
+    /// Time by which the statement in progress must finish.
+    deadline: Option<Instant>,
+    /// Innermost node of the statement in progress that failed.
+    failed: Option<NodeId>,
+}
+
+impl Environment {
+    /// Evaluates `f` with `name` temporarily bound to `value`.
+    pub fn with_variable<T>(
+        &mut self,
+        name: &str,
+        value: Value,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let previous = Arc::make_mut(&mut self.variables).insert(name.to_string(), value);
+        let result = f(self);
+        let variables = Arc::make_mut(&mut self.variables);
+        match previous {
+            Some(previous) => variables.insert(name.to_string(), previous),
+            None => variables.remove(name),
+        };
+        result
+    }
+
+    /// Snapshot of the variables, functions and settings.
+    pub fn context(&self) -> Context {
+        Context {
+            variables: Arc::clone(&self.variables),
+            functions: Arc::clone(&self.functions),
+            settings: Arc::new(self.settings.clone()),
+        }
+    }
+
+    /// Environment without variables that evaluates with the settings and
+    /// within the limits of `self`.
+    pub fn detached(&self) -> Self {
+        Self {
+            settings: self.settings.clone(),
+            cancel: self.cancel.clone(),
+            max_depth: self.max_depth,
+            deadline: self.deadline,
+            ..Default::default()
+        }
+    }
+
+    /// Deepest the statement in progress may nest.
+    pub fn depth_limit(&self) -> usize {
+        self.max_depth.map_or(self.settings.max_depth, |depth| {
+            depth.min(self.settings.max_depth)
+        })
+    }
+
+    /// Fails once the evaluation is cancelled or out of time.
+    pub fn check(&self) -> Result<(), ComputeError> {
+        if self.cancel.is_cancelled() {
+            return Err(ComputeError::Interrupted);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(ComputeError::Timeout);
+        }
+        Ok(())
+    }
+}
 
 pub enum ComputeError {
     Overflow,
@@ -14,9 +293,69 @@ pub enum ComputeError {
     MustBeInt,
     MustBeNonNegative,
     NoAns,
+    DimensionMismatch,
+    SingularMatrix,
+    ExpectedNumber,
+    ExpectedBool,
+    Pole,
+    UndefinedVariable(String),
+    UndefinedFunction(String),
+    WrongArgumentCount,
+    ExpectedVariable,
+    NotDifferentiable,
+    NoConvergence,
+    IterationLimit,
+    MisplacedEquation,
+    UnknownSetting(String),
+    InvalidSetting(String),
+    ZeroStep,
+    ZeroPolynomial,
+    UndecidedComparison,
+    DepthLimit,
+    Timeout,
+    Interrupted,
+    TooLarge,
     Unknown,
 }
 
+impl ComputeError {
+    /// Stable name of the error for machine readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Overflow => "overflow",
+            Self::DivByZero => "div_by_zero",
+            Self::LogBaseZero => "log_base_zero",
+            Self::NotReal => "not_real",
+            Self::MustBeInt => "must_be_int",
+            Self::MustBeNonNegative => "must_be_non_negative",
+            Self::NoAns => "no_ans",
+            Self::DimensionMismatch => "dimension_mismatch",
+            Self::SingularMatrix => "singular_matrix",
+            Self::ExpectedNumber => "expected_number",
+            Self::ExpectedBool => "expected_bool",
+            Self::Pole => "pole",
+            Self::UndefinedVariable(_) => "undefined_variable",
+            Self::UndefinedFunction(_) => "undefined_function",
+            Self::WrongArgumentCount => "wrong_argument_count",
+            Self::ExpectedVariable => "expected_variable",
+            Self::NotDifferentiable => "not_differentiable",
+            Self::NoConvergence => "no_convergence",
+            Self::IterationLimit => "iteration_limit",
+            Self::MisplacedEquation => "misplaced_equation",
+            Self::UnknownSetting(_) => "unknown_setting",
+            Self::InvalidSetting(_) => "invalid_setting",
+            Self::ZeroStep => "zero_step",
+            Self::ZeroPolynomial => "zero_polynomial",
+            Self::UndecidedComparison => "undecided_comparison",
+            Self::DepthLimit => "depth_limit",
+            Self::Timeout => "timeout",
+            Self::Interrupted => "interrupted",
+            Self::TooLarge => "too_large",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
 impl Display for ComputeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -27,12 +366,51 @@ impl Display for ComputeError {
             Self::MustBeInt => write!(f, "Input to function must be an integer"),
             Self::MustBeNonNegative => write!(f, "Input to function must not be negative"),
             Self::NoAns => write!(f, "No previous answer"),
+            Self::DimensionMismatch => write!(f, "Matrix dimensions do not match"),
+            Self::SingularMatrix => write!(f, "Matrix is singular"),
+            Self::ExpectedNumber => write!(f, "Input to function must be a number"),
+            Self::ExpectedBool => write!(f, "Condition must be true or false"),
+            Self::Pole => write!(f, "Function has a pole at this input"),
+            Self::UndefinedVariable(name) => write!(f, "Undefined variable {name}"),
+            Self::UndefinedFunction(name) => write!(f, "Undefined function {name}"),
+            Self::WrongArgumentCount => write!(f, "Wrong number of arguments to function"),
+            Self::ExpectedVariable => write!(f, "Expected a variable"),
+            Self::NotDifferentiable => write!(f, "Expression can not be differentiated"),
+            Self::NoConvergence => write!(f, "Iteration did not converge"),
+            Self::IterationLimit => write!(f, "Too many iterations"),
+            Self::MisplacedEquation => write!(f, "Equations can only be solved"),
+            Self::UnknownSetting(name) => write!(f, "Unknown setting {name}"),
+            Self::InvalidSetting(name) => write!(f, "Value out of range for setting {name}"),
+            Self::ZeroStep => write!(f, "Step must not be zero"),
+            Self::ZeroPolynomial => write!(f, "Every number is a root of the zero polynomial"),
+            Self::UndecidedComparison => {
+                write!(f, "Comparison depends on values within the intervals")
+            }
+            Self::DepthLimit => write!(f, "Expression is nested too deeply"),
+            Self::Timeout => write!(f, "Evaluation took too long"),
+            Self::Interrupted => write!(f, "Evaluation was interrupted"),
+            Self::TooLarge => write!(f, "Result is too large"),
             Self::Unknown => write!(f, "Unkown"),
         }
     }
 }
 
 fn factorial(mut val: Decimal) -> Result<Decimal, ComputeError> {
+    if !val.is_integer() || val.is_sign_negative() {
+        return gamma::gamma(
+            val.checked_add(Decimal::ONE)
+                .ok_or(ComputeError::Overflow)?,
+        );
+    }
+    let mut result = Decimal::ONE;
+    while val > Decimal::ONE {
+        result = result.checked_mul(val).ok_or(ComputeError::Overflow)?;
+        val -= Decimal::ONE;
+    }
+    Ok(result)
+}
+
+fn double_factorial(mut val: Decimal) -> Result<Decimal, ComputeError> {
     if !val.is_integer() {
         return Err(ComputeError::MustBeInt);
     }
@@ -42,84 +420,1143 @@ fn factorial(mut val: Decimal) -> Result<Decimal, ComputeError> {
     let mut result = Decimal::ONE;
     while val > Decimal::ONE {
         result = result.checked_mul(val).ok_or(ComputeError::Overflow)?;
-        val -= Decimal::ONE;
+        val -= Decimal::TWO;
     }
     Ok(result)
 }
 
-fn compute(tree: Option<Box<ParseTree>>, ans: Option<Decimal>) -> Result<Decimal, ComputeError> {
-    match tree {
-        Some(tree) => match tree.token {
-            Token::Add => compute(tree.left, ans)?
-                .checked_add(compute(tree.right, ans)?)
-                .ok_or(ComputeError::Overflow),
-            Token::Sub => compute(tree.left, ans)?
-                .checked_sub(compute(tree.right, ans)?)
-                .ok_or(ComputeError::Overflow),
-            Token::Mul | Token::ImplMul => compute(tree.left, ans)?
-                .checked_mul(compute(tree.right, ans)?)
-                .ok_or(ComputeError::Overflow),
-            Token::Div => {
-                let right = compute(tree.right, ans)?;
-                if right.is_zero() {
-                    return Err(ComputeError::DivByZero);
+/// Converts an input of an integer function, rejecting fractions and negatives.
+fn integer(val: Decimal) -> Result<u128, ComputeError> {
+    if !val.is_integer() {
+        return Err(ComputeError::MustBeInt);
+    }
+    if val.is_sign_negative() {
+        return Err(ComputeError::MustBeNonNegative);
+    }
+    val.to_u128().ok_or(ComputeError::Overflow)
+}
+
+fn decimal(val: Option<u128>) -> Result<Decimal, ComputeError> {
+    val.and_then(Decimal::from_u128)
+        .ok_or(ComputeError::Overflow)
+}
+
+fn number(value: Value) -> Result<Decimal, ComputeError> {
+    match value {
+        Value::Number(x) => Ok(x),
+        Value::Factorization(factors) => factors.into_iter().try_fold(Decimal::ONE, |x, (p, e)| {
+            decimal(p.checked_pow(e))?
+                .checked_mul(x)
+                .ok_or(ComputeError::Overflow)
+        }),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+/// Brings an operand of an arithmetic operation to a number or a matrix.
+fn numeric(value: Value) -> Result<Value, ComputeError> {
+    match value {
+        Value::Matrix(matrix) => Ok(Value::Matrix(matrix)),
+        value => number(value).map(Value::Number),
+    }
+}
+
+fn interval(value: Value) -> Result<Interval, ComputeError> {
+    match value {
+        Value::Interval(x) => Ok(x),
+        value => number(value).map(Interval::point),
+    }
+}
+
+/// Both operands as intervals if at least one of them is an interval.
+fn intervals(left: &Value, right: &Value) -> Result<Option<(Interval, Interval)>, ComputeError> {
+    if !matches!(left, Value::Interval(_)) && !matches!(right, Value::Interval(_)) {
+        return Ok(None);
+    }
+    Ok(Some((interval(left.clone())?, interval(right.clone())?)))
+}
+
+fn uncertain(value: Value) -> Result<Uncertain, ComputeError> {
+    match value {
+        Value::Uncertain(x) => Ok(x),
+        value => number(value).map(Uncertain::exact),
+    }
+}
+
+/// Both operands as uncertain values if at least one of them has a
+/// standard deviation.
+fn uncertains(left: &Value, right: &Value) -> Result<Option<(Uncertain, Uncertain)>, ComputeError> {
+    if !matches!(left, Value::Uncertain(_)) && !matches!(right, Value::Uncertain(_)) {
+        return Ok(None);
+    }
+    Ok(Some((uncertain(left.clone())?, uncertain(right.clone())?)))
+}
+
+fn boolean(value: Value) -> Result<bool, ComputeError> {
+    match value {
+        Value::Bool(x) => Ok(x),
+        _ => Err(ComputeError::ExpectedBool),
+    }
+}
+
+/// Uncertain values compare by their central value.
+fn central(value: Value) -> Value {
+    match value {
+        Value::Uncertain(x) => Value::Number(x.value),
+        value => value,
+    }
+}
+
+fn equal(left: Value, right: Value) -> Result<bool, ComputeError> {
+    let (left, right) = (central(left), central(right));
+    if let Some((a, b)) = intervals(&left, &right)? {
+        return match (a.high < b.low || b.high < a.low, a == b && a.low == a.high) {
+            (true, _) => Ok(false),
+            (false, true) => Ok(true),
+            (false, false) => Err(ComputeError::UndecidedComparison),
+        };
+    }
+    match (left, right) {
+        (Value::Bool(x), Value::Bool(y)) => Ok(x == y),
+        (Value::Matrix(a), Value::Matrix(b)) => Ok(a == b),
+        (left, right) => Ok(number(left)? == number(right)?),
+    }
+}
+
+/// Whether `left < right`, or `left <= right` with `or_equal`. Intervals
+/// compare only if the answer is the same for all of their values.
+fn less(left: Value, right: Value, or_equal: bool) -> Result<bool, ComputeError> {
+    let (a, b) = (interval(central(left))?, interval(central(right))?);
+    let holds = |x: Decimal, y: Decimal| if or_equal { x <= y } else { x < y };
+    match (holds(a.high, b.low), holds(a.low, b.high)) {
+        (true, _) => Ok(true),
+        (false, false) => Ok(false),
+        (false, true) => Err(ComputeError::UndecidedComparison),
+    }
+}
+
+fn matrix(value: Value) -> Result<Matrix, ComputeError> {
+    match value {
+        Value::Matrix(matrix) => Ok(matrix),
+        value => number(value).map(Matrix::scalar),
+    }
+}
+
+fn add(left: Value, right: Value) -> Result<Value, ComputeError> {
+    let add = |x: Decimal, y: Decimal| x.checked_add(y).ok_or(ComputeError::Overflow);
+    if let Some((a, b)) = intervals(&left, &right)? {
+        return a.checked_add(b).map(Value::Interval);
+    }
+    if let Some((a, b)) = uncertains(&left, &right)? {
+        return a.checked_add(&b).map(Value::Uncertain);
+    }
+    match (numeric(left)?, numeric(right)?) {
+        (Value::Number(x), Value::Number(y)) => add(x, y).map(Value::Number),
+        (Value::Number(x), Value::Matrix(m)) | (Value::Matrix(m), Value::Number(x)) => {
+            m.map(|y| add(x, y)).map(Value::Matrix)
+        }
+        (Value::Matrix(a), Value::Matrix(b)) => a.zip(&b, add).map(Value::Matrix),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+fn sub(left: Value, right: Value) -> Result<Value, ComputeError> {
+    let sub = |x: Decimal, y: Decimal| x.checked_sub(y).ok_or(ComputeError::Overflow);
+    if let Some((a, b)) = intervals(&left, &right)? {
+        return a.checked_sub(b).map(Value::Interval);
+    }
+    if let Some((a, b)) = uncertains(&left, &right)? {
+        return a.checked_sub(&b).map(Value::Uncertain);
+    }
+    match (numeric(left)?, numeric(right)?) {
+        (Value::Number(x), Value::Number(y)) => sub(x, y).map(Value::Number),
+        (Value::Number(x), Value::Matrix(m)) => m.map(|y| sub(x, y)).map(Value::Matrix),
+        (Value::Matrix(m), Value::Number(y)) => m.map(|x| sub(x, y)).map(Value::Matrix),
+        (Value::Matrix(a), Value::Matrix(b)) => a.zip(&b, sub).map(Value::Matrix),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+fn mul(left: Value, right: Value, env: &Environment) -> Result<Value, ComputeError> {
+    let mul = |x: Decimal, y: Decimal| x.checked_mul(y).ok_or(ComputeError::Overflow);
+    if let Some((a, b)) = intervals(&left, &right)? {
+        return a.checked_mul(b).map(Value::Interval);
+    }
+    if let Some((a, b)) = uncertains(&left, &right)? {
+        return a.checked_mul(&b).map(Value::Uncertain);
+    }
+    match (numeric(left)?, numeric(right)?) {
+        (Value::Number(x), Value::Number(y)) => mul(x, y).map(Value::Number),
+        (Value::Number(x), Value::Matrix(m)) | (Value::Matrix(m), Value::Number(x)) => {
+            m.map(|y| mul(x, y)).map(Value::Matrix)
+        }
+        (Value::Matrix(a), Value::Matrix(b)) => a.checked_mul(&b, env).map(Value::Matrix),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+fn div(left: Value, right: Value, env: &Environment) -> Result<Value, ComputeError> {
+    if let Some((a, b)) = intervals(&left, &right)? {
+        return a.checked_div(b).map(Value::Interval);
+    }
+    if let Some((a, b)) = uncertains(&left, &right)? {
+        return a.checked_div(&b).map(Value::Uncertain);
+    }
+    match (numeric(left)?, numeric(right)?) {
+        (_, Value::Number(y)) if y.is_zero() => Err(ComputeError::DivByZero),
+        (Value::Number(x), Value::Number(y)) => x
+            .checked_div(y)
+            .map(Value::Number)
+            .ok_or(ComputeError::Overflow),
+        (Value::Matrix(m), Value::Number(y)) => m
+            .map(|x| x.checked_div(y).ok_or(ComputeError::Overflow))
+            .map(Value::Matrix),
+        (left, Value::Matrix(m)) => mul(left, Value::Matrix(m.inverse(env)?), env),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+fn pow(left: Value, right: Value, env: &Environment) -> Result<Value, ComputeError> {
+    if let Some((a, b)) = intervals(&left, &right)? {
+        // Integer powers are defined for negative bases as well
+        if b.low == b.high && b.low.is_integer() {
+            let n = b.low.to_i64().ok_or(ComputeError::Overflow)?;
+            return a.checked_powi(n).map(Value::Interval);
+        }
+        return a.checked_pow(b).map(Value::Interval);
+    }
+    if let Some((a, b)) = uncertains(&left, &right)? {
+        return a.checked_pow(&b).map(Value::Uncertain);
+    }
+    match (numeric(left)?, number(right)?) {
+        // 0^-1 is 1 / 0
+        (Value::Number(x), y) if x.is_zero() && y < Decimal::ZERO => Err(ComputeError::DivByZero),
+        (Value::Number(x), y) => x
+            .checked_powd(y)
+            .map(Value::Number)
+            .ok_or(ComputeError::Overflow),
+        (Value::Matrix(m), y) => m.checked_pow(y, env).map(Value::Matrix),
+        _ => Err(ComputeError::ExpectedNumber),
+    }
+}
+
+/// `left ± right`, the values within `right` of `left`, or with `sigma` a
+/// measurement with standard deviation `right`.
+fn plus_minus(left: Value, right: Value, sigma: bool) -> Result<Value, ComputeError> {
+    if sigma {
+        let deviation = Uncertain::new(Decimal::ZERO, number(right)?);
+        return uncertain(left)?
+            .checked_add(&deviation)
+            .map(Value::Uncertain);
+    }
+    let radius = interval(right)?;
+    let radius = radius.low.abs().max(radius.high.abs());
+    interval(left)?
+        .checked_add(Interval::around(Decimal::ZERO, radius)?)
+        .map(Value::Interval)
+}
+
+/// One degree in radians.
+pub fn degree() -> Decimal {
+    Decimal::PI / dec!(180)
+}
+
+/// Argument of a trigonometric function in radians, whatever the angle
+/// mode.
+fn radians(x: Value, env: &Environment) -> Result<Value, ComputeError> {
+    match env.settings.degrees {
+        true => mul(x, Value::Number(degree()), env),
+        false => Ok(x),
+    }
+}
+
+/// Functions of one number that also accept intervals.
+pub fn elementary(token: &Token, x: Decimal) -> Result<Decimal, ComputeError> {
+    match token {
+        Token::Sin => x.checked_sin().ok_or(ComputeError::Overflow),
+        Token::Cos => x.checked_cos().ok_or(ComputeError::Overflow),
+        Token::Tan => x.checked_tan().ok_or(ComputeError::Overflow),
+        Token::Exp => gamma::exp(x),
+        Token::Ln => x.checked_ln().ok_or(ComputeError::Overflow),
+        Token::Log if x.is_zero() => Err(ComputeError::Overflow),
+        Token::Log => x.checked_log10().ok_or(ComputeError::NotReal),
+        Token::Sqrt => x.sqrt().ok_or(ComputeError::NotReal),
+        _ => Err(ComputeError::Unknown),
+    }
+}
+
+/// Number of remembered function values after which they are forgotten.
+const CACHE_SIZE: usize = 4096;
+
+/// `f(x)` for the pure function named by `token`, remembered in `env`
+/// unless optimizations are disabled.
+fn memoized(
+    token: &Token,
+    x: Decimal,
+    env: &mut Environment,
+    f: impl FnOnce(Decimal) -> Result<Decimal, ComputeError>,
+) -> Result<Decimal, ComputeError> {
+    if !env.settings.optimize {
+        return f(x);
+    }
+    let key = (token.clone(), x);
+    if let Some(y) = env.cache.get(&key) {
+        return Ok(*y);
+    }
+    let y = f(x)?;
+    if env.cache.len() >= CACHE_SIZE {
+        env.cache.clear();
+    }
+    env.cache.insert(key, y);
+    Ok(y)
+}
+
+/// Image of an interval under an elementary function, found from the
+/// values at the ends and the extremes in between.
+fn elementary_interval(token: &Token, x: Interval) -> Result<Interval, ComputeError> {
+    let f = |x| elementary(token, x);
+    match token {
+        Token::Sin => x.periodic(f, Decimal::HALF_PI),
+        Token::Cos => x.periodic(f, Decimal::ZERO),
+        Token::Tan => x.tan(f),
+        _ => x.increasing(f),
+    }
+}
+
+/// Elementary function of an uncertain value, propagated through its
+/// derivative.
+fn elementary_uncertain(token: &Token, x: Uncertain) -> Result<Uncertain, ComputeError> {
+    let value = elementary(token, x.value)?;
+    let slope = match token {
+        Token::Sin => Some(elementary(&Token::Cos, x.value)?),
+        Token::Cos => Some(-elementary(&Token::Sin, x.value)?),
+        Token::Tan => value
+            .checked_mul(value)
+            .and_then(|x| x.checked_add(Decimal::ONE)),
+        Token::Exp => Some(value),
+        Token::Ln => Decimal::ONE.checked_div(x.value),
+        Token::Log => x
+            .value
+            .checked_mul(Decimal::TEN.ln())
+            .and_then(|x| Decimal::ONE.checked_div(x)),
+        Token::Sqrt if value.is_zero() => return Err(ComputeError::Pole),
+        Token::Sqrt => Decimal::ONE.checked_div(value * Decimal::TWO),
+        _ => return Err(ComputeError::Unknown),
+    }
+    .ok_or(ComputeError::Overflow)?;
+    x.map(value, slope)
+}
+
+/// Calls a user defined function. A variable followed by parentheses is
+/// a multiplication instead.
+fn call(name: &str, args: Vec<Value>, env: &mut Environment) -> Result<Value, ComputeError> {
+    let Some(function) = env.functions.get(name).cloned() else {
+        return match (
+            env.variables.get(name).cloned(),
+            <[Value; 1]>::try_from(args),
+        ) {
+            (Some(value), Ok([arg])) => mul(value, arg, env),
+            (Some(_), Err(_)) => Err(ComputeError::WrongArgumentCount),
+            (None, _) => Err(ComputeError::UndefinedFunction(name.to_string())),
+        };
+    };
+    if function.params.len() != args.len() {
+        return Err(ComputeError::WrongArgumentCount);
+    }
+    let mut previous = Vec::with_capacity(args.len());
+    let variables = Arc::make_mut(&mut env.variables);
+    for (param, arg) in function.params.iter().zip(args) {
+        previous.push(variables.insert(param.clone(), arg));
+    }
+    let result = compute_tree(&function.body, env);
+    let variables = Arc::make_mut(&mut env.variables);
+    for (param, value) in function.params.iter().zip(previous) {
+        match value {
+            Some(value) => variables.insert(param.clone(), value),
+            None => variables.remove(param),
+        };
+    }
+    result
+}
+
+/// `tree` as a function of `var`, run on the bytecode machine when it
+/// compiles and by `compute` otherwise.
+struct Evaluator<'a> {
+    tree: Ast,
+    var: &'a str,
+    program: Option<Program>,
+    stack: Vec<Decimal>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(tree: Ast, var: &'a str, env: &Environment) -> Self {
+        let tree = match env.settings.optimize {
+            true => optimize::fold(&tree, env),
+            false => tree,
+        };
+        Self {
+            program: Program::compile(&tree, var, env),
+            tree,
+            var,
+            stack: Vec::new(),
+        }
+    }
+
+    fn at(&mut self, x: Decimal, env: &mut Environment) -> Result<Decimal, ComputeError> {
+        env.check()?;
+        match &self.program {
+            Some(program) => program.run(x, &mut self.stack),
+            None => evaluate_at(&self.tree, self.var, x, env),
+        }
+    }
+
+    /// Like `at`, but the tree may also give values other than numbers.
+    fn value_at(&mut self, x: Decimal, env: &mut Environment) -> Result<Value, ComputeError> {
+        env.check()?;
+        match &self.program {
+            Some(program) => program.run(x, &mut self.stack).map(Value::Number),
+            None => env.with_variable(self.var, Value::Number(x), |env| {
+                compute_tree(&self.tree, env)
+            }),
+        }
+    }
+}
+
+/// Name of the variable an operator such as `diff` or `sum` binds.
+fn variable(ast: &Ast, id: NodeId) -> Result<String, ComputeError> {
+    match &ast.node(id).kind {
+        NodeKind::Identifier(var) => Ok(var.clone()),
+        _ => Err(ComputeError::ExpectedVariable),
+    }
+}
+
+/// Evaluates `tree` as a number with `var` bound to `x`.
+pub fn evaluate_at(
+    tree: &Ast,
+    var: &str,
+    x: Decimal,
+    env: &mut Environment,
+) -> Result<Decimal, ComputeError> {
+    env.with_variable(var, Value::Number(x), |env| {
+        number(compute_tree(tree, env)?)
+    })
+}
+
+/// Finds a value of the variable `var` for which `f` is zero, starting
+/// from `guess`. Newton's method is tried first and Brent's method takes
+/// over when the derivative is unavailable or the iteration fails.
+fn find_root(
+    f: Ast,
+    var: &str,
+    guess: Decimal,
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    let settings = env.settings.clone();
+    let derivative = diff::derivative(&f, var, env);
+    let mut function = Evaluator::new(f, var, env);
+    if let Ok(derivative) = derivative {
+        let mut slope = Evaluator::new(derivative, var, env);
+        let newton = roots::newton(
+            |x| Ok((function.at(x, env)?, slope.at(x, env)?)),
+            guess,
+            &settings,
+        );
+        if let Ok(x) = newton {
+            return Ok(Value::Number(x));
+        }
+    }
+    roots::brent(|x| function.at(x, env), guess, &settings).map(Value::Number)
+}
+
+/// Samples every one of `bodies` across `[start, end]`. Points where they
+/// can not be evaluated become gaps, the first error is only reported when
+/// there is nothing to plot.
+fn plot(
+    bodies: Vec<Ast>,
+    var: String,
+    start: Decimal,
+    end: Decimal,
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    if env
+        .settings
+        .samples
+        .checked_mul(bodies.len())
+        .is_none_or(|size| size > env.settings.max_elements)
+    {
+        return Err(ComputeError::TooLarge);
+    }
+    let mut error = None;
+    let mut curves = Vec::new();
+    for body in bodies {
+        let label = body.to_string();
+        let mut curve = Evaluator::new(body, &var, env);
+        let values: Vec<_> = Plot::abscissae(start, end, env.settings.samples)?
+            .map(|x| match curve.at(x, env) {
+                Ok(y) => Some(y),
+                Err(err) => {
+                    error.get_or_insert(err);
+                    None
                 }
-                compute(tree.left, ans)?
-                    .checked_div(right)
+            })
+            .collect();
+        curves.push(Curve { label, values });
+    }
+    let empty = curves
+        .iter()
+        .all(|curve| curve.values.iter().all(Option::is_none));
+    match error {
+        Some(err) if empty => Err(err),
+        _ => Ok(Value::Plot(Plot::new(start, end, curves))),
+    }
+}
+
+/// Evaluates `body` at `start`, `start + step`, ... up to `end`, keeping
+/// the error of every row that fails.
+fn table(
+    body: Ast,
+    var: String,
+    start: Decimal,
+    end: Decimal,
+    step: Decimal,
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    if step.is_zero() {
+        return Err(ComputeError::ZeroStep);
+    }
+    let rows = end
+        .checked_sub(start)
+        .and_then(|span| span.checked_div(step))
+        .ok_or(ComputeError::IterationLimit)?
+        .floor()
+        .max(-Decimal::ONE);
+    if rows >= Decimal::from(env.settings.max_terms) {
+        return Err(ComputeError::IterationLimit);
+    }
+    let label = body.to_string();
+    let mut row = Evaluator::new(body, &var, env);
+    let rows = (0..=rows.to_i64().unwrap_or(-1))
+        .map(|i| {
+            let x = (start + step * Decimal::from(i)).normalize();
+            (x, row.value_at(x, env).map_err(|err| err.to_string()))
+        })
+        .collect();
+    Ok(Value::Table(Table::new(var, label, rows)))
+}
+
+/// `sum` or `prod` of `body` over the integers from `start` to `end`.
+fn series(
+    token: &Token,
+    body: Ast,
+    var: String,
+    start: Decimal,
+    end: Decimal,
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    if !start.is_integer() || !end.is_integer() {
+        return Err(ComputeError::MustBeInt);
+    }
+    let terms = end
+        .checked_sub(start)
+        .and_then(|terms| terms.checked_add(Decimal::ONE))
+        .ok_or(ComputeError::IterationLimit)?;
+    if terms > Decimal::from(env.settings.max_terms) {
+        return Err(ComputeError::IterationLimit);
+    }
+    let mut result = Value::Number(match token {
+        Token::Sum => Decimal::ZERO,
+        _ => Decimal::ONE,
+    });
+    let mut terms = Evaluator::new(body, &var, env);
+    let mut i = Some(start);
+    // The counter stops when it would step past the largest number
+    while let Some(x) = i.filter(|x| *x <= end) {
+        let term = terms.value_at(x, env)?;
+        result = match token {
+            Token::Sum => add(result, term)?,
+            _ => mul(result, term, env)?,
+        };
+        i = x.checked_add(Decimal::ONE);
+    }
+    Ok(result)
+}
+
+/// Value of the node `id` of `ast`. The innermost node that fails is
+/// recorded in `env`, to show the error where it happened.
+fn compute(ast: &Ast, id: NodeId, env: &mut Environment) -> Result<Value, ComputeError> {
+    if env.depth >= env.depth_limit() {
+        env.failed.get_or_insert(id);
+        return Err(ComputeError::DepthLimit);
+    }
+    env.check()?;
+    env.depth += 1;
+    let result = compute_node(ast, id, env);
+    env.depth -= 1;
+    match result {
+        // Running out of time says nothing about the node
+        Err(ComputeError::Timeout | ComputeError::Interrupted) => (),
+        Err(_) => {
+            env.failed.get_or_insert(id);
+        }
+        Ok(_) => (),
+    }
+    result
+}
+
+/// The `i`th argument of a call.
+fn nth(args: &[NodeId], i: usize) -> Result<NodeId, ComputeError> {
+    args.get(i).copied().ok_or(ComputeError::WrongArgumentCount)
+}
+
+/// Value of the optional argument `id` as a number, `default` without it.
+fn number_or(
+    ast: &Ast,
+    id: Option<&NodeId>,
+    default: Decimal,
+    env: &mut Environment,
+) -> Result<Decimal, ComputeError> {
+    match id {
+        Some(id) => number(compute(ast, *id, env)?),
+        None => Ok(default),
+    }
+}
+
+fn compute_node(ast: &Ast, id: NodeId, env: &mut Environment) -> Result<Value, ComputeError> {
+    match &ast.node(id).kind {
+        NodeKind::Literal(token) => match token {
+            Token::Literal(x) => Ok(Value::Number(*x)),
+            Token::PI => Ok(Value::Number(Decimal::PI)),
+            Token::E => Ok(Value::Number(Decimal::E)),
+            Token::True => Ok(Value::Bool(true)),
+            Token::False => Ok(Value::Bool(false)),
+            Token::Ans => env.ans.clone().ok_or(ComputeError::NoAns),
+            Token::Measurement(x, sigma) if env.settings.sigma => {
+                Ok(Value::Uncertain(Uncertain::new(*x, *sigma)))
+            }
+            // Written like a measurement, otherwise a product
+            Token::Measurement(x, sigma) => {
+                let digits = sigma * Decimal::TEN.powi(x.scale().into());
+                x.checked_mul(digits.normalize())
+                    .map(Value::Number)
                     .ok_or(ComputeError::Overflow)
             }
-            Token::Pow => compute(tree.left, ans)?
-                .checked_powd(compute(tree.right, ans)?)
-                .ok_or(ComputeError::Overflow),
-            Token::Sin => compute(tree.left, ans)?
-                .checked_sin()
-                .ok_or(ComputeError::Overflow),
-            Token::Cos => compute(tree.left, ans)?
-                .checked_cos()
-                .ok_or(ComputeError::Overflow),
-            Token::Tan => compute(tree.left, ans)?
-                .checked_tan()
-                .ok_or(ComputeError::Overflow),
-            Token::Exp => Decimal::E
-                .checked_powd(compute(tree.left, ans)?)
-                .ok_or(ComputeError::Overflow),
-            Token::Ln => compute(tree.left, ans)?
-                .checked_ln()
-                .ok_or(ComputeError::Overflow),
-            Token::Log => {
-                if tree.right.is_none() {
-                    let input = compute(tree.left, ans)?;
-                    if input.is_zero() {
-                        return Err(ComputeError::Overflow);
-                    }
-                    input.checked_log10().ok_or(ComputeError::NotReal)
-                } else {
-                    let base = compute(tree.left, ans)?;
-                    if base.is_zero() {
-                        return Err(ComputeError::LogBaseZero);
-                    }
-                    let val = compute(tree.right, ans)?;
-                    if val.is_zero() {
-                        return Err(ComputeError::Overflow);
+            _ => Err(ComputeError::Unknown),
+        },
+        NodeKind::Identifier(name) => env
+            .variables
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ComputeError::UndefinedVariable(name.clone())),
+        NodeKind::Unary(token, arg) => {
+            let x = compute(ast, *arg, env)?;
+            match token {
+                Token::Not => Ok(Value::Bool(!boolean(x)?)),
+                Token::Factorial => factorial(number(x)?).map(Value::Number),
+                Token::DoubleFactorial => double_factorial(number(x)?).map(Value::Number),
+                _ => Err(ComputeError::Unknown),
+            }
+        }
+        NodeKind::Binary(token, left, right) => operation(ast, token, *left, *right, env),
+        NodeKind::Call(token, args) => function(ast, token, args, env),
+    }
+}
+
+fn operation(
+    ast: &Ast,
+    token: &Token,
+    left: NodeId,
+    right: NodeId,
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    match token {
+        Token::And => {
+            return Ok(Value::Bool(
+                boolean(compute(ast, left, env)?)? && boolean(compute(ast, right, env)?)?,
+            ))
+        }
+        Token::Or => {
+            return Ok(Value::Bool(
+                boolean(compute(ast, left, env)?)? || boolean(compute(ast, right, env)?)?,
+            ))
+        }
+        Token::Div => {
+            let right = compute(ast, right, env)?;
+            return div(compute(ast, left, env)?, right, env);
+        }
+        Token::Assign => return Err(ComputeError::MisplacedEquation),
+        _ => (),
+    }
+    let left = compute(ast, left, env)?;
+    let right = compute(ast, right, env)?;
+    match token {
+        Token::Add => add(left, right),
+        Token::Sub => sub(left, right),
+        Token::PlusMinus => plus_minus(left, right, env.settings.sigma),
+        Token::Mul | Token::ImplMul => mul(left, right, env),
+        Token::Pow => pow(left, right, env),
+        Token::Equal => equal(left, right).map(Value::Bool),
+        Token::NotEqual => equal(left, right).map(|x| Value::Bool(!x)),
+        Token::Less => less(left, right, false).map(Value::Bool),
+        Token::LessEqual => less(left, right, true).map(Value::Bool),
+        Token::GreaterEqual => less(right, left, true).map(Value::Bool),
+        Token::Greater => less(right, left, false).map(Value::Bool),
+        _ => Err(ComputeError::Unknown),
+    }
+}
+
+fn function(
+    ast: &Ast,
+    token: &Token,
+    args: &[NodeId],
+    env: &mut Environment,
+) -> Result<Value, ComputeError> {
+    match token {
+        Token::If => {
+            let condition = boolean(compute(ast, nth(args, 0)?, env)?)?;
+            compute(ast, nth(args, if condition { 1 } else { 2 })?, env)
+        }
+        token @ (Token::Sin | Token::Cos | Token::Tan | Token::Exp | Token::Ln | Token::Sqrt) => {
+            let x = compute(ast, nth(args, 0)?, env)?;
+            let x = match token {
+                Token::Sin | Token::Cos | Token::Tan => radians(x, env)?,
+                _ => x,
+            };
+            match x {
+                Value::Interval(x) => elementary_interval(token, x).map(Value::Interval),
+                Value::Uncertain(x) => elementary_uncertain(token, x).map(Value::Uncertain),
+                x => memoized(token, number(x)?, env, |x| elementary(token, x)).map(Value::Number),
+            }
+        }
+        Token::Log if args.len() == 1 => match compute(ast, args[0], env)? {
+            Value::Interval(x) => elementary_interval(&Token::Log, x).map(Value::Interval),
+            Value::Uncertain(x) => elementary_uncertain(&Token::Log, x).map(Value::Uncertain),
+            x => memoized(&Token::Log, number(x)?, env, |x| elementary(&Token::Log, x))
+                .map(Value::Number),
+        },
+        Token::Interval => {
+            let low = number(compute(ast, nth(args, 0)?, env)?)?;
+            let high = number(compute(ast, nth(args, 1)?, env)?)?;
+            Ok(Value::Interval(Interval::new(low, high)))
+        }
+        Token::IsPrime => {
+            let n = integer(number(compute(ast, nth(args, 0)?, env)?)?)?;
+            number_theory::is_prime(n, env).map(Value::Bool)
+        }
+        Token::Matrix => {
+            let elements = args
+                .iter()
+                .map(|element| compute(ast, *element, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            if !elements.iter().any(|x| matches!(x, Value::Matrix(_))) {
+                let row = elements.into_iter().map(number).collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(Matrix::row(row)))
+            } else {
+                Matrix::from_rows(elements.into_iter().map(matrix).collect::<Result<_, _>>()?)
+                    .map(Value::Matrix)
+            }
+        }
+        Token::Transpose => match compute(ast, nth(args, 0)?, env)? {
+            Value::Matrix(m) => Ok(Value::Matrix(m.transpose())),
+            x => Ok(x),
+        },
+        Token::Det => matrix(compute(ast, nth(args, 0)?, env)?)?
+            .det(env)
+            .map(Value::Number),
+        Token::Inv => match compute(ast, nth(args, 0)?, env)? {
+            Value::Matrix(m) => m.inverse(env).map(Value::Matrix),
+            x => div(Value::Number(Decimal::ONE), x, env),
+        },
+        Token::Rank => matrix(compute(ast, nth(args, 0)?, env)?)?
+            .rank(env)
+            .map(|rank| Value::Number(rank.into())),
+        Token::Trace => matrix(compute(ast, nth(args, 0)?, env)?)?
+            .trace()
+            .map(Value::Number),
+        Token::Eye => {
+            let n = number(compute(ast, nth(args, 0)?, env)?)?;
+            if !n.is_integer() {
+                return Err(ComputeError::MustBeInt);
+            }
+            if n.is_sign_negative() {
+                return Err(ComputeError::MustBeNonNegative);
+            }
+            let n = n.to_usize().ok_or(ComputeError::Overflow)?;
+            if n == 0 {
+                return Err(ComputeError::DimensionMismatch);
+            }
+            Matrix::identity(n, env).map(Value::Matrix)
+        }
+        Token::Solve => {
+            let (first, second) = (nth(args, 0)?, nth(args, 1)?);
+            if let NodeKind::Binary(Token::Assign, lhs, rhs) = &ast.node(first).kind {
+                // solve(lhs = rhs, x) looks for a root of lhs - rhs
+                let f = Ast::binary(Token::Sub, ast.subtree(*lhs), ast.subtree(*rhs));
+                let var = variable(ast, second)?;
+                let guess = number_or(ast, args.get(2), Decimal::ZERO, env)?;
+                return find_root(f, &var, guess, env);
+            }
+            if args.len() > 2 {
+                return Err(ComputeError::WrongArgumentCount);
+            }
+            let a = matrix(compute(ast, first, env)?)?;
+            let b = matrix(compute(ast, second, env)?)?;
+            a.solve(&b, env).map(Value::Matrix)
+        }
+        Token::Root => {
+            let f = ast.subtree(nth(args, 0)?);
+            let var = variable(ast, nth(args, 1)?)?;
+            let guess = number_or(ast, args.get(2), Decimal::ZERO, env)?;
+            find_root(f, &var, guess, env)
+        }
+        Token::Plot => {
+            if args.len() < 4 {
+                return Err(ComputeError::WrongArgumentCount);
+            }
+            let (bodies, bounds) = args.split_at(args.len() - 3);
+            let var = variable(ast, bounds[0])?;
+            let start = number(compute(ast, bounds[1], env)?)?;
+            let end = number(compute(ast, bounds[2], env)?)?;
+            let bodies = bodies.iter().map(|body| ast.subtree(*body)).collect();
+            plot(bodies, var, start, end, env)
+        }
+        Token::Table => {
+            let body = ast.subtree(nth(args, 0)?);
+            let var = variable(ast, nth(args, 1)?)?;
+            let start = number(compute(ast, nth(args, 2)?, env)?)?;
+            let end = number(compute(ast, nth(args, 3)?, env)?)?;
+            let step = number(compute(ast, nth(args, 4)?, env)?)?;
+            table(body, var, start, end, step, env)
+        }
+        token @ (Token::Integrate | Token::Sum | Token::Prod) => {
+            let body = ast.subtree(nth(args, 0)?);
+            let var = variable(ast, nth(args, 1)?)?;
+            let start = number(compute(ast, nth(args, 2)?, env)?)?;
+            let end = number(compute(ast, nth(args, 3)?, env)?)?;
+            if *token != Token::Integrate {
+                return series(token, body, var, start, end, env);
+            }
+            let settings = env.settings.clone();
+            let mut integrand = Evaluator::new(body, &var, env);
+            quadrature::integrate(|x| integrand.at(x, env), start, end, &settings)
+                .map(Value::Number)
+        }
+        Token::PolyRoots => {
+            let mut coefficients = args
+                .iter()
+                .map(|arg| compute(ast, *arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            // The coefficients may also be given as a single vector
+            let coefficients = match coefficients.pop() {
+                Some(Value::Matrix(m)) if coefficients.is_empty() => {
+                    m.vector().ok_or(ComputeError::ExpectedNumber)?.to_vec()
+                }
+                last => coefficients
+                    .into_iter()
+                    .chain(last)
+                    .map(number)
+                    .collect::<Result<_, _>>()?,
+            };
+            let roots = polynomial::roots(&coefficients, &env.settings)?;
+            Ok(Value::List(
+                roots
+                    .into_iter()
+                    .map(|z| match z.im.is_zero() {
+                        true => Value::Number(z.re),
+                        false => Value::Complex(z),
+                    })
+                    .collect(),
+            ))
+        }
+        Token::Factor => {
+            let n = integer(number(compute(ast, nth(args, 0)?, env)?)?)?;
+            Ok(Value::Factorization(if n == 0 {
+                vec![(0, 1)]
+            } else {
+                number_theory::factor(n, env)?
+            }))
+        }
+        Token::Diff => {
+            let expression = ast.subtree(nth(args, 0)?);
+            let var = variable(ast, nth(args, 1)?)?;
+            let derivative = diff::derivative(&expression, &var, env)?;
+            match args.get(2) {
+                Some(point) => {
+                    let point = compute(ast, *point, env)?;
+                    env.with_variable(&var, point, |env| compute_tree(&derivative, env))
+                }
+                None => Ok(Value::Expression(derivative)),
+            }
+        }
+        Token::Call(name) => {
+            let args = args
+                .iter()
+                .map(|arg| compute(ast, *arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, args, env)
+        }
+        _ => compute_number(ast, token, args, env).map(Value::Number),
+    }
+}
+
+fn compute_number(
+    ast: &Ast,
+    token: &Token,
+    args: &[NodeId],
+    env: &mut Environment,
+) -> Result<Decimal, ComputeError> {
+    let mut arg = |i: usize| number(compute(ast, nth(args, i)?, env)?);
+    match token {
+        Token::Log => {
+            let base = arg(0)?;
+            if base.is_zero() {
+                return Err(ComputeError::LogBaseZero);
+            }
+            let val = arg(1)?;
+            if val.is_zero() {
+                return Err(ComputeError::Overflow);
+            }
+            val.checked_log10()
+                .ok_or(ComputeError::NotReal)?
+                .checked_div(base.checked_log10().ok_or(ComputeError::NotReal)?)
+                .ok_or(ComputeError::Overflow)
+        }
+        Token::Gamma => {
+            let x = arg(0)?;
+            memoized(&Token::Gamma, x, env, gamma::gamma)
+        }
+        Token::LGamma => {
+            let x = arg(0)?;
+            memoized(&Token::LGamma, x, env, gamma::lgamma)
+        }
+        Token::Beta => gamma::beta(arg(0)?, arg(1)?),
+        Token::Choose => {
+            let n = integer(arg(0)?)?;
+            decimal(number_theory::choose(n, integer(arg(1)?)?))
+        }
+        Token::Permute => {
+            let n = integer(arg(0)?)?;
+            decimal(number_theory::permute(n, integer(arg(1)?)?))
+        }
+        Token::NextPrime => {
+            let n = integer(arg(0)?)?;
+            decimal(Some(number_theory::next_prime(n, env)?))
+        }
+        Token::Totient => {
+            let n = integer(arg(0)?)?;
+            decimal(Some(number_theory::totient(n, env)?))
+        }
+        Token::Fib => decimal(number_theory::fib(integer(arg(0)?)?)),
+        Token::PowMod => {
+            let base = arg(0)?;
+            let exponent = integer(arg(1)?)?;
+            let modulus = integer(arg(2)?)?;
+            if modulus == 0 {
+                return Err(ComputeError::DivByZero);
+            }
+            if !base.is_integer() {
+                return Err(ComputeError::MustBeInt);
+            }
+            let base = base
+                .to_i128()
+                .ok_or(ComputeError::Overflow)?
+                .rem_euclid(modulus as i128) as u128;
+            decimal(Some(number_theory::pow_mod(base, exponent, modulus)))
+        }
+        _ => Err(ComputeError::Unknown),
+    }
+}
+
+/// Value of a tree other than the statement's, such as the body of a
+/// function. Its errors are shown at the node that evaluated it.
+pub fn compute_tree(tree: &Ast, env: &mut Environment) -> Result<Value, ComputeError> {
+    let result = compute(tree, tree.root(), env);
+    if result.is_err() {
+        env.failed = None;
+    }
+    result
+}
+
+/// Error of a statement and the node of its tree that failed, unless it
+/// failed outside of evaluating one.
+pub type LocatedError = (ComputeError, Option<NodeId>);
+
+/// Runs a statement, returning the value it produced if any. The statement
+/// fails once it takes longer than the `timeout` setting or the `limit` of
+/// `env`.
+pub fn execute(
+    statement: &Statement,
+    env: &mut Environment,
+) -> Result<Option<Value>, ComputeError> {
+    execute_located(statement, env).map_err(|(err, _)| err)
+}
+
+/// Like `execute`, also giving the node an error happened at.
+pub fn execute_located(
+    statement: &Statement,
+    env: &mut Environment,
+) -> Result<Option<Value>, LocatedError> {
+    env.depth = 0;
+    env.failed = None;
+    let timeout = match env.settings.timeout {
+        0 => None,
+        timeout => Some(Duration::from_millis(timeout as u64)),
+    };
+    env.deadline = timeout
+        .into_iter()
+        .chain(env.limit)
+        .min()
+        .map(|timeout| Instant::now() + timeout);
+    let result = run(statement, env);
+    env.deadline = None;
+    result.map_err(|err| (err, env.failed.take()))
+}
+
+fn run(statement: &Statement, env: &mut Environment) -> Result<Option<Value>, ComputeError> {
+    match statement {
+        Statement::Expression(tree) => {
+            let value = compute(tree, tree.root(), env)?;
+            env.ans = Some(value.clone());
+            Ok(Some(value))
+        }
+        Statement::Assignment(name, tree) => {
+            let value = compute(tree, tree.root(), env)?;
+            Arc::make_mut(&mut env.variables).insert(name.clone(), value.clone());
+            Ok(Some(value))
+        }
+        Statement::Function(name, params, body) => {
+            let function = Function {
+                params: params.clone(),
+                body: Arc::new(body.clone()),
+            };
+            Arc::make_mut(&mut env.functions).insert(name.clone(), function);
+            Ok(None)
+        }
+        Statement::Set(name, tree) => {
+            let value = number(compute(tree, tree.root(), env)?)?;
+            match name.as_str() {
+                "tolerance" if value.is_sign_negative() => {
+                    return Err(ComputeError::MustBeNonNegative)
+                }
+                "tolerance" => env.settings.tolerance = value,
+                "iterations" => {
+                    env.settings.max_iterations = integer(value)?
+                        .try_into()
+                        .map_err(|_| ComputeError::Overflow)?
+                }
+                "terms" => {
+                    env.settings.max_terms = integer(value)?
+                        .try_into()
+                        .map_err(|_| ComputeError::Overflow)?
+                }
+                "samples" | "width" | "height" | "depth" | "timeout" | "elements" | "precision" => {
+                    let value: usize = integer(value)?
+                        .try_into()
+                        .map_err(|_| ComputeError::Overflow)?;
+                    check_setting(name, value)?;
+                    match name.as_str() {
+                        "samples" => env.settings.samples = value,
+                        "width" => env.settings.width = value,
+                        "height" => env.settings.height = value,
+                        "depth" => env.settings.max_depth = value,
+                        "timeout" => env.settings.timeout = value,
+                        "precision" => env.settings.precision = value as u32,
+                        _ => env.settings.max_elements = value,
                     }
-                    val.checked_log10()
-                        .ok_or(ComputeError::NotReal)?
-                        .checked_div(base.checked_log10().ok_or(ComputeError::NotReal)?)
-                        .ok_or(ComputeError::Overflow)
                 }
+                "bounds" => env.settings.bounds = !value.is_zero(),
+                "sigma" => env.settings.sigma = !value.is_zero(),
+                "optimize" => env.settings.optimize = !value.is_zero(),
+                "degrees" => env.settings.degrees = !value.is_zero(),
+                _ => return Err(ComputeError::UnknownSetting(name.clone())),
             }
-            Token::Sqrt => compute(tree.left, ans)?.sqrt().ok_or(ComputeError::NotReal),
-            Token::Factorial => factorial(compute(tree.left, ans)?),
-            Token::Literal(x) => Ok(x),
-            Token::PI => Ok(Decimal::PI),
-            Token::E => Ok(Decimal::E),
-            Token::Ans => ans.ok_or(ComputeError::NoAns),
-            _ => Err(ComputeError::Unknown),
-        },
-        None => Ok(dec!(0)),
+            Ok(Some(Value::Number(value)))
+        }
+        Statement::Simplify(tree) => {
+            // Known variables are replaced by their values
+            let values: Vec<(String, Ast)> = env
+                .variables
+                .iter()
+                .filter_map(|(name, value)| match value {
+                    Value::Number(x) => Some((name.clone(), Ast::new(Token::Literal(*x)))),
+                    Value::Expression(tree) => Some((name.clone(), tree.clone())),
+                    _ => None,
+                })
+                .collect();
+            let bindings: Vec<_> = values.iter().map(|(name, tree)| (name, tree)).collect();
+            let tree = diff::inline(tree, env)?.substitute(&bindings);
+            Ok(Some(Value::Expression(simplify::simplify(&tree, env)?)))
+        }
     }
 }
 
-pub fn compute_tree(tree: ParseTree, ans: Option<Decimal>) -> Result<Decimal, ComputeError> {
-    compute(Some(Box::new(tree)), ans)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    /// Value of the last statement of `input`.
+    fn run(input: &str, env: &mut Environment) -> Result<String, ComputeError> {
+        let mut result = Ok(String::new());
+        for statement in parse_statements(tokenize(input.to_string()).unwrap()).unwrap() {
+            result = execute(&statement, env)
+                .map(|value| value.map(|value| value.to_string()).unwrap_or_default());
+        }
+        result
+    }
+
+    #[test]
+    fn evaluates_only_the_branch_taken() {
+        let mut env = Environment::default();
+        assert_eq!(
+            run("if(1 < 2, 1, 1/0)", &mut env).ok().as_deref(),
+            Some("1")
+        );
+        assert_eq!(run("1 >= 2 ? 1/0 : 2", &mut env).ok().as_deref(), Some("2"));
+        assert_eq!(
+            run("false and 1/0 == 1", &mut env).ok().as_deref(),
+            Some("false")
+        );
+        assert_eq!(
+            run("true or 1/0 == 1", &mut env).ok().as_deref(),
+            Some("true")
+        );
+        assert_eq!(run("not (2 != 2)", &mut env).ok().as_deref(), Some("true"));
+        let f = "f(n) = if(n < 2, 1, n * f(n - 1)); f(20)";
+        assert_eq!(
+            run(f, &mut env).ok().as_deref(),
+            Some("2432902008176640000")
+        );
+        assert!(matches!(
+            run("if(1, 2, 3)", &mut env),
+            Err(ComputeError::ExpectedBool)
+        ));
+        assert!(matches!(
+            run("true and 1/0 == 1", &mut env),
+            Err(ComputeError::DivByZero)
+        ));
+    }
+    #[test]
+    fn evaluates_a_tree_again_with_other_variables() {
+        let mut env = Environment::default();
+        let statements = parse_statements(tokenize("x^2 + 1/y".to_string()).unwrap()).unwrap();
+        let [Statement::Expression(tree)] = statements.as_slice() else {
+            panic!("not one expression");
+        };
+        let mut values = Vec::new();
+        for (x, y) in [(1, 1), (3, 2), (2, 0), (3, 2)] {
+            run(&format!("x = {x}; y = {y}"), &mut env).ok();
+            values.push(
+                compute_tree(tree, &mut env)
+                    .map(|value| value.to_string())
+                    .ok(),
+            );
+            // The statement itself stays usable after failing
+            let again = execute(&statements[0], &mut env).map(|value| value.map(|v| v.to_string()));
+            assert_eq!(again.ok().flatten(), values.last().cloned().flatten());
+        }
+        let expected = [Some("2"), Some("9.50"), None, Some("9.50")];
+        assert_eq!(values, expected.map(|value| value.map(str::to_string)));
+        assert_eq!(tree.to_string(), "x^2 + 1 / y");
+    }
 }
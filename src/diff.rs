@@ -0,0 +1,256 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::ast::{Ast, NodeId, NodeKind};
+use crate::compute::{ComputeError, Environment};
+use crate::simplify::simplify;
+use crate::tokenizer::Token;
+
+fn constant(x: Decimal) -> Ast {
+    Ast::new(Token::Literal(x))
+}
+
+fn literal(tree: &Ast) -> Option<Decimal> {
+    tree.literal(tree.root())
+}
+
+fn is(tree: &Ast, x: Decimal) -> bool {
+    literal(tree) == Some(x)
+}
+
+/// Folds two literals with `op`, keeping the operation when it overflows.
+fn fold(token: Token, left: Ast, right: Ast, op: fn(Decimal, Decimal) -> Option<Decimal>) -> Ast {
+    match (literal(&left), literal(&right)) {
+        (Some(x), Some(y)) => match op(x, y) {
+            Some(z) => constant(z),
+            None => Ast::binary(token, left, right),
+        },
+        _ => Ast::binary(token, left, right),
+    }
+}
+
+fn add(left: Ast, right: Ast) -> Ast {
+    if is(&left, Decimal::ZERO) {
+        return right;
+    }
+    if is(&right, Decimal::ZERO) {
+        return left;
+    }
+    fold(Token::Add, left, right, Decimal::checked_add)
+}
+
+fn sub(left: Ast, right: Ast) -> Ast {
+    if is(&right, Decimal::ZERO) {
+        return left;
+    }
+    fold(Token::Sub, left, right, Decimal::checked_sub)
+}
+
+fn mul(left: Ast, right: Ast) -> Ast {
+    if is(&left, Decimal::ZERO) || is(&right, Decimal::ZERO) {
+        return constant(Decimal::ZERO);
+    }
+    if is(&left, Decimal::ONE) {
+        return right;
+    }
+    if is(&right, Decimal::ONE) {
+        return left;
+    }
+    fold(Token::Mul, left, right, Decimal::checked_mul)
+}
+
+fn div(left: Ast, right: Ast) -> Ast {
+    if is(&left, Decimal::ZERO) || is(&right, Decimal::ONE) {
+        return left;
+    }
+    Ast::binary(Token::Div, left, right)
+}
+
+fn pow(left: Ast, right: Ast) -> Ast {
+    if is(&right, Decimal::ZERO) {
+        return constant(Decimal::ONE);
+    }
+    if is(&right, Decimal::ONE) {
+        return left;
+    }
+    Ast::binary(Token::Pow, left, right)
+}
+
+fn function(token: Token, arg: Ast) -> Ast {
+    Ast::call(token, vec![arg])
+}
+
+/// One unit of angle in radians, `pi / 180` when trigonometric functions
+/// take degrees.
+fn unit(env: &Environment) -> Ast {
+    match env.settings.degrees {
+        true => div(Ast::new(Token::PI), constant(dec!(180))),
+        false => constant(Decimal::ONE),
+    }
+}
+
+/// Expands calls to user defined functions into their bodies.
+pub fn inline(tree: &Ast, env: &Environment) -> Result<Ast, ComputeError> {
+    inline_node(tree, tree.root(), 0, env)
+}
+
+/// Inlines the node `id` of `ast` within `depth` nested function calls,
+/// which are limited like calls during evaluation so that recursive
+/// functions fail instead of expanding forever.
+fn inline_node(
+    ast: &Ast,
+    id: NodeId,
+    depth: usize,
+    env: &Environment,
+) -> Result<Ast, ComputeError> {
+    env.check()?;
+    let kind = &ast.node(id).kind;
+    let args = kind
+        .children()
+        .into_iter()
+        .map(|child| inline_node(ast, child, depth, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    match (kind, args.as_slice()) {
+        (NodeKind::Call(Token::Diff, _), [expression, var]) => {
+            let NodeKind::Identifier(var) = &var.node(var.root()).kind else {
+                return Err(ComputeError::ExpectedVariable);
+            };
+            d(expression, expression.root(), var, &unit(env))
+        }
+        (NodeKind::Call(Token::Call(name), _), _) => match env.functions.get(name) {
+            Some(_) if depth >= env.depth_limit() => Err(ComputeError::DepthLimit),
+            Some(function) if function.params.len() == args.len() => {
+                let bindings: Vec<_> = function.params.iter().zip(&args).collect();
+                let body = function.body.substitute(&bindings);
+                inline_node(&body, body.root(), depth + 1, env)
+            }
+            Some(_) => Err(ComputeError::WrongArgumentCount),
+            // A variable followed by parentheses is a multiplication
+            None => match <[Ast; 1]>::try_from(args) {
+                Ok([arg]) => Ok(Ast::binary(
+                    Token::Mul,
+                    Ast::new(Token::Identifier(name.clone())),
+                    arg,
+                )),
+                Err(_) => Err(ComputeError::UndefinedFunction(name.clone())),
+            },
+        },
+        _ => Ok(Ast::compose(kind, args)),
+    }
+}
+
+/// Derivative of the node `id` of `ast`, where `unit` is the size of one
+/// unit of angle in radians.
+fn d(ast: &Ast, id: NodeId, var: &str, unit: &Ast) -> Result<Ast, ComputeError> {
+    if !ast.contains(id, var) {
+        return Ok(constant(Decimal::ZERO));
+    }
+    let (token, args) = match &ast.node(id).kind {
+        NodeKind::Identifier(_) => return Ok(constant(Decimal::ONE)),
+        NodeKind::Binary(token, left, right) => (token, vec![*left, *right]),
+        NodeKind::Call(token, args) => (token, args.clone()),
+        _ => return Err(ComputeError::NotDifferentiable),
+    };
+    let tree = || ast.subtree(id);
+    let u = args[0];
+    let du = d(ast, u, var, unit)?;
+    let u = || ast.subtree(u);
+    Ok(match token {
+        Token::Add => add(du, d(ast, args[1], var, unit)?),
+        Token::Sub => sub(du, d(ast, args[1], var, unit)?),
+        Token::Mul | Token::ImplMul => {
+            let v = args[1];
+            add(mul(du, ast.subtree(v)), mul(u(), d(ast, v, var, unit)?))
+        }
+        Token::Div => {
+            let v = args[1];
+            if !ast.contains(v, var) {
+                div(du, ast.subtree(v))
+            } else {
+                div(
+                    sub(mul(du, ast.subtree(v)), mul(u(), d(ast, v, var, unit)?)),
+                    pow(ast.subtree(v), constant(Decimal::TWO)),
+                )
+            }
+        }
+        Token::Pow => {
+            let v = args[1];
+            if !ast.contains(v, var) {
+                let exponent = sub(ast.subtree(v), constant(Decimal::ONE));
+                mul(mul(ast.subtree(v), pow(u(), exponent)), du)
+            } else if !ast.contains(args[0], var) {
+                let ln = function(Token::Ln, u());
+                mul(mul(tree(), ln), d(ast, v, var, unit)?)
+            } else {
+                let ln = function(Token::Ln, u());
+                let inner = add(
+                    mul(d(ast, v, var, unit)?, ln),
+                    div(mul(ast.subtree(v), du), u()),
+                );
+                mul(tree(), inner)
+            }
+        }
+        Token::Sin => mul(function(Token::Cos, u()), mul(du, unit.clone())),
+        Token::Cos => sub(
+            constant(Decimal::ZERO),
+            mul(function(Token::Sin, u()), mul(du, unit.clone())),
+        ),
+        Token::Tan => div(
+            mul(du, unit.clone()),
+            pow(function(Token::Cos, u()), constant(Decimal::TWO)),
+        ),
+        Token::Exp => mul(tree(), du),
+        Token::Ln => div(du, u()),
+        Token::Log if args.len() == 1 => {
+            div(du, mul(u(), function(Token::Ln, constant(Decimal::TEN))))
+        }
+        Token::Log => {
+            // log(b, v) = ln(v) / ln(b)
+            let quotient = Ast::binary(
+                Token::Div,
+                function(Token::Ln, ast.subtree(args[1])),
+                function(Token::Ln, u()),
+            );
+            return d(&quotient, quotient.root(), var, unit);
+        }
+        Token::Sqrt => div(du, mul(constant(Decimal::TWO), tree())),
+        _ => return Err(ComputeError::NotDifferentiable),
+    })
+}
+
+/// Differentiates `tree` with respect to `var`.
+pub fn derivative(tree: &Ast, var: &str, env: &Environment) -> Result<Ast, ComputeError> {
+    let inlined = inline(tree, env)?;
+    simplify(&d(&inlined, inlined.root(), var, &unit(env))?, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::execute;
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn run(input: &str, env: &mut Environment) -> Result<String, ComputeError> {
+        let mut result = Ok(String::new());
+        for statement in parse_statements(tokenize(input.to_string()).unwrap()).unwrap() {
+            result = execute(&statement, env)
+                .map(|value| value.map(|value| value.to_string()).unwrap_or_default());
+        }
+        result
+    }
+
+    #[test]
+    fn differentiates_user_functions() {
+        let mut env = Environment::default();
+        let derivative = run("f(x) = x^3 + 2x; diff(f(x), x)", &mut env);
+        assert_eq!(derivative.ok().as_deref(), Some("3x^2 + 2"));
+    }
+
+    #[test]
+    fn stops_inlining_recursive_functions() {
+        let mut env = Environment::default();
+        let derivative = run("set depth 50; f(x) = f(x) + 1; diff(f(x), x)", &mut env);
+        assert!(matches!(derivative, Err(ComputeError::DepthLimit)));
+    }
+}
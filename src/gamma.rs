@@ -0,0 +1,236 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::compute::ComputeError;
+
+/// ln(2π) / 2
+const HALF_LN_TAU: Decimal = dec!(0.9189385332046727417803297364);
+
+/// Arguments are shifted up to at least this value before the asymptotic
+/// series is applied, which keeps its truncation error below 1e-28.
+const SHIFT: Decimal = dec!(20);
+
+/// `B(2k) / (2k (2k - 1))` for k = 1..=12 as numerator and denominator pairs.
+const STIRLING: [(i64, i64); 12] = [
+    (1, 12),
+    (-1, 360),
+    (1, 1260),
+    (-1, 1680),
+    (1, 1188),
+    (-691, 360360),
+    (1, 156),
+    (-3617, 122400),
+    (43867, 244188),
+    (-174611, 125400),
+    (77683, 5796),
+    (-236364091, 1506960),
+];
+
+fn is_pole(x: Decimal) -> bool {
+    x.is_integer() && x <= Decimal::ZERO
+}
+
+/// ln Γ(x) for x >= SHIFT through Stirling's series.
+fn stirling(x: Decimal) -> Result<Decimal, ComputeError> {
+    let ln_x = x.checked_ln().ok_or(ComputeError::Overflow)?;
+    let mut result = (x - dec!(0.5))
+        .checked_mul(ln_x)
+        .and_then(|y| y.checked_sub(x))
+        .and_then(|y| y.checked_add(HALF_LN_TAU))
+        .ok_or(ComputeError::Overflow)?;
+    // Where x² overflows every term after the first is below the precision
+    let x_squared = x.checked_mul(x).unwrap_or(Decimal::MAX);
+    let mut power = x;
+    for (numerator, denominator) in STIRLING {
+        let term = Decimal::from(numerator)
+            .checked_div(Decimal::from(denominator))
+            .and_then(|c| c.checked_div(power))
+            .ok_or(ComputeError::Overflow)?;
+        result += term;
+        power = match power.checked_mul(x_squared) {
+            Some(power) => power,
+            None => break,
+        };
+    }
+    Ok(result)
+}
+
+/// Splits a positive `x` into `ln Γ(x + n)` and `x (x + 1) ... (x + n - 1)`
+/// with `x + n >= SHIFT`.
+fn shifted(mut x: Decimal) -> Result<(Decimal, Decimal), ComputeError> {
+    let mut product = Decimal::ONE;
+    while x < SHIFT {
+        product = product.checked_mul(x).ok_or(ComputeError::Overflow)?;
+        x += Decimal::ONE;
+    }
+    Ok((stirling(x)?, product))
+}
+
+/// e^x to full precision. `Decimal::checked_exp` loses digits quickly and
+/// gives up on arguments above 20, so the integer part is raised separately
+/// and only the remainder goes through the Taylor series.
+pub fn exp(x: Decimal) -> Result<Decimal, ComputeError> {
+    // e^-65 is already below the smallest representable value
+    if x < dec!(-65) {
+        return Ok(Decimal::ZERO);
+    }
+    let n = x.round();
+    let r = x - n;
+    let mut term = Decimal::ONE;
+    let mut sum = Decimal::ONE;
+    for k in 1..40 {
+        term = term * r / Decimal::from(k);
+        if term.is_zero() {
+            break;
+        }
+        sum += term;
+    }
+    let n = n.to_i64().ok_or(ComputeError::Overflow)?;
+    Decimal::E
+        .checked_powi(n)
+        .and_then(|y| y.checked_mul(sum))
+        .ok_or(ComputeError::Overflow)
+}
+
+/// sin(πx), exact at integers.
+fn sin_pi(x: Decimal) -> Result<Decimal, ComputeError> {
+    // Reduce to [-0.5, 0.5] using sin(π(x + 1)) = -sin(πx)
+    let n = x.round();
+    let r = x - n;
+    if r.is_zero() {
+        return Ok(Decimal::ZERO);
+    }
+    let y = Decimal::PI * r;
+    let y_squared = y * y;
+    let mut term = y;
+    let mut sum = y;
+    for k in 1..20 {
+        term = -term * y_squared / Decimal::from((2 * k) * (2 * k + 1));
+        if term.is_zero() {
+            break;
+        }
+        sum += term;
+    }
+    Ok(if (n % Decimal::TWO).is_zero() {
+        sum
+    } else {
+        -sum
+    })
+}
+
+pub fn gamma(x: Decimal) -> Result<Decimal, ComputeError> {
+    if is_pole(x) {
+        return Err(ComputeError::Pole);
+    }
+    if x.is_integer() {
+        // Γ(n) = (n - 1)!, kept exact
+        let mut result = Decimal::ONE;
+        let mut n = x - Decimal::ONE;
+        while n > Decimal::ONE {
+            result = result.checked_mul(n).ok_or(ComputeError::Overflow)?;
+            n -= Decimal::ONE;
+        }
+        return Ok(result);
+    }
+    if x < dec!(0.5) {
+        // Reflection formula: Γ(x) Γ(1 - x) = π / sin(πx)
+        let denominator = sin_pi(x)?
+            .checked_mul(gamma(Decimal::ONE - x)?)
+            .ok_or(ComputeError::Overflow)?;
+        return Decimal::PI
+            .checked_div(denominator)
+            .ok_or(ComputeError::Overflow);
+    }
+    let (ln_gamma, product) = shifted(x)?;
+    exp(ln_gamma)?
+        .checked_div(product)
+        .ok_or(ComputeError::Overflow)
+}
+
+/// ln |Γ(x)|, which stays representable far beyond the range of `gamma`.
+pub fn lgamma(x: Decimal) -> Result<Decimal, ComputeError> {
+    if is_pole(x) {
+        return Err(ComputeError::Pole);
+    }
+    if x < dec!(0.5) {
+        let ln_sin = sin_pi(x)?
+            .abs()
+            .checked_ln()
+            .ok_or(ComputeError::Overflow)?;
+        let reflected = lgamma(Decimal::ONE - x)?;
+        return Decimal::PI
+            .checked_ln()
+            .and_then(|y| y.checked_sub(ln_sin))
+            .and_then(|y| y.checked_sub(reflected))
+            .ok_or(ComputeError::Overflow);
+    }
+    let (ln_gamma, product) = shifted(x)?;
+    product
+        .checked_ln()
+        .and_then(|y| ln_gamma.checked_sub(y))
+        .ok_or(ComputeError::Overflow)
+}
+
+pub fn beta(a: Decimal, b: Decimal) -> Result<Decimal, ComputeError> {
+    if is_pole(a) || is_pole(b) {
+        return Err(ComputeError::Pole);
+    }
+    let sum = a.checked_add(b).ok_or(ComputeError::Overflow)?;
+    if is_pole(sum) {
+        return Ok(Decimal::ZERO);
+    }
+    if a.is_sign_positive() && b.is_sign_positive() {
+        let ln_sum = lgamma(sum)?;
+        return exp(lgamma(a)?
+            .checked_add(lgamma(b)?)
+            .and_then(|y| y.checked_sub(ln_sum))
+            .ok_or(ComputeError::Overflow)?);
+    }
+    let gamma_sum = gamma(sum)?;
+    gamma(a)?
+        .checked_mul(gamma(b)?)
+        .and_then(|y| y.checked_div(gamma_sum))
+        .ok_or(ComputeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQRT_PI: Decimal = dec!(1.7724538509055160272981674833);
+
+    fn close(x: Decimal, expected: Decimal) -> bool {
+        (x - expected).abs() <= dec!(0.00000000000000000001) * expected.abs().max(Decimal::ONE)
+    }
+
+    #[test]
+    fn matches_known_values() {
+        assert!(matches!(gamma(dec!(5)), Ok(x) if x == dec!(24)));
+        assert!(matches!(gamma(dec!(0.5)), Ok(x) if close(x, SQRT_PI)));
+        assert!(matches!(gamma(dec!(-0.5)), Ok(x) if close(x, dec!(-2) * SQRT_PI)));
+        assert!(
+            matches!(gamma(dec!(4.5)), Ok(x) if close(x, dec!(11.631728396567448929144224109)))
+        );
+        assert!(
+            matches!(lgamma(dec!(100)), Ok(x) if close(x, dec!(359.13420536957539877604401046)))
+        );
+        assert!(matches!(beta(dec!(2), dec!(3)), Ok(x) if close(x, Decimal::ONE / dec!(12))));
+        assert!(matches!(gamma(Decimal::ZERO), Err(ComputeError::Pole)));
+        assert!(matches!(gamma(dec!(-3)), Err(ComputeError::Pole)));
+    }
+
+    #[test]
+    fn overflows_near_the_largest_number() {
+        assert!(matches!(gamma(Decimal::MAX), Err(ComputeError::Overflow)));
+        assert!(matches!(gamma(dec!(100.5)), Err(ComputeError::Overflow)));
+        assert!(matches!(gamma(Decimal::MIN), Err(ComputeError::Pole)));
+        let x = lgamma(dec!(1000000000000000000000000));
+        assert!(matches!(x, Ok(x) if close(x, dec!(54262042231857096416431768.200))));
+        assert!(matches!(lgamma(Decimal::MAX), Err(ComputeError::Overflow)));
+        assert!(matches!(exp(Decimal::MAX), Err(ComputeError::Overflow)));
+        assert!(matches!(
+            beta(Decimal::MAX, Decimal::ONE),
+            Err(ComputeError::Overflow)
+        ));
+    }
+}
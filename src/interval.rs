@@ -0,0 +1,363 @@
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::ComputeError;
+
+/// Relative error assumed for the elementary functions of `Decimal`, the
+/// bounds computed from them are widened by this much.
+const APPROXIMATION: Decimal = dec!(0.00000000000000000001);
+
+/// Relative widening of a rounded arithmetic result, a few units in the
+/// last of the 28 digits.
+const ROUNDING: Decimal = dec!(0.000000000000000000000000001);
+
+/// Smallest widening, for results close to zero.
+const MINIMUM: Decimal = dec!(0.0000000000000000000000000001);
+
+/// Closed interval `[low, high]` that is guaranteed to contain the exact
+/// result. Bounds that may have been rounded are moved outward. It is
+/// written `interval(4.9, 5.1)` or `5 ± 0.1`, brackets like `[4.9, 5.1]`
+/// are a matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Interval {
+    pub low: Decimal,
+    pub high: Decimal,
+}
+
+fn checked(x: Option<Decimal>) -> Result<Decimal, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+fn down(x: Decimal, relative: Decimal) -> Decimal {
+    x.checked_sub((x.abs() * relative).max(MINIMUM))
+        .unwrap_or(Decimal::MIN)
+}
+
+fn up(x: Decimal, relative: Decimal) -> Decimal {
+    x.checked_add((x.abs() * relative).max(MINIMUM))
+        .unwrap_or(Decimal::MAX)
+}
+
+/// Smallest interval containing all of `values`, widened by `relative`
+/// unless every value is exact.
+fn hull(values: &[(Decimal, bool)], relative: Decimal) -> Interval {
+    let (mut low, mut low_exact) = values[0];
+    let (mut high, mut high_exact) = values[0];
+    for &(x, exact) in &values[1..] {
+        if x < low || (x == low && !exact) {
+            (low, low_exact) = (x, exact);
+        }
+        if x > high || (x == high && !exact) {
+            (high, high_exact) = (x, exact);
+        }
+    }
+    Interval {
+        low: if low_exact { low } else { down(low, relative) },
+        high: if high_exact { high } else { up(high, relative) },
+    }
+}
+
+/// Whether `[low, high]` contains `phase + k period` for some integer `k`.
+/// Bounds too far from `phase` to measure are taken to contain it, which
+/// only widens the image of the interval.
+fn contains_phase(low: Decimal, high: Decimal, phase: Decimal, period: Decimal) -> bool {
+    let (Some(low), Some(high)) = (low.checked_sub(phase), high.checked_sub(phase)) else {
+        return true;
+    };
+    (low / period).ceil() <= (high / period).floor()
+}
+
+impl Interval {
+    pub fn new(a: Decimal, b: Decimal) -> Self {
+        Self {
+            low: a.min(b),
+            high: a.max(b),
+        }
+    }
+
+    pub fn point(x: Decimal) -> Self {
+        Self::new(x, x)
+    }
+
+    /// `center ± radius`.
+    pub fn around(center: Decimal, radius: Decimal) -> Result<Self, ComputeError> {
+        let radius = radius.abs();
+        Ok(Self::new(
+            checked(center.checked_sub(radius))?,
+            checked(center.checked_add(radius))?,
+        ))
+    }
+
+    pub fn center(&self) -> Decimal {
+        match self.high.checked_sub(self.low) {
+            Some(width) => self.low + width / Decimal::TWO,
+            // Bounds this far apart have opposite signs
+            None => self.low / Decimal::TWO + self.high / Decimal::TWO,
+        }
+    }
+
+    /// Half the width, or the largest number when it is wider than that.
+    pub fn radius(&self) -> Decimal {
+        match self.high.checked_sub(self.low) {
+            Some(width) => width / Decimal::TWO,
+            None => (self.high / Decimal::TWO)
+                .checked_sub(self.low / Decimal::TWO)
+                .unwrap_or(Decimal::MAX),
+        }
+    }
+
+    pub fn contains(&self, x: Decimal) -> bool {
+        self.low <= x && x <= self.high
+    }
+
+    pub fn checked_add(&self, other: Self) -> Result<Self, ComputeError> {
+        let add = |x: Decimal, y: Decimal| {
+            let sum = checked(x.checked_add(y))?;
+            let exact = sum.checked_sub(x) == Some(y) && sum.checked_sub(y) == Some(x);
+            Ok((sum, exact))
+        };
+        Ok(hull(
+            &[add(self.low, other.low)?, add(self.high, other.high)?],
+            ROUNDING,
+        ))
+    }
+
+    pub fn checked_neg(&self) -> Self {
+        Self::new(-self.high, -self.low)
+    }
+
+    pub fn checked_sub(&self, other: Self) -> Result<Self, ComputeError> {
+        self.checked_add(other.checked_neg())
+    }
+
+    pub fn checked_mul(&self, other: Self) -> Result<Self, ComputeError> {
+        let mul = |x: Decimal, y: Decimal| {
+            let product = checked(x.checked_mul(y))?;
+            // Decimal keeps every digit of an exact product
+            Ok((product, product.scale() == x.scale() + y.scale()))
+        };
+        Ok(hull(
+            &[
+                mul(self.low, other.low)?,
+                mul(self.low, other.high)?,
+                mul(self.high, other.low)?,
+                mul(self.high, other.high)?,
+            ],
+            ROUNDING,
+        ))
+    }
+
+    /// Division by an interval containing zero would be unbounded.
+    pub fn checked_div(&self, other: Self) -> Result<Self, ComputeError> {
+        if other.contains(Decimal::ZERO) {
+            return Err(ComputeError::DivByZero);
+        }
+        let div = |x: Decimal, y: Decimal| {
+            let quotient = checked(x.checked_div(y))?;
+            Ok((quotient, quotient.checked_mul(y) == Some(x)))
+        };
+        Ok(hull(
+            &[
+                div(self.low, other.low)?,
+                div(self.low, other.high)?,
+                div(self.high, other.low)?,
+                div(self.high, other.high)?,
+            ],
+            ROUNDING,
+        ))
+    }
+
+    /// Image under a function that increases over the whole interval.
+    pub fn increasing(
+        &self,
+        f: impl Fn(Decimal) -> Result<Decimal, ComputeError>,
+    ) -> Result<Self, ComputeError> {
+        Ok(hull(
+            &[(f(self.low)?, false), (f(self.high)?, false)],
+            APPROXIMATION,
+        ))
+    }
+
+    /// Image under a function of period 2π with its maximum at `peak` and
+    /// its minimum half a period later, such as sin and cos.
+    pub fn periodic(
+        &self,
+        f: impl Fn(Decimal) -> Result<Decimal, ComputeError>,
+        peak: Decimal,
+    ) -> Result<Self, ComputeError> {
+        let period = Decimal::TWO_PI;
+        let mut bounds = self.increasing(&f)?;
+        if contains_phase(self.low, self.high, peak, period) {
+            bounds.high = Decimal::ONE;
+        }
+        if contains_phase(self.low, self.high, peak + Decimal::PI, period) {
+            bounds.low = -Decimal::ONE;
+        }
+        Ok(Self::new(
+            bounds.low.max(-Decimal::ONE),
+            bounds.high.min(Decimal::ONE),
+        ))
+    }
+
+    /// Image under tan, which increases between its poles.
+    pub fn tan(
+        &self,
+        f: impl Fn(Decimal) -> Result<Decimal, ComputeError>,
+    ) -> Result<Self, ComputeError> {
+        if contains_phase(self.low, self.high, Decimal::HALF_PI, Decimal::PI) {
+            return Err(ComputeError::Pole);
+        }
+        self.increasing(f)
+    }
+
+    /// `self^n` for an integer `n`, even powers are smallest at zero.
+    pub fn checked_powi(&self, n: i64) -> Result<Self, ComputeError> {
+        if n < 0 {
+            return Self::point(Decimal::ONE).checked_div(self.checked_powi(-n)?);
+        }
+        let power = |x: Decimal| {
+            let result = checked(x.checked_powi(n))?;
+            let scale = u32::try_from(n).ok().and_then(|n| x.scale().checked_mul(n));
+            Ok((result, scale == Some(result.scale())))
+        };
+        let (low, high) = (power(self.low)?, power(self.high)?);
+        if n % 2 == 0 && self.contains(Decimal::ZERO) {
+            let high = if self.high.abs() > self.low.abs() {
+                high
+            } else {
+                low
+            };
+            return Ok(hull(&[(Decimal::ZERO, true), high], ROUNDING));
+        }
+        Ok(hull(&[low, high], ROUNDING))
+    }
+
+    /// `self^exponent` for a non-negative base, monotonic in both arguments
+    /// so that the extremes lie at the corners.
+    pub fn checked_pow(&self, exponent: Self) -> Result<Self, ComputeError> {
+        if self.low.is_sign_negative() && !self.low.is_zero() {
+            return Err(ComputeError::NotReal);
+        }
+        let pow = |x: Decimal, y: Decimal| -> Result<(Decimal, bool), ComputeError> {
+            Ok((checked(x.checked_powd(y))?, false))
+        };
+        Ok(hull(
+            &[
+                pow(self.low, exponent.low)?,
+                pow(self.low, exponent.high)?,
+                pow(self.high, exponent.low)?,
+                pow(self.high, exponent.high)?,
+            ],
+            APPROXIMATION,
+        ))
+    }
+
+    /// Rounds the bounds outward so that the result still contains the
+    /// exact value.
+    pub fn round_dp(&self, dp: u32) -> Self {
+        Self {
+            low: self
+                .low
+                .round_dp_with_strategy(dp, RoundingStrategy::ToNegativeInfinity),
+            high: self
+                .high
+                .round_dp_with_strategy(dp, RoundingStrategy::ToPositiveInfinity),
+        }
+    }
+}
+
+/// `center ± radius`, or `[low, high]` with the alternate flag.
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "[{}, {}]", self.low, self.high)
+        } else {
+            write!(
+                f,
+                "{} ± {}",
+                self.center().normalize(),
+                self.radius().normalize()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{execute, Environment};
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn run(input: &str) -> Option<String> {
+        let statements = parse_statements(tokenize(input.to_string()).ok()?).ok()?;
+        let value = execute(statements.first()?, &mut Environment::default()).ok()?;
+        Some(value?.to_string())
+    }
+
+    #[test]
+    fn rounds_outward() {
+        let third = Interval::point(Decimal::ONE)
+            .checked_div(Interval::point(Decimal::from(3)))
+            .unwrap_or_else(|_| panic!("1 / 3 failed"));
+        assert!(third.low < third.high);
+        assert!(third.low * Decimal::from(3) < Decimal::ONE);
+        assert!(third.high * Decimal::from(3) > Decimal::ONE);
+
+        // Exact results are not widened
+        let sum = Interval::point(dec!(0.1)).checked_add(Interval::point(dec!(0.2)));
+        assert!(matches!(sum, Ok(sum) if sum == Interval::point(dec!(0.3))));
+        let product = Interval::new(dec!(0.1), dec!(0.2)).checked_mul(Interval::point(dec!(-3)));
+        assert!(matches!(product, Ok(product) if product == Interval::new(dec!(-0.6), dec!(-0.3))));
+
+        let rounded = third.round_dp(2);
+        assert_eq!((rounded.low, rounded.high), (dec!(0.33), dec!(0.34)));
+    }
+
+    #[test]
+    fn bounds_sine_and_cosine_between_their_extremes() {
+        let Ok(sin) =
+            Interval::new(Decimal::ZERO, dec!(3)).periodic(|x| Ok(x.sin()), Decimal::HALF_PI)
+        else {
+            panic!("sin failed");
+        };
+        assert_eq!(sin.high, Decimal::ONE);
+        assert!(sin.low <= Decimal::ZERO && sin.low > dec!(-0.000001));
+
+        // cos decreases on [1, 2], its bounds come from the far ends
+        let Ok(cos) =
+            Interval::new(Decimal::ONE, Decimal::TWO).periodic(|x| Ok(x.cos()), Decimal::ZERO)
+        else {
+            panic!("cos failed");
+        };
+        assert!(cos.contains(Decimal::TWO.cos()) && cos.contains(Decimal::ONE.cos()));
+        assert!(cos.low > dec!(-0.4162) && cos.high < dec!(0.5404));
+
+        let whole = Interval::new(dec!(-10), dec!(10)).periodic(|x| Ok(x.cos()), Decimal::ZERO);
+        assert!(matches!(whole, Ok(whole) if whole == Interval::new(-Decimal::ONE, Decimal::ONE)));
+    }
+
+    #[test]
+    fn refuses_to_divide_by_an_interval_around_zero() {
+        let divisor = Interval::new(-Decimal::ONE, Decimal::ONE);
+        assert!(matches!(
+            Interval::point(Decimal::ONE).checked_div(divisor),
+            Err(ComputeError::DivByZero)
+        ));
+        assert!(matches!(
+            divisor.checked_powi(-2),
+            Err(ComputeError::DivByZero)
+        ));
+        assert_eq!(run("1 / interval(-1, 1)"), None);
+    }
+
+    #[test]
+    fn reads_intervals_but_not_brackets() {
+        assert_eq!(run("interval(5.1, 4.9)").as_deref(), Some("5 ± 0.1"));
+        assert_eq!(run("(5 ± 0.1) * 2").as_deref(), Some("10 ± 0.2"));
+        assert_eq!(run("[4.9, 5.1]").as_deref(), Some("[ 4.9 5.1 ]"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::BufRead;
+
+use rust_decimal::prelude::*;
+use serde_json::{json, Map};
+
+use crate::compute::{execute_located, Environment};
+use crate::parser::parse_statements_spanned;
+use crate::tokenizer::tokenize_spanned;
+use crate::value::Value;
+
+/// Byte range of the input an error refers to.
+pub fn span(start: usize, end: usize) -> serde_json::Value {
+    json!({ "start": start, "end": end })
+}
+
+pub fn error(kind: &str, message: String, span: serde_json::Value) -> serde_json::Value {
+    json!({ "kind": kind, "message": message, "span": span })
+}
+
+fn decimals(values: &[Decimal]) -> serde_json::Value {
+    values.iter().map(|x| x.normalize().to_string()).collect()
+}
+
+/// Structured form of a result. Numbers are given as strings so that no
+/// digits are lost to floating point.
+fn result(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Number(x) => json!({ "type": "number", "value": x.normalize().to_string() }),
+        Value::Bool(x) => json!({ "type": "bool", "value": x }),
+        Value::Matrix(matrix) => json!({
+            "type": "matrix",
+            "value": matrix.rows().map(decimals).collect::<Vec<_>>(),
+        }),
+        Value::Complex(z) => json!({
+            "type": "complex",
+            "re": z.re.normalize().to_string(),
+            "im": z.im.normalize().to_string(),
+        }),
+        Value::Interval(x) => json!({
+            "type": "interval",
+            "low": x.low.normalize().to_string(),
+            "high": x.high.normalize().to_string(),
+        }),
+        Value::Uncertain(x) => json!({
+            "type": "uncertain",
+            "value": x.value.normalize().to_string(),
+            "sigma": x.sigma().map(|x| x.normalize().to_string()).ok(),
+        }),
+        Value::List(values) => json!({
+            "type": "list",
+            "value": values.iter().map(result).collect::<Vec<_>>(),
+        }),
+        Value::Factorization(factors) => json!({
+            "type": "factorization",
+            "value": factors
+                .iter()
+                .map(|(p, e)| json!([p.to_string(), e]))
+                .collect::<Vec<_>>(),
+        }),
+        Value::Expression(tree) => json!({ "type": "expression", "value": tree.to_string() }),
+        Value::Plot(plot) => json!({ "type": "plot", "value": plot.to_string() }),
+        Value::Table(table) => json!({ "type": "table", "value": table.to_string() }),
+    }
+}
+
+/// Evaluates one line of input, giving the fields of the response.
+pub fn evaluate(input: &str, env: &mut Environment) -> Map<String, serde_json::Value> {
+    let mut response = Map::new();
+    response.insert("input".to_string(), json!(input));
+    let mut outcome = (serde_json::Value::Null, serde_json::Value::Null);
+    let err = match tokenize_spanned(input) {
+        Err(position) => {
+            let end = position + input[position..].chars().next().map_or(0, char::len_utf8);
+            Some(error(
+                "invalid_token",
+                "Invalid token".to_string(),
+                span(position, end),
+            ))
+        }
+        Ok(tokens) => match parse_statements_spanned(tokens) {
+            Err((err, at)) => Some(error(err.kind(), err.to_string(), span(at.start, at.end))),
+            Ok(statements) => statements.iter().find_map(|statement| {
+                match execute_located(&statement.statement, env) {
+                    Ok(Some(x)) => {
+                        let x = x.round_dp_with_strategy(
+                            env.settings.precision,
+                            RoundingStrategy::MidpointAwayFromZero,
+                        );
+                        let text = match env.settings.bounds {
+                            true => format!("{x:#}"),
+                            false => x.to_string(),
+                        };
+                        outcome = (result(&x), json!(text));
+                        None
+                    }
+                    Ok(None) => None,
+                    Err((err, node)) => {
+                        let ast = statement.statement.ast();
+                        let at = node.map_or(statement.span, |id| ast.node(id).span);
+                        Some(error(err.kind(), err.to_string(), span(at.start, at.end)))
+                    }
+                }
+            }),
+        },
+    };
+    response.insert("result".to_string(), outcome.0);
+    response.insert("formatted".to_string(), outcome.1);
+    response.insert("error".to_string(), err.unwrap_or_default());
+    response
+}
+
+/// Response to one line of input. A line holding a JSON object is a
+/// request, its `input` is evaluated and its `id` is echoed back, other
+/// lines are evaluated as they are.
+fn respond(line: &str, env: &mut Environment) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(serde_json::Value::Object(request)) => match request.get("input") {
+            Some(serde_json::Value::String(input)) => {
+                let mut response = evaluate(input, env);
+                if let Some(id) = request.get("id") {
+                    response.insert("id".to_string(), id.clone());
+                }
+                serde_json::Value::Object(response)
+            }
+            _ => json!({
+                "id": request.get("id"),
+                "error": error("invalid_request", "Missing input".to_string(), span(0, 0)),
+            }),
+        },
+        _ => serde_json::Value::Object(evaluate(line, env)),
+    }
+}
+
+/// Answers every line of `reader` with one line of JSON.
+pub fn run(reader: impl BufRead, env: &mut Environment) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            eprintln!("Error reading line from standard input.");
+            std::process::exit(1);
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        env.cancel.reset();
+        println!("{}", respond(&line, env));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input the error of the last statement of `input` points at.
+    fn error_at(input: &str, env: &mut Environment) -> String {
+        let response = evaluate(input, env);
+        let span = &response["error"]["span"];
+        let (start, end) = (
+            span["start"].as_u64().unwrap(),
+            span["end"].as_u64().unwrap(),
+        );
+        input[start as usize..end as usize].to_string()
+    }
+
+    #[test]
+    fn locates_errors_at_the_failing_node() {
+        let mut env = Environment::default();
+        assert_eq!(error_at("1 + 1/0", &mut env), "1/0");
+        assert_eq!(error_at("2 * sin(x)", &mut env), "(x)");
+        assert_eq!(error_at("set depth 0", &mut env), "set depth 0");
+        assert_eq!(error_at("(1 + ", &mut env), "(");
+        // Errors in a function body are shown at the call
+        assert_eq!(error_at("f(x) = 1/x; 2 + f(0)", &mut env), "f(0)");
+        assert_eq!(
+            error_at("sum(1/k, k, 0, 3) + 1", &mut env),
+            "sum(1/k, k, 0, 3)"
+        );
+    }
+    #[test]
+    fn answers_with_a_result_or_an_error() {
+        let mut env = Environment::default();
+        assert_eq!(
+            respond("[[1, 2]] * 2", &mut env),
+            json!({
+                "input": "[[1, 2]] * 2",
+                "result": { "type": "matrix", "value": [["2", "4"]] },
+                "formatted": "[ 2 4 ]",
+                "error": null,
+            })
+        );
+        assert_eq!(
+            respond("1 + $", &mut env)["error"],
+            json!({ "kind": "invalid_token", "message": "Invalid token", "span": span(4, 5) })
+        );
+        let response = respond("(1 + ", &mut env);
+        assert_eq!(response["result"], serde_json::Value::Null);
+        assert_eq!(response["error"]["kind"], "invalid_parenthesis");
+    }
+
+    #[test]
+    fn echoes_the_id_of_a_request() {
+        let mut env = Environment::default();
+        let response = respond(r#"{"id": 7, "input": "x = 2; x^10"}"#, &mut env);
+        assert_eq!(response["id"], 7);
+        assert_eq!(
+            response["result"],
+            json!({ "type": "number", "value": "1024" })
+        );
+        assert_eq!(response["input"], "x = 2; x^10");
+        assert_eq!(
+            respond(r#"{"id": "a"}"#, &mut env),
+            json!({
+                "id": "a",
+                "error": { "kind": "invalid_request", "message": "Missing input", "span": span(0, 0) },
+            })
+        );
+    }
+}
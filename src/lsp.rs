@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use rust_decimal::prelude::*;
+use serde_json::json;
+
+use crate::ast::{Ast, NodeKind, Span};
+use crate::compute::{execute, execute_located, Environment};
+use crate::output;
+use crate::parser::{parse_statements_spanned, SpannedStatement, Statement};
+use crate::tokenizer::{open_groups, tokenize_spanned, Token};
+use crate::value::Value;
+
+/// Longest a statement may take while the document is being edited.
+const ANALYSIS_LIMIT: Duration = Duration::from_secs(1);
+
+/// Built-in functions offered for completion.
+const FUNCTIONS: &[&str] = &[
+    "sin",
+    "cos",
+    "tan",
+    "exp",
+    "ln",
+    "log",
+    "sqrt",
+    "transpose",
+    "det",
+    "inv",
+    "rank",
+    "trace",
+    "eye",
+    "solve",
+    "nCr",
+    "nPr",
+    "isprime",
+    "nextprime",
+    "factor",
+    "totient",
+    "fib",
+    "powmod",
+    "gamma",
+    "lgamma",
+    "beta",
+    "diff",
+    "root",
+    "integrate",
+    "sum",
+    "prod",
+    "polyroots",
+    "plot",
+    "table",
+    "interval",
+    "if",
+];
+
+/// Built-in constants and keywords offered for completion.
+const KEYWORDS: &[&str] = &[
+    "pi", "e", "ans", "true", "false", "and", "or", "not", "set", "simplify",
+];
+
+/// Line holding a single expression, kept so that hovering over a part of
+/// it can show the value of that part.
+struct Expression {
+    line: String,
+    ast: Ast,
+    /// Variables as they were before the line was evaluated.
+    env: Environment,
+}
+
+/// Outcome of a group of lines evaluated together, a statement continues
+/// on the next line while parentheses are open.
+struct Group {
+    /// First and last line, counted from zero.
+    lines: (usize, usize),
+    value: Option<String>,
+    /// Message and range as `(line, character)` start and end positions,
+    /// in UTF-16 code units.
+    error: Option<(String, Range)>,
+    expression: Option<Expression>,
+}
+
+impl Group {
+    /// Value of the innermost operation or call around the UTF-16 column
+    /// `character`, unless that is the whole line.
+    fn part(&self, character: usize) -> Option<String> {
+        let Expression { line, ast, env } = self.expression.as_ref()?;
+        let mut units = 0;
+        let (offset, _) = line.char_indices().find(|(_, c)| {
+            units += c.len_utf16();
+            units > character
+        })?;
+        let mut id = ast.node_at(offset)?;
+        // A number on its own says nothing, show what it is part of
+        while let NodeKind::Literal(_) = ast.node(id).kind {
+            id = ast.node(id).parent?;
+        }
+        ast.node(id).parent?;
+        let statement = Statement::Expression(ast.subtree(id));
+        let value = execute(&statement, &mut env.clone()).ok()??;
+        let span = ast.node(id).span;
+        Some(format!(
+            "{} = {}",
+            &line[span.start..span.end],
+            show(value, env)
+        ))
+    }
+}
+
+/// Result of evaluating a whole document.
+struct Analysis {
+    groups: Vec<Group>,
+    env: Environment,
+}
+
+/// Result as it is printed when running a script.
+fn show(x: Value, env: &Environment) -> String {
+    let x = x.round_dp_with_strategy(
+        env.settings.precision,
+        RoundingStrategy::MidpointAwayFromZero,
+    );
+    match env.settings.bounds {
+        true => format!("{x:#}"),
+        false => x.to_string(),
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+type Position = (usize, usize);
+type Range = (Position, Position);
+
+/// Line and UTF-16 column of byte `offset` of `input`, which starts on line
+/// `first`.
+fn position(input: &str, first: usize, offset: usize) -> Position {
+    let before = &input[..offset];
+    let line = first + before.matches('\n').count();
+    (
+        line,
+        utf16_len(&before[before.rfind('\n').map_or(0, |i| i + 1)..]),
+    )
+}
+
+/// Range of the bytes in `span` of `input`, which starts on line `first`.
+fn locate(input: &str, first: usize, span: Span) -> Range {
+    (
+        position(input, first, span.start),
+        position(input, first, span.end),
+    )
+}
+
+/// Evaluates `text` the way a script is run, without writing any file it
+/// redirects to.
+fn analyze(text: &str) -> Analysis {
+    let mut env = Environment::default();
+    env.limit = Some(ANALYSIS_LIMIT);
+    let mut groups = Vec::new();
+    let mut input = String::new();
+    let mut start = 0;
+    for (number, line) in text.lines().enumerate() {
+        if input.is_empty() {
+            start = number;
+        }
+        input.push_str(line);
+        input.push('\n');
+        let (source, _) = output::redirection(&input);
+        let mut group = Group {
+            lines: (start, number),
+            value: None,
+            error: None,
+            expression: None,
+        };
+        let spanned = match tokenize_spanned(source) {
+            Ok(spanned) => spanned,
+            Err(offset) => {
+                let width = input[offset..].chars().next().map_or(0, char::len_utf8);
+                let span = Span {
+                    start: offset,
+                    end: offset + width,
+                };
+                group.error = Some(("Invalid token".to_string(), locate(&input, start, span)));
+                input.clear();
+                groups.push(group);
+                continue;
+            }
+        };
+        let tokens: Vec<Token> = spanned.iter().map(|(token, _)| token.clone()).collect();
+        if open_groups(&tokens) > 0 {
+            continue;
+        }
+        match parse_statements_spanned(spanned) {
+            Err((err, span)) => {
+                group.error = Some((format!("Syntax error: {err}"), locate(&input, start, span)));
+            }
+            Ok(statements) => {
+                // Parts are only shown for a line that is a single expression
+                if let [SpannedStatement {
+                    statement: Statement::Expression(ast),
+                    ..
+                }] = statements.as_slice()
+                {
+                    if start == number {
+                        group.expression = Some(Expression {
+                            line: line.to_string(),
+                            ast: ast.clone(),
+                            env: env.clone(),
+                        });
+                    }
+                }
+                for spanned in &statements {
+                    match execute_located(&spanned.statement, &mut env) {
+                        Ok(Some(x)) => group.value = Some(show(x, &env)),
+                        Ok(None) => (),
+                        Err((err, node)) => {
+                            let ast = spanned.statement.ast();
+                            let span = node.map_or(spanned.span, |id| ast.node(id).span);
+                            let range = locate(&input, start, span);
+                            group.error = Some((format!("Math error: {err}"), range));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        input.clear();
+        groups.push(group);
+    }
+    if !input.is_empty() {
+        let last = text.lines().count().saturating_sub(1);
+        let end = text.lines().last().map_or(0, utf16_len);
+        groups.push(Group {
+            lines: (start, last),
+            value: None,
+            error: Some((
+                "Syntax error: Invalid Parenthesis".to_string(),
+                ((start, 0), (last, end)),
+            )),
+            expression: None,
+        });
+    }
+    Analysis { groups, env }
+}
+
+fn range(((line, start), (end_line, end)): Range) -> serde_json::Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": end_line, "character": end },
+    })
+}
+
+fn diagnostics(uri: &str, analysis: &Analysis) -> serde_json::Value {
+    let diagnostics: Vec<_> = analysis
+        .groups
+        .iter()
+        .filter_map(|group| group.error.as_ref())
+        .map(|(message, position)| {
+            json!({
+                "range": range(*position),
+                "severity": 1,
+                "source": "calculator",
+                "message": message,
+            })
+        })
+        .collect();
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Open documents with their text and the result of evaluating it.
+#[derive(Default)]
+struct Documents {
+    documents: HashMap<String, (String, Analysis)>,
+}
+
+impl Documents {
+    fn update(&mut self, uri: &str, text: String) -> serde_json::Value {
+        let analysis = analyze(&text);
+        let notification = diagnostics(uri, &analysis);
+        self.documents.insert(uri.to_string(), (text, analysis));
+        notification
+    }
+
+    /// Value of the part of an expression under the cursor, or of the
+    /// statement on that line.
+    fn hover(&self, uri: &str, line: usize, character: usize) -> serde_json::Value {
+        let Some((_, analysis)) = self.documents.get(uri) else {
+            return serde_json::Value::Null;
+        };
+        analysis
+            .groups
+            .iter()
+            .find(|group| group.lines.0 <= line && line <= group.lines.1)
+            .and_then(|group| group.part(character).or_else(|| group.value.clone()))
+            .map_or(
+                serde_json::Value::Null,
+                |value| json!({ "contents": { "kind": "plaintext", "value": value } }),
+            )
+    }
+
+    /// `= value` after the last line of every group with a one line value.
+    fn inlay_hints(&self, uri: &str) -> serde_json::Value {
+        let Some((text, analysis)) = self.documents.get(uri) else {
+            return json!([]);
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        let hints: Vec<_> = analysis
+            .groups
+            .iter()
+            .filter_map(|group| {
+                let value = group.value.as_ref().filter(|value| !value.contains('\n'))?;
+                let line = group.lines.1;
+                let character = lines.get(line).map_or(0, |line| utf16_len(line));
+                Some(json!({
+                    "position": { "line": line, "character": character },
+                    "label": format!("= {value}"),
+                    "paddingLeft": true,
+                }))
+            })
+            .collect();
+        json!(hints)
+    }
+
+    fn completion(&self, uri: &str) -> serde_json::Value {
+        // Kinds from the protocol: function, variable and keyword
+        let mut items: Vec<_> = FUNCTIONS
+            .iter()
+            .map(|name| json!({ "label": name, "kind": 3 }))
+            .chain(
+                KEYWORDS
+                    .iter()
+                    .map(|name| json!({ "label": name, "kind": 14 })),
+            )
+            .collect();
+        if let Some((_, analysis)) = self.documents.get(uri) {
+            let mut variables: Vec<_> = analysis.env.variables.keys().collect();
+            variables.sort();
+            items.extend(
+                variables
+                    .into_iter()
+                    .map(|name| json!({ "label": name, "kind": 6 })),
+            );
+            let mut functions: Vec<_> = analysis.env.functions.iter().collect();
+            functions.sort_by_key(|(name, _)| *name);
+            items.extend(functions.into_iter().map(|(name, function)| {
+                json!({
+                    "label": name,
+                    "kind": 3,
+                    "detail": format!("{name}({}) = {}", function.params.join(", "), function.body),
+                })
+            }));
+        }
+        json!(items)
+    }
+}
+
+/// Reads one message framed by a Content-Length header.
+fn read_message(reader: &mut impl BufRead) -> Option<serde_json::Value> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn write_message(writer: &mut impl Write, message: &serde_json::Value) {
+    let body = message.to_string();
+    let _ = write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len());
+    let _ = writer.flush();
+}
+
+/// Runs a language server for calculator scripts on standard input and
+/// output until the client sends `exit`.
+pub fn run() {
+    let mut reader = std::io::stdin().lock();
+    let mut writer = std::io::stdout().lock();
+    let mut documents = Documents::default();
+
+    while let Some(message) = read_message(&mut reader) {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "inlayHintProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "calculator" },
+            }),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                write_message(&mut writer, &documents.update(uri, text.to_string()));
+                continue;
+            }
+            "textDocument/didChange" => {
+                // Only full synchronisation is announced, the last change
+                // holds the whole document
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|x| x.last()?["text"].as_str()) {
+                    write_message(&mut writer, &documents.update(uri, text.to_string()));
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.documents.remove(uri);
+                let cleared = json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                });
+                write_message(&mut writer, &cleared);
+                continue;
+            }
+            "textDocument/hover" => {
+                let position = &params["position"];
+                let line = position["line"].as_u64().unwrap_or_default();
+                let character = position["character"].as_u64().unwrap_or_default();
+                documents.hover(uri, line as usize, character as usize)
+            }
+            "textDocument/inlayHint" => documents.inlay_hints(uri),
+            "textDocument/completion" => documents.completion(uri),
+            "shutdown" => serde_json::Value::Null,
+            "exit" => return,
+            _ if message.get("id").is_none() => continue,
+            _ => {
+                let error = json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("Unknown method {method}") },
+                });
+                write_message(&mut writer, &error);
+                continue;
+            }
+        };
+        let response = json!({ "jsonrpc": "2.0", "id": message["id"], "result": result });
+        write_message(&mut writer, &response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///notes.calc";
+    const TEXT: &str = "x = 3\ny = (x +\n  1)\n1/0 + x\nx * (x + 1)\n1 + 😀 + 😀\n";
+
+    fn hover(documents: &Documents, line: usize, character: usize) -> serde_json::Value {
+        documents.hover(URI, line, character)["contents"]["value"].clone()
+    }
+
+    #[test]
+    fn reports_errors_where_they_happen() {
+        let mut documents = Documents::default();
+        let notification = documents.update(URI, TEXT.to_string());
+        assert_eq!(notification["params"]["uri"], URI);
+        let diagnostics = &notification["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().map(Vec::len), Some(2));
+        assert_eq!(diagnostics[0]["message"], "Math error: Division by zero");
+        assert_eq!(diagnostics[0]["range"], range(((3, 0), (3, 3))));
+        // Columns count UTF-16 code units
+        assert_eq!(diagnostics[1]["message"], "Invalid token");
+        assert_eq!(diagnostics[1]["range"], range(((5, 4), (5, 6))));
+    }
+
+    #[test]
+    fn shows_values_on_hover_and_after_lines() {
+        let mut documents = Documents::default();
+        documents.update(URI, TEXT.to_string());
+        assert_eq!(hover(&documents, 0, 0), "3");
+        // A statement continued over two lines has one value
+        assert_eq!(hover(&documents, 1, 0), "4");
+        assert_eq!(hover(&documents, 2, 1), "4");
+        assert_eq!(hover(&documents, 4, 8), "(x + 1) = 4");
+        assert_eq!(hover(&documents, 4, 0), "x = 3");
+        assert_eq!(documents.hover(URI, 9, 0), serde_json::Value::Null);
+
+        let hints = documents.inlay_hints(URI);
+        let hints: Vec<_> = hints
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|hint| (hint["position"]["line"].clone(), hint["label"].clone()))
+            .collect();
+        assert_eq!(
+            hints,
+            [
+                (json!(0), json!("= 3")),
+                (json!(2), json!("= 4")),
+                (json!(4), json!("= 12"))
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_messages_by_length() {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "result": "é" });
+        let mut framed = Vec::new();
+        write_message(&mut framed, &message);
+        write_message(&mut framed, &message);
+        let mut reader = framed.as_slice();
+        assert_eq!(read_message(&mut reader), Some(message.clone()));
+        assert_eq!(read_message(&mut reader), Some(message));
+        assert_eq!(read_message(&mut reader), None);
+    }
+}
@@ -1,56 +1,348 @@
+mod ast;
+mod batch;
+mod bytecode;
+mod complex;
 mod compute;
+mod diff;
+mod gamma;
+mod interval;
+mod json;
+mod lsp;
+mod matrix;
+mod number_theory;
+mod optimize;
+mod output;
 mod parser;
+mod plot;
+mod polynomial;
+mod printer;
+mod quadrature;
+mod roots;
+mod server;
+mod session;
+mod simplify;
+mod table;
 mod tokenizer;
+mod uncertain;
+mod value;
 
-use compute::compute_tree;
-use parser::parse;
-use rust_decimal::RoundingStrategy;
-use tokenizer::tokenize;
+use std::io::{BufRead, Write};
+use std::time::Instant;
 
-fn main() {
+use compute::{execute, Environment};
+use parser::{parse_statements, Statement};
+use rust_decimal::{Decimal, RoundingStrategy};
+use tokenizer::{open_groups, tokenize};
+
+/// Evaluates every statement read from `reader`. Errors are prefixed with
+/// the source name and line when reading a script.
+fn run(reader: impl BufRead, env: &mut Environment, script: Option<&str>) {
     let mut input = String::new();
-    let mut ans = None;
+    let mut line_number = 0;
+    let mut start = 0;
 
-    loop {
-        match std::io::stdin().read_line(&mut input) {
-            Ok(_) => (),
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
             Err(_) => {
                 eprintln!("Error reading line from standard input.");
                 std::process::exit(1);
             }
         };
+        line_number += 1;
+        if input.is_empty() {
+            start = line_number;
+        }
+        input.push_str(&line);
+        input.push('\n');
 
-        let tokens = tokenize(input.clone());
-        input.clear();
+        let location = match script {
+            Some(name) => format!("{name}:{start}: "),
+            None => String::new(),
+        };
+
+        if let Some((command, path)) = session::command(&input) {
+            let result = match command {
+                "save" => session::save(path, env).map_err(|err| ("saving", err)),
+                _ => session::load(path, env).map_err(|err| ("loading", err)),
+            };
+            if let Err((action, err)) = result {
+                println!("{location}Error {action} {path}: {err}");
+            }
+            input.clear();
+            continue;
+        }
 
-        let tokens = match tokens {
-            Some(tokens) => tokens,
-            None => {
-                println!("Invalid token.");
+        let (source, target) = output::redirection(&input);
+        let target = target.map(str::to_string);
+        let tokens = match tokenize(source.to_string()) {
+            Ok(tokens) => tokens,
+            Err(_) => {
+                println!("{location}Invalid token.");
+                input.clear();
                 continue;
             }
         };
+        if open_groups(&tokens) > 0 {
+            continue;
+        }
+        env.history.push(input.trim_end().to_string());
+        input.clear();
 
-        let tree = match parse(tokens) {
-            Ok(tree) => tree,
+        let statements = match parse_statements(tokens) {
+            Ok(statements) => statements,
             Err(err) => {
-                println!("Syntax error: {err}");
+                println!("{location}Syntax error: {err}");
                 continue;
             }
         };
 
-        ans = match compute_tree(tree, ans) {
-            Ok(x) => {
-                println!(
-                    "{}",
-                    x.round_dp_with_strategy(10, RoundingStrategy::MidpointAwayFromZero)
-                );
-                Some(x)
+        env.cancel.reset();
+        let count = statements.len();
+        for (i, statement) in statements.iter().enumerate() {
+            let name = match statement {
+                Statement::Assignment(name, _) | Statement::Set(name, _) => Some(name.clone()),
+                _ => None,
+            };
+            let target = target.as_deref().filter(|_| i + 1 == count);
+            match execute(statement, env) {
+                Ok(Some(x)) => {
+                    let x = x.round_dp_with_strategy(
+                        env.settings.precision,
+                        RoundingStrategy::MidpointAwayFromZero,
+                    );
+                    let text = match env.settings.bounds {
+                        true => format!("{x:#}"),
+                        false => x.to_string(),
+                    };
+                    match (target, name) {
+                        // A redirected result goes to the file instead
+                        (Some(path), _) => {
+                            if let Err(err) = output::write(path, &x, &env.settings) {
+                                println!("{location}Error writing {path}: {err}");
+                            }
+                        }
+                        (None, Some(name))
+                            if matches!(
+                                x,
+                                value::Value::Matrix(_)
+                                    | value::Value::Plot(_)
+                                    | value::Value::Table(_)
+                            ) =>
+                        {
+                            println!("{name} =\n{text}")
+                        }
+                        (None, Some(name)) => println!("{name} = {text}"),
+                        (None, None) => println!("{text}"),
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    println!("{location}Math error: {err}");
+                    break;
+                }
+            }
+        }
+    }
+
+    if !input.is_empty() {
+        match script {
+            Some(name) => println!("{name}:{start}: Syntax error: Invalid Parenthesis"),
+            None => println!("Syntax error: Invalid Parenthesis"),
+        }
+    }
+}
+
+/// `serve [--port N] [--timeout MILLISECONDS] [--jobs N]`
+fn serve(args: &[String]) {
+    let mut port = 8000;
+    let mut timeout = 5000;
+    let mut jobs = batch::default_jobs();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().and_then(|value| value.parse().ok());
+        match (arg.as_str(), value) {
+            ("--port", Some(value)) => port = value,
+            ("--timeout", Some(value)) => timeout = value,
+            ("--jobs", Some(value)) if value > 0 => jobs = value as usize,
+            _ => {
+                eprintln!("Usage: calculator serve [--port N] [--timeout MILLISECONDS] [--jobs N]");
+                std::process::exit(2);
+            }
+        }
+    }
+    let Ok(port) = u16::try_from(port) else {
+        eprintln!("Invalid port {port}");
+        std::process::exit(2);
+    };
+    if let Err(err) = server::serve(port, std::time::Duration::from_millis(timeout), jobs) {
+        eprintln!("Error starting server: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// `bench [--iterations N] [expression]` times an expression of `x` on
+/// the tree walking evaluator and on the bytecode machine.
+fn bench(args: &[String]) {
+    let usage = || {
+        eprintln!("Usage: calculator bench [--iterations N] [expression of x]");
+        std::process::exit(2);
+    };
+    let mut iterations: usize = 10000;
+    let mut source = String::from("sin(x)^2 + cos(x) * x / 3 - sqrt(x + 1)");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iterations" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => iterations = n,
+                _ => usage(),
+            },
+            expression => source = expression.to_string(),
+        }
+    }
+    let tree = match tokenize(source.clone()).ok().map(parse_statements) {
+        Some(Ok(statements)) => match <[Statement; 1]>::try_from(statements) {
+            Ok([Statement::Expression(tree)]) => tree,
+            _ => return usage(),
+        },
+        _ => return usage(),
+    };
+    let mut env = Environment::default();
+    let Some(program) = bytecode::Program::compile(&tree, "x", &env) else {
+        eprintln!("{source} can not be compiled to bytecode");
+        std::process::exit(1);
+    };
+    let points: Vec<_> = (0..iterations)
+        .map(|i| Decimal::from(i) / Decimal::from(iterations))
+        .collect();
+
+    let start = Instant::now();
+    let walked: Vec<_> = points
+        .iter()
+        .map(|&x| compute::evaluate_at(&tree, "x", x, &mut env).ok())
+        .collect();
+    let tree_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut stack = Vec::new();
+    let compiled: Vec<_> = points
+        .iter()
+        .map(|&x| program.run(x, &mut stack).ok())
+        .collect();
+    let bytecode_time = start.elapsed();
+
+    println!("{source}, {iterations} evaluations");
+    println!("tree:     {tree_time:?}");
+    println!("bytecode: {bytecode_time:?}");
+    println!(
+        "speedup:  {:.1}x",
+        tree_time.as_secs_f64() / bytecode_time.as_secs_f64().max(f64::EPSILON)
+    );
+    if walked != compiled {
+        println!("Results differ");
+        std::process::exit(1);
+    }
+}
+
+/// `parse [--binary] expression` prints the parse tree of an expression as
+/// JSON, or writes it in the binary format.
+fn parse(args: &[String]) {
+    let (json, source) = match args {
+        [source] => (true, source),
+        [flag, source] if flag == "--binary" => (false, source),
+        _ => {
+            eprintln!("Usage: calculator parse [--binary] expression");
+            std::process::exit(2);
+        }
+    };
+    let tree = match tokenize(source.clone()).ok().map(parse_statements) {
+        Some(Ok(statements)) => match <[Statement; 1]>::try_from(statements) {
+            Ok([Statement::Expression(tree)]) => tree,
+            _ => {
+                eprintln!("{source} is not a single expression");
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{source} can not be parsed");
+            std::process::exit(1);
+        }
+    };
+    let written = session::encode(&tree, json)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&bytes).map_err(|err| err.to_string())?;
+            if json {
+                writeln!(stdout).map_err(|err| err.to_string())?;
             }
+            Ok(())
+        });
+    if let Err(err) = written {
+        eprintln!("Error writing parse tree: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Makes Ctrl-C stop the evaluation in progress instead of the process. A
+/// second Ctrl-C before another line is read still ends the process.
+fn interrupt_on_ctrl_c(env: &Environment) {
+    let cancel = env.cancel.clone();
+    let handler = ctrlc::set_handler(move || {
+        if cancel.cancel() {
+            std::process::exit(130);
+        }
+    });
+    if let Err(err) = handler {
+        eprintln!("Error installing Ctrl-C handler: {err}");
+    }
+}
+
+fn start() {
+    let mut env = Environment::default();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("serve") => return serve(&args[1..]),
+        Some("lsp") => return lsp::run(),
+        Some("bench") => return bench(&args[1..]),
+        Some("parse") => return parse(&args[1..]),
+        _ => (),
+    }
+    let (flags, scripts): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| arg == "--json");
+    let json = !flags.is_empty();
+
+    if scripts.is_empty() {
+        interrupt_on_ctrl_c(&env);
+        match json {
+            true => json::run(std::io::stdin().lock(), &mut env),
+            false => run(std::io::stdin().lock(), &mut env, None),
+        }
+        return;
+    }
+
+    for script in scripts {
+        let file = match std::fs::File::open(&script) {
+            Ok(file) => file,
             Err(err) => {
-                println!("Math error: {err}");
-                continue;
+                eprintln!("Error opening {script}: {err}");
+                std::process::exit(1);
             }
         };
+        match json {
+            true => json::run(std::io::BufReader::new(file), &mut env),
+            false => run(std::io::BufReader::new(file), &mut env, Some(&script)),
+        }
+    }
+}
+
+fn main() {
+    // Nested expressions are evaluated recursively, the main thread may not
+    // have room for `compute::MAX_DEPTH` levels
+    let worker = std::thread::Builder::new()
+        .stack_size(compute::STACK_SIZE)
+        .spawn(start);
+    if !worker.is_ok_and(|worker| worker.join().is_ok()) {
+        std::process::exit(101);
     }
 }
@@ -0,0 +1,486 @@
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{ComputeError, Environment, Settings};
+
+/// Pivots smaller than this are treated as zero during elimination.
+const EPSILON: Decimal = dec!(0.00000000000000000001);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Saved")]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<Decimal>,
+}
+
+/// Matrix as read from a saved session, which must have at least one row
+/// and column and as many elements as its shape says.
+#[derive(Deserialize)]
+struct Saved {
+    rows: usize,
+    cols: usize,
+    data: Vec<Decimal>,
+}
+
+impl TryFrom<Saved> for Matrix {
+    type Error = String;
+
+    fn try_from(saved: Saved) -> Result<Self, String> {
+        if saved.rows == 0 || saved.cols == 0 {
+            return Err(format!("{} by {} matrix", saved.rows, saved.cols));
+        }
+        if saved.rows.checked_mul(saved.cols) != Some(saved.data.len()) {
+            return Err(format!(
+                "{} elements in a {} by {} matrix",
+                saved.data.len(),
+                saved.rows,
+                saved.cols
+            ));
+        }
+        Ok(Self {
+            rows: saved.rows,
+            cols: saved.cols,
+            data: saved.data,
+        })
+    }
+}
+
+fn check_size(rows: usize, cols: usize, settings: &Settings) -> Result<(), ComputeError> {
+    match rows.checked_mul(cols) {
+        Some(size) if size <= settings.max_elements => Ok(()),
+        _ => Err(ComputeError::TooLarge),
+    }
+}
+
+impl Matrix {
+    fn zero(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            data: vec![Decimal::ZERO; rows * cols],
+        }
+    }
+
+    /// A zero matrix that may be computed into, if it does not have more
+    /// elements than the `elements` setting allows.
+    fn result(rows: usize, cols: usize, env: &Environment) -> Result<Self, ComputeError> {
+        check_size(rows, cols, &env.settings)?;
+        Ok(Self::zero(rows, cols))
+    }
+
+    /// Fails if the matrix has more elements than the `elements` setting
+    /// allows, for matrices read from a file.
+    pub fn check(&self, settings: &Settings) -> Result<(), ComputeError> {
+        check_size(self.rows, self.cols, settings)
+    }
+
+    pub fn identity(n: usize, env: &Environment) -> Result<Self, ComputeError> {
+        let mut matrix = Self::result(n, n, env)?;
+        for i in 0..n {
+            matrix[(i, i)] = Decimal::ONE;
+        }
+        Ok(matrix)
+    }
+
+    pub fn scalar(value: Decimal) -> Self {
+        Self {
+            rows: 1,
+            cols: 1,
+            data: vec![value],
+        }
+    }
+
+    pub fn row(data: Vec<Decimal>) -> Self {
+        Self {
+            rows: 1,
+            cols: data.len(),
+            data,
+        }
+    }
+
+    /// Stacks row vectors on top of each other.
+    pub fn from_rows(rows: Vec<Matrix>) -> Result<Self, ComputeError> {
+        let cols = rows.first().map_or(0, |row| row.cols);
+        if rows.iter().any(|row| row.rows != 1 || row.cols != cols) {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        Ok(Self {
+            rows: rows.len(),
+            cols,
+            data: rows.into_iter().flat_map(|row| row.data).collect(),
+        })
+    }
+
+    /// Elements of a row or column vector.
+    pub fn vector(&self) -> Option<&[Decimal]> {
+        (self.rows == 1 || self.cols == 1).then_some(self.data.as_slice())
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Decimal]> {
+        self.data.chunks(self.cols)
+    }
+
+    pub fn is_square(&self) -> bool {
+        self.rows == self.cols
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut result = Self::zero(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                result[(j, i)] = self[(i, j)];
+            }
+        }
+        result
+    }
+
+    /// Applies `f` to every element.
+    pub fn map(
+        &self,
+        f: impl Fn(Decimal) -> Result<Decimal, ComputeError>,
+    ) -> Result<Self, ComputeError> {
+        Ok(Self {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().map(|&x| f(x)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn round_dp_with_strategy(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+        Self {
+            rows: self.rows,
+            cols: self.cols,
+            data: self
+                .data
+                .iter()
+                .map(|x| x.round_dp_with_strategy(dp, strategy))
+                .collect(),
+        }
+    }
+
+    /// Combines the elements of two matrices of the same shape with `f`.
+    pub fn zip(
+        &self,
+        other: &Matrix,
+        f: impl Fn(Decimal, Decimal) -> Result<Decimal, ComputeError>,
+    ) -> Result<Self, ComputeError> {
+        if self.rows != other.rows || self.cols != other.cols {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        Ok(Self {
+            rows: self.rows,
+            cols: self.cols,
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(&x, &y)| f(x, y))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn checked_mul(&self, other: &Matrix, env: &Environment) -> Result<Self, ComputeError> {
+        if self.cols != other.rows {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        let mut result = Self::result(self.rows, other.cols, env)?;
+        for i in 0..self.rows {
+            env.check()?;
+            for j in 0..other.cols {
+                let mut sum = Decimal::ZERO;
+                for k in 0..self.cols {
+                    sum = self[(i, k)]
+                        .checked_mul(other[(k, j)])
+                        .and_then(|x| sum.checked_add(x))
+                        .ok_or(ComputeError::Overflow)?;
+                }
+                result[(i, j)] = sum;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn checked_pow(&self, exponent: Decimal, env: &Environment) -> Result<Self, ComputeError> {
+        if !self.is_square() {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        if !exponent.is_integer() {
+            return Err(ComputeError::MustBeInt);
+        }
+        let mut base = if exponent.is_sign_negative() {
+            self.inverse(env)?
+        } else {
+            self.clone()
+        };
+        let mut exponent = exponent.abs().to_u64().ok_or(ComputeError::Overflow)?;
+        let mut result = Self::identity(self.rows, env)?;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.checked_mul(&base, env)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.checked_mul(&base, env)?;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn trace(&self) -> Result<Decimal, ComputeError> {
+        if !self.is_square() {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        (0..self.rows).try_fold(Decimal::ZERO, |sum, i| {
+            sum.checked_add(self[(i, i)]).ok_or(ComputeError::Overflow)
+        })
+    }
+
+    pub fn det(&self, env: &Environment) -> Result<Decimal, ComputeError> {
+        if !self.is_square() {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        let mut reduced = self.clone();
+        let (swaps, rank) = reduced.row_echelon(self.cols, env)?;
+        if rank < self.rows {
+            return Ok(Decimal::ZERO);
+        }
+        let det = (0..self.rows).try_fold(Decimal::ONE, |det, i| {
+            det.checked_mul(reduced[(i, i)])
+                .ok_or(ComputeError::Overflow)
+        })?;
+        Ok(if swaps % 2 == 1 { -det } else { det })
+    }
+
+    pub fn rank(&self, env: &Environment) -> Result<usize, ComputeError> {
+        let mut reduced = self.clone();
+        Ok(reduced.row_echelon(self.cols, env)?.1)
+    }
+
+    pub fn inverse(&self, env: &Environment) -> Result<Self, ComputeError> {
+        if !self.is_square() {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        self.solve(&Self::identity(self.rows, env)?, env)
+    }
+
+    /// Solves `self * x = rhs` for `x`. A row vector on the right hand side
+    /// is treated as a column vector.
+    pub fn solve(&self, rhs: &Matrix, env: &Environment) -> Result<Self, ComputeError> {
+        if !self.is_square() {
+            return Err(ComputeError::DimensionMismatch);
+        }
+        let rhs = if rhs.rows == 1 && rhs.cols == self.rows && self.rows != 1 {
+            rhs.transpose()
+        } else {
+            rhs.clone()
+        };
+        if rhs.rows != self.rows {
+            return Err(ComputeError::DimensionMismatch);
+        }
+
+        let n = self.rows;
+        let mut augmented = Self::zero(n, n + rhs.cols);
+        for i in 0..n {
+            for j in 0..n {
+                augmented[(i, j)] = self[(i, j)];
+            }
+            for j in 0..rhs.cols {
+                augmented[(i, n + j)] = rhs[(i, j)];
+            }
+        }
+        if augmented.row_echelon(n, env)?.1 < n {
+            return Err(ComputeError::SingularMatrix);
+        }
+
+        let mut result = Self::result(n, rhs.cols, env)?;
+        for j in 0..rhs.cols {
+            env.check()?;
+            for i in (0..n).rev() {
+                let mut sum = augmented[(i, n + j)];
+                for k in i + 1..n {
+                    sum = augmented[(i, k)]
+                        .checked_mul(result[(k, j)])
+                        .and_then(|x| sum.checked_sub(x))
+                        .ok_or(ComputeError::Overflow)?;
+                }
+                result[(i, j)] = sum
+                    .checked_div(augmented[(i, i)])
+                    .ok_or(ComputeError::Overflow)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Gaussian elimination with partial pivoting over the first `cols`
+    /// columns. Returns the number of row swaps and the rank found.
+    fn row_echelon(
+        &mut self,
+        cols: usize,
+        env: &Environment,
+    ) -> Result<(usize, usize), ComputeError> {
+        let mut swaps = 0;
+        let mut rank = 0;
+        for col in 0..cols {
+            env.check()?;
+            if rank == self.rows {
+                break;
+            }
+            let pivot = (rank..self.rows)
+                .max_by_key(|&i| self[(i, col)].abs())
+                .unwrap_or(rank);
+            if self[(pivot, col)].abs() <= EPSILON {
+                continue;
+            }
+            if pivot != rank {
+                for j in 0..self.cols {
+                    self.data.swap(pivot * self.cols + j, rank * self.cols + j);
+                }
+                swaps += 1;
+            }
+            for i in rank + 1..self.rows {
+                let factor = self[(i, col)]
+                    .checked_div(self[(rank, col)])
+                    .ok_or(ComputeError::Overflow)?;
+                self[(i, col)] = Decimal::ZERO;
+                for j in col + 1..self.cols {
+                    self[(i, j)] = factor
+                        .checked_mul(self[(rank, j)])
+                        .and_then(|x| self[(i, j)].checked_sub(x))
+                        .ok_or(ComputeError::Overflow)?;
+                }
+            }
+            rank += 1;
+        }
+        Ok((swaps, rank))
+    }
+}
+
+impl std::ops::Index<(usize, usize)> for Matrix {
+    type Output = Decimal;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Decimal {
+        &self.data[i * self.cols + j]
+    }
+}
+
+impl std::ops::IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Decimal {
+        &mut self.data[i * self.cols + j]
+    }
+}
+
+impl Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells: Vec<String> = self.data.iter().map(|x| x.to_string()).collect();
+        let widths: Vec<usize> = (0..self.cols)
+            .map(|j| {
+                (0..self.rows)
+                    .map(|i| cells[i * self.cols + j].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for i in 0..self.rows {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "[")?;
+            for j in 0..self.cols {
+                write!(
+                    f,
+                    " {:>width$}",
+                    cells[i * self.cols + j],
+                    width = widths[j]
+                )?;
+            }
+            write!(f, " ]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: &[&[i64]]) -> Matrix {
+        let data = rows
+            .iter()
+            .flat_map(|row| row.iter().map(|&x| Decimal::from(x)));
+        Matrix {
+            rows: rows.len(),
+            cols: rows[0].len(),
+            data: data.collect(),
+        }
+    }
+
+    /// Whether `a` is `b` up to the error of elimination.
+    fn close(a: Result<Matrix, ComputeError>, b: &Matrix) -> bool {
+        let difference = a.and_then(|a| a.zip(b, |x, y| Ok(x - y)));
+        difference.is_ok_and(|d| d.data.iter().all(|x| x.abs() < dec!(0.0000000000000000001)))
+    }
+
+    #[test]
+    fn eliminates_with_pivoting() {
+        let env = Environment::default();
+        // The first pivot is zero, elimination has to swap rows
+        let a = matrix(&[&[0, 2, 1], &[1, 1, 0], &[2, 0, 3]]);
+        assert!(matches!(a.det(&env), Ok(x) if x == dec!(-8)));
+        assert!(matches!(a.rank(&env), Ok(3)));
+        let Ok(inverse) = a.inverse(&env) else {
+            panic!("no inverse");
+        };
+        let identity = matrix(&[&[1, 0, 0], &[0, 1, 0], &[0, 0, 1]]);
+        assert!(close(a.checked_mul(&inverse, &env), &identity));
+        let x = a.solve(&matrix(&[&[7, 3, 11]]), &env);
+        assert!(close(x, &matrix(&[&[1], &[2], &[3]])));
+        let squared = inverse.checked_mul(&inverse, &env);
+        assert!(squared.is_ok_and(|squared| close(a.checked_pow(dec!(-2), &env), &squared)));
+    }
+
+    #[test]
+    fn rejects_singular_and_mismatched_matrices() {
+        let env = Environment::default();
+        let singular = matrix(&[&[1, 2], &[2, 4]]);
+        assert!(matches!(singular.det(&env), Ok(x) if x.is_zero()));
+        assert!(matches!(singular.rank(&env), Ok(1)));
+        assert!(matches!(
+            singular.inverse(&env),
+            Err(ComputeError::SingularMatrix)
+        ));
+        let wide = matrix(&[&[1, 2, 3]]);
+        assert!(matches!(
+            wide.det(&env),
+            Err(ComputeError::DimensionMismatch)
+        ));
+        assert!(matches!(
+            wide.checked_mul(&wide, &env),
+            Err(ComputeError::DimensionMismatch)
+        ));
+        assert!(matches!(
+            Matrix::from_rows(vec![wide, matrix(&[&[1]])]),
+            Err(ComputeError::DimensionMismatch)
+        ));
+    }
+
+    #[test]
+    fn stays_within_the_elements_setting() {
+        let mut env = Environment::default();
+        env.settings.max_elements = 4;
+        let column = matrix(&[&[1], &[2], &[3]]);
+        let row = column.transpose();
+        assert!(matches!(row.checked_mul(&column, &env), Ok(m) if m == matrix(&[&[14]])));
+        assert!(matches!(
+            column.checked_mul(&row, &env),
+            Err(ComputeError::TooLarge)
+        ));
+        assert!(matches!(
+            Matrix::identity(3, &env),
+            Err(ComputeError::TooLarge)
+        ));
+    }
+}
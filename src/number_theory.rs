@@ -0,0 +1,274 @@
+use crate::compute::{ComputeError, Environment};
+
+const SMALL_PRIMES: [u128; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn mul_mod(a: u128, b: u128, m: u128) -> u128 {
+    if let Some(product) = a.checked_mul(b) {
+        return product % m;
+    }
+    let (mut a, mut b) = (a % m, b % m);
+    let mut result = 0u128;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = add_mod(result, a, m);
+        }
+        a = add_mod(a, a, m);
+        b >>= 1;
+    }
+    result
+}
+
+fn add_mod(a: u128, b: u128, m: u128) -> u128 {
+    if a >= m - b {
+        a - (m - b)
+    } else {
+        a + b
+    }
+}
+
+/// Computes `base^exponent mod modulus`, `modulus` must not be zero.
+pub fn pow_mod(base: u128, mut exponent: u128, modulus: u128) -> u128 {
+    let mut base = base % modulus;
+    let mut result = 1 % modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Miller-Rabin test using the first twenty primes as witnesses. This is
+/// deterministic below 3.3e24 and has no known counterexample above it.
+pub fn is_prime(n: u128, env: &Environment) -> Result<bool, ComputeError> {
+    if n < 2 {
+        return Ok(false);
+    }
+    for p in SMALL_PRIMES {
+        if n.is_multiple_of(p) {
+            return Ok(n == p);
+        }
+    }
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    'witness: for a in SMALL_PRIMES {
+        env.check()?;
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Smallest prime strictly greater than `n`.
+pub fn next_prime(n: u128, env: &Environment) -> Result<u128, ComputeError> {
+    let mut candidate = n.checked_add(1).ok_or(ComputeError::Overflow)?.max(2);
+    while !is_prime(candidate, env)? {
+        candidate = candidate.checked_add(1).ok_or(ComputeError::Overflow)?;
+    }
+    Ok(candidate)
+}
+
+/// Finds a non trivial divisor of the composite `n` with Pollard's rho.
+fn pollard_rho(n: u128, env: &Environment) -> Result<u128, ComputeError> {
+    if n.is_multiple_of(2) {
+        return Ok(2);
+    }
+    for c in 1.. {
+        let f = |x: u128| add_mod(mul_mod(x, x, n), c, n);
+        let (mut x, mut y, mut d) = (2u128, 2u128, 1u128);
+        while d == 1 {
+            env.check()?;
+            x = f(x);
+            y = f(f(y));
+            d = gcd(x.abs_diff(y), n);
+        }
+        if d != n {
+            return Ok(d);
+        }
+    }
+    unreachable!()
+}
+
+/// Prime factorization of `n` as sorted `(prime, exponent)` pairs.
+pub fn factor(n: u128, env: &Environment) -> Result<Vec<(u128, u32)>, ComputeError> {
+    fn split(n: u128, primes: &mut Vec<u128>, env: &Environment) -> Result<(), ComputeError> {
+        if n == 1 {
+            return Ok(());
+        }
+        if is_prime(n, env)? {
+            primes.push(n);
+            return Ok(());
+        }
+        let d = pollard_rho(n, env)?;
+        split(d, primes, env)?;
+        split(n / d, primes, env)
+    }
+
+    let mut primes = Vec::new();
+    let mut n = n;
+    for p in SMALL_PRIMES {
+        while n.is_multiple_of(p) && n > 1 {
+            primes.push(p);
+            n /= p;
+        }
+    }
+    split(n, &mut primes, env)?;
+    primes.sort_unstable();
+
+    let mut factors: Vec<(u128, u32)> = Vec::new();
+    for p in primes {
+        match factors.last_mut() {
+            Some((last, exponent)) if *last == p => *exponent += 1,
+            _ => factors.push((p, 1)),
+        }
+    }
+    Ok(factors)
+}
+
+/// Euler's totient function.
+pub fn totient(n: u128, env: &Environment) -> Result<u128, ComputeError> {
+    if n == 0 {
+        return Ok(0);
+    }
+    Ok(factor(n, env)?
+        .into_iter()
+        .fold(n, |result, (p, _)| result / p * (p - 1)))
+}
+
+/// Binomial coefficient, `None` on overflow.
+pub fn choose(n: u128, k: u128) -> Option<u128> {
+    if k > n {
+        return Some(0);
+    }
+    let k = k.min(n - k);
+    let mut result = 1u128;
+    for i in 0..k {
+        let g = gcd(result, i + 1);
+        result = (result / g).checked_mul((n - i) / ((i + 1) / g))?;
+    }
+    Some(result)
+}
+
+/// Number of ordered selections of `k` items out of `n`, `None` on overflow.
+pub fn permute(n: u128, k: u128) -> Option<u128> {
+    if k > n {
+        return Some(0);
+    }
+    (n - k + 1..=n).try_fold(1u128, |result, x| result.checked_mul(x))
+}
+
+/// `F(2k)` from `F(k)` and `F(k + 1)`.
+fn fib_even(a: u128, b: u128) -> Option<u128> {
+    a.checked_mul(b.checked_mul(2)?.checked_sub(a)?)
+}
+
+/// `F(2k + 1)` from `F(k)` and `F(k + 1)`.
+fn fib_odd(a: u128, b: u128) -> Option<u128> {
+    a.checked_mul(a)?.checked_add(b.checked_mul(b)?)
+}
+
+/// The `n`th Fibonacci number by fast doubling, `None` on overflow.
+pub fn fib(n: u128) -> Option<u128> {
+    /// `F(n)` and `F(n + 1)`.
+    fn pair(n: u128) -> Option<(u128, u128)> {
+        if n == 0 {
+            return Some((0, 1));
+        }
+        let (a, b) = pair(n / 2)?;
+        let (c, d) = (fib_even(a, b)?, fib_odd(a, b)?);
+        if n.is_multiple_of(2) {
+            Some((c, d))
+        } else {
+            Some((d, c.checked_add(d)?))
+        }
+    }
+    // F(n + 1) is not needed and may overflow when F(n) does not
+    let (a, b) = pair(n / 2)?;
+    match n.is_multiple_of(2) {
+        true => fib_even(a, b),
+        false => fib_odd(a, b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest prime below 2^128.
+    const LARGEST_PRIME: u128 = u128::MAX - 158;
+
+    #[test]
+    fn reduces_near_the_largest_modulus() {
+        assert_eq!(pow_mod(2, 127, u128::MAX), 1 << 127);
+        assert_eq!(pow_mod(u128::MAX - 1, 2, u128::MAX), 1);
+        assert_eq!(pow_mod(3, LARGEST_PRIME - 1, LARGEST_PRIME), 1);
+        assert_eq!(pow_mod(5, 0, 1), 0);
+    }
+
+    #[test]
+    fn tells_primes_from_pseudoprimes() {
+        let env = Environment::default();
+        let prime = |n| matches!(is_prime(n, &env), Ok(true));
+        assert!(prime(LARGEST_PRIME) && prime((1 << 89) - 1) && prime(2));
+        // Carmichael number, strong pseudoprime to the bases 2, 3, 5 and 7,
+        // and a Fermat number
+        assert!(!prime(561) && !prime(3215031751) && !prime((1 << 64) + 1));
+        assert!(matches!(
+            next_prime(LARGEST_PRIME - 1, &env),
+            Ok(LARGEST_PRIME)
+        ));
+    }
+
+    #[test]
+    fn factors_and_counts() {
+        let env = Environment::default();
+        assert!(matches!(
+            factor((1 << 64) + 1, &env),
+            Ok(factors) if factors == [(274177, 1), (67280421310721, 1)]
+        ));
+        assert!(matches!(factor(2592, &env), Ok(factors) if factors == [(2, 5), (3, 4)]));
+        assert!(matches!(totient(36, &env), Ok(12)));
+        assert_eq!(choose(100, 50), Some(100891344545564193334812497256));
+        assert_eq!(choose(200, 100), None);
+        assert_eq!(permute(10, 3), Some(720));
+        assert_eq!(fib(186), Some(332825110087067562321196029789634457848));
+        assert_eq!(fib(187), None);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let env = Environment::default();
+        assert!(matches!(
+            factor(1000003 * 1000033, &env),
+            Ok(factors) if factors == [(1000003, 1), (1000033, 1)]
+        ));
+        // Takes more than a minute unless stopped
+        let n = 281474976710677 * 181474976710681;
+        env.cancel.cancel();
+        assert!(matches!(factor(n, &env), Err(ComputeError::Interrupted)));
+        assert!(matches!(
+            next_prime(n, &env),
+            Err(ComputeError::Interrupted)
+        ));
+    }
+}
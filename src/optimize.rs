@@ -0,0 +1,99 @@
+use crate::ast::{Ast, NodeId, NodeKind, Span};
+use crate::compute::{compute_tree, Environment};
+use crate::tokenizer::Token;
+use crate::value::Value;
+
+/// Whether the subtree at `id` has the same value whatever the variables
+/// are.
+pub fn is_constant(ast: &Ast, id: NodeId) -> bool {
+    match &ast.node(id).kind {
+        NodeKind::Literal(Token::Ans)
+        | NodeKind::Identifier(_)
+        | NodeKind::Call(Token::Call(_) | Token::Diff, _) => false,
+        kind => kind
+            .children()
+            .into_iter()
+            .all(|child| is_constant(ast, child)),
+    }
+}
+
+/// Replaces every constant subtree of `tree` that is a number or a boolean
+/// by its value, so that `x * (pi / 180)` only divides once. A subtree that
+/// can not be evaluated is kept, its error is raised if it is ever reached.
+pub fn fold(tree: &Ast, env: &Environment) -> Ast {
+    let mut folded = Ast::default();
+    fold_node(tree, tree.root(), env, &mut folded);
+    folded
+}
+
+/// Adds the node `id` of `ast` to `folded`, after its folded children.
+fn fold_node(ast: &Ast, id: NodeId, env: &Environment, folded: &mut Ast) -> NodeId {
+    if is_constant(ast, id) {
+        let value = match compute_tree(&ast.subtree(id), &mut env.detached()) {
+            Ok(Value::Number(x)) => Some(Token::Literal(x)),
+            Ok(Value::Bool(true)) => Some(Token::True),
+            Ok(Value::Bool(false)) => Some(Token::False),
+            _ => None,
+        };
+        if let Some(value) = value {
+            return folded.leaf(value, Span::default());
+        }
+    }
+    let node = ast.node(id);
+    let children: Vec<_> = node
+        .kind
+        .children()
+        .into_iter()
+        .map(|child| fold_node(ast, child, env, folded))
+        .collect();
+    folded.push(node.kind.with_children(children), node.span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::execute;
+    use crate::parser::{parse_statements, Statement};
+    use crate::tokenizer::tokenize;
+
+    fn folded(input: &str, env: &Environment) -> String {
+        match parse_statements(tokenize(input.to_string()).unwrap())
+            .unwrap()
+            .pop()
+        {
+            Some(Statement::Expression(tree)) => fold(&tree, env).to_string(),
+            _ => panic!("{input} is not an expression"),
+        }
+    }
+
+    #[test]
+    fn folds_subtrees_that_do_not_depend_on_variables() {
+        let mut env = Environment::default();
+        assert_eq!(
+            folded("x * (pi / 180)", &env),
+            "x * 0.0174532925199432957692369077"
+        );
+        assert_eq!(folded("2 < 3 and x", &env), "true and x");
+        assert_eq!(folded("f(2 + 2)", &env), "f(4)");
+        assert_eq!(folded("[[1 + 1]] * x", &env), "[[2]] * x");
+        // Variables, the previous answer and failing subtrees are kept
+        assert_eq!(folded("ans + y * 2", &env), "ans + y * 2");
+        assert_eq!(folded("1/0 + x", &env), "1 / 0 + x");
+        env.settings.degrees = true;
+        assert_eq!(folded("sin(90) + y", &env), "1 + y");
+    }
+
+    #[test]
+    fn remembers_function_values_only_when_optimizing() {
+        let mut env = Environment::default();
+        let statements =
+            parse_statements(tokenize("sin(1) + sin(1)".to_string()).unwrap()).unwrap();
+        assert!(execute(&statements[0], &mut env).is_ok());
+        assert_eq!(env.cache.len(), 1);
+
+        let mut env = Environment::default();
+        env.settings.optimize = false;
+        assert!(execute(&statements[0], &mut env).is_ok());
+        assert!(env.cache.is_empty());
+    }
+}
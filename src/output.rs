@@ -0,0 +1,81 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::compute::Settings;
+use crate::value::Value;
+
+pub enum OutputError {
+    UnsupportedFormat(String),
+    UnsupportedValue(&'static str),
+    Io(std::io::Error),
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedFormat(extension) => write!(f, "Unsupported file type .{extension}"),
+            Self::UnsupportedValue(format) => write!(f, "Result can not be saved as {format}"),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Whether `path` looks like a file name with an extension rather than the
+/// rest of an expression.
+pub fn is_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains(char::is_whitespace)
+        && !path.starts_with('=')
+        && path.rsplit_once('.').is_some_and(|(name, extension)| {
+            !name.is_empty()
+                && !extension.is_empty()
+                && extension.chars().all(|c| c.is_ascii_alphabetic())
+        })
+}
+
+/// Splits a trailing `> file.ext` redirection off a line of input. A
+/// comparison such as `x > 2.5` has no letters after the last dot and is
+/// left alone, and a `>` in a `#` comment is not a redirection.
+pub fn redirection(input: &str) -> (&str, Option<&str>) {
+    // Only the last line with code on it can end in a redirection
+    let mut offset = 0;
+    let mut last = None;
+    for line in input.split_inclusive('\n') {
+        let code = line.split('#').next().unwrap_or_default();
+        if !code.trim().is_empty() {
+            last = Some((offset, code));
+        }
+        offset += line.len();
+    }
+    let Some((offset, code)) = last else {
+        return (input, None);
+    };
+    let Some(position) = code.rfind('>') else {
+        return (input, None);
+    };
+    let path = code[position + 1..].trim();
+    if is_path(path) {
+        (&input[..offset + position], Some(path))
+    } else {
+        (input, None)
+    }
+}
+
+/// Writes `value` to `path` in the format given by its extension.
+pub fn write(path: &str, value: &Value, settings: &Settings) -> Result<(), OutputError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let contents = match (extension.as_str(), value) {
+        ("svg", Value::Plot(plot)) => plot.svg(settings.width, settings.height),
+        ("svg", _) => return Err(OutputError::UnsupportedValue("SVG")),
+        ("csv", Value::Table(table)) => table.delimited(','),
+        ("tsv", Value::Table(table)) => table.delimited('\t'),
+        ("csv", _) => return Err(OutputError::UnsupportedValue("CSV")),
+        ("tsv", _) => return Err(OutputError::UnsupportedValue("TSV")),
+        _ => return Err(OutputError::UnsupportedFormat(extension)),
+    };
+    std::fs::write(path, contents).map_err(OutputError::Io)
+}
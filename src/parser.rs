@@ -1,24 +1,12 @@
 use std::fmt::Display;
 
+use crate::ast::{Ast, NodeId, NodeKind, Span};
 use crate::tokenizer::Token;
 use rust_decimal_macros::dec;
 
-#[derive(Debug)]
-pub struct ParseTree {
-    pub token: Token,
-    pub left: Option<Box<ParseTree>>,
-    pub right: Option<Box<ParseTree>>,
-}
-
-impl ParseTree {
-    fn new(token: Token) -> Self {
-        Self {
-            token,
-            left: None,
-            right: None,
-        }
-    }
-}
+/// Deepest nesting of parentheses and operations that is parsed. Trees
+/// are walked recursively, deeper ones could overflow the stack.
+const MAX_DEPTH: usize = 1000;
 
 #[derive(Debug)]
 pub enum ParsingError {
@@ -28,9 +16,37 @@ pub enum ParsingError {
     ExpectedExpression,
     ExpectedOperation,
     BlankInput,
+    InvalidAssignment,
+    MissingElse,
+    TooDeep,
     Unknown,
 }
 
+/// Parse error and the span of the input it was found at.
+pub type SpannedError = (ParsingError, Span);
+
+impl ParsingError {
+    fn at(self, span: Span) -> SpannedError {
+        (self, span)
+    }
+
+    /// Stable name of the error for machine readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidParenthesis => "invalid_parenthesis",
+            Self::InvalidComma => "invalid_comma",
+            Self::InvalidArgs => "invalid_args",
+            Self::ExpectedExpression => "expected_expression",
+            Self::ExpectedOperation => "expected_operation",
+            Self::BlankInput => "blank_input",
+            Self::InvalidAssignment => "invalid_assignment",
+            Self::MissingElse => "missing_else",
+            Self::TooDeep => "too_deep",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
 impl Display for ParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -40,6 +56,9 @@ impl Display for ParsingError {
             Self::ExpectedExpression => write!(f, "Expected Expression"),
             Self::ExpectedOperation => write!(f, "Expected Operation"),
             Self::BlankInput => write!(f, "Missing Input"),
+            Self::InvalidAssignment => write!(f, "Invalid Assignment"),
+            Self::MissingElse => write!(f, "Expected `:` after `?`"),
+            Self::TooDeep => write!(f, "Expression is nested too deeply"),
             Self::Unknown => write!(f, "Unknown"),
         }
     }
@@ -47,136 +66,178 @@ impl Display for ParsingError {
 
 #[derive(Debug)]
 enum Expression {
-    Token(Token),
-    Tree(ParseTree),
+    Token(Token, Span),
+    Tree(NodeId),
+    /// Parenthesised arguments separated by commas.
+    Group(Vec<NodeId>, Span),
 }
 
 impl Expression {
-    fn unwrap_token_unchecked(self) -> Token {
+    fn unwrap_token_unchecked(self) -> (Token, Span) {
         match self {
-            Self::Token(x) => x,
+            Self::Token(token, span) => (token, span),
             _ => unreachable!(),
         }
     }
+
+    fn span(&self, ast: &Ast) -> Span {
+        match self {
+            Self::Token(_, span) | Self::Group(_, span) => *span,
+            Self::Tree(id) => ast.node(*id).span,
+        }
+    }
+
+    fn into_tree(self, ast: &mut Ast) -> Result<NodeId, SpannedError> {
+        match self {
+            Self::Token(token, span) if token.is_value() => Ok(ast.leaf(token, span)),
+            Self::Token(_, span) => Err(ParsingError::ExpectedExpression.at(span)),
+            Self::Tree(id) => Ok(id),
+            Self::Group(_, span) => Err(ParsingError::InvalidComma.at(span)),
+        }
+    }
 }
 
-macro_rules! next_expression {
-    ($iter:ident) => {
-        match $iter.next() {
-            Some(Expression::Token(Token::OpenParenthesis)) => {
-                let mut expressions: Vec<Vec<Expression>> = vec![ Vec::new() ];
-                let mut curr: usize = 0;
-                let mut open_count = 1usize;
-                let mut close_count = 0usize;
-                while let Some(next) = $iter.next() {
-                    match next {
-                        Expression::Token(Token::OpenParenthesis) => {
-                            open_count += 1;
-                            expressions[curr].push(next);
-                        }
-                        Expression::Token(Token::Comma) => {
-                            curr += 1;
-                            expressions.push(Vec::new());
-                        }
-                        Expression::Token(Token::CloseParenthesis) => {
-                            close_count += 1;
-                            if close_count == open_count {
-                                break;
-                            }
-                            expressions[curr].push(next);
-                        }
-                        _ => expressions[curr].push(next),
+/// Arguments of the function at `function` together with the span they
+/// were read from.
+fn arguments(
+    expr: Option<Expression>,
+    function: Span,
+    ast: &mut Ast,
+) -> Result<(Vec<NodeId>, Span), SpannedError> {
+    match expr {
+        Some(Expression::Group(args, span)) => Ok((args, span)),
+        Some(expr) => {
+            let span = expr.span(ast);
+            Ok((vec![expr.into_tree(ast)?], span))
+        }
+        None => Err(ParsingError::ExpectedExpression.at(function)),
+    }
+}
+
+/// Consumes expressions up to the `close` token matching the already
+/// consumed opening token at `open`, parsing each comma separated element
+/// on its own. Also gives the span of the closing token.
+fn collect_group(
+    iter: &mut impl Iterator<Item = Expression>,
+    open: Span,
+    close: Token,
+    ast: &mut Ast,
+) -> Result<(Vec<NodeId>, Span), SpannedError> {
+    let mut groups: Vec<Vec<Expression>> = vec![Vec::new()];
+    let mut depth = 0usize;
+    for next in iter.by_ref() {
+        match next {
+            Expression::Token(Token::OpenParenthesis | Token::OpenBracket, _) => {
+                depth += 1;
+                groups.last_mut().unwrap().push(next);
+            }
+            Expression::Token(
+                ref token @ (Token::CloseParenthesis | Token::CloseBracket),
+                span,
+            ) => {
+                if depth == 0 {
+                    if *token != close {
+                        return Err(ParsingError::InvalidParenthesis.at(span));
                     }
+                    let elements = groups
+                        .into_iter()
+                        .map(|group| parse_expressions(group, open.to(span), ast))
+                        .collect::<Result<_, _>>()?;
+                    return Ok((elements, span));
                 }
-                if close_count != open_count {
-                    return Err(ParsingError::InvalidParenthesis);
+                depth -= 1;
+                groups.last_mut().unwrap().push(next);
+            }
+            Expression::Token(Token::Comma, _) if depth == 0 => groups.push(Vec::new()),
+            _ => groups.last_mut().unwrap().push(next),
+        }
+    }
+    Err(ParsingError::InvalidParenthesis.at(open))
+}
+
+macro_rules! next_expression {
+    ($iter:ident, $ast:ident) => {
+        match $iter.next() {
+            Some(Expression::Token(Token::OpenParenthesis, open)) => {
+                let (mut args, close) =
+                    collect_group(&mut $iter, open, Token::CloseParenthesis, $ast)?;
+                if args.len() == 1 {
+                    let arg = args.pop().unwrap();
+                    $ast.extend(arg, open.to(close));
+                    Some(Expression::Tree(arg))
+                } else {
+                    Some(Expression::Group(args, open.to(close)))
                 }
-                expressions.into_iter().map(|expression| parse_expressions(expression).map(|tree| Expression::Tree(tree))).collect::<Result<Vec<_>, _>>()?
             }
-            Some(Expression::Token(Token::CloseParenthesis)) => {
-                return Err(ParsingError::InvalidParenthesis);
+            Some(Expression::Token(Token::OpenBracket, open)) => {
+                let (elements, close) = collect_group(&mut $iter, open, Token::CloseBracket, $ast)?;
+                let matrix = $ast.push(NodeKind::Call(Token::Matrix, elements), open.to(close));
+                Some(Expression::Tree(matrix))
+            }
+            Some(Expression::Token(Token::CloseParenthesis | Token::CloseBracket, span)) => {
+                return Err(ParsingError::InvalidParenthesis.at(span));
             }
-            Some(expr) => vec![expr],
-            None => Vec::new(),
+            expr => expr,
         }
     };
 }
 
-macro_rules! parse_factorial {
-    ($expressions:ident, $buffer:ident) => {
-        loop {
-            let expr = next_expression!($expressions);
-            if expr.len() == 0 {
-                break;
-            } else if expr.len() > 1 {
-                return Err(ParsingError::InvalidComma);
+macro_rules! parse_call {
+    ($expressions:ident, $buffer:ident, $ast:ident) => {
+        while let Some(expr) = next_expression!($expressions, $ast) {
+            match expr {
+                Expression::Token(Token::Identifier(name), span)
+                    if matches!(
+                        $expressions.as_slice().first(),
+                        Some(Expression::Token(Token::OpenParenthesis, _))
+                    ) =>
+                {
+                    let (args, group) =
+                        arguments(next_expression!($expressions, $ast), span, $ast)?;
+                    let call = $ast.push(NodeKind::Call(Token::Call(name), args), span.to(group));
+                    $buffer.push(Expression::Tree(call));
+                }
+                _ => $buffer.push(expr),
             }
-            let expr = unsafe { expr.into_iter().next().unwrap_unchecked() };
+        }
+    };
+}
+
+macro_rules! parse_factorial {
+    ($expressions:ident, $buffer:ident, $ast:ident, $op:ident $(, $other_op:ident)*) => {
+        while let Some(expr) = next_expression!($expressions, $ast) {
             match expr {
-                Expression::Token(Token::Factorial) => {
-                    let arg1 = $buffer.pop().ok_or(ParsingError::ExpectedExpression)?;
-                    $buffer.push(Expression::Tree(ParseTree {
-                        token: Token::Factorial,
-                        left: match arg1 {
-                            Expression::Token(lit) => Some(Box::new(ParseTree::new(lit))),
-                            Expression::Tree(tree) => Some(Box::new(tree)),
-                        },
-                        right: None,
-                    }));
+                Expression::Token(Token::$op $(| Token::$other_op)*, _) => {
+                    let (operation, span) = expr.unwrap_token_unchecked();
+                    let arg1 = $buffer
+                        .pop()
+                        .ok_or(ParsingError::ExpectedExpression.at(span))?
+                        .into_tree($ast)?;
+                    let span = $ast.node(arg1).span.to(span);
+                    $buffer.push(Expression::Tree($ast.push(NodeKind::Unary(operation, arg1), span)));
                 }
-                _ => $buffer.push(expr)
+                _ => $buffer.push(expr),
             }
         }
     };
 }
 
 macro_rules! parse_function {
-    ($expressions:ident, $buffer:ident, $fn:ident $(, $other_fn:ident)*) => {
-        loop {
-            let expr = next_expression!($expressions);
-            if expr.len() == 0 {
-                break;
-            } else if expr.len() > 1 {
-                return Err(ParsingError::InvalidComma);
-            }
-            let expr = unsafe { expr.into_iter().next().unwrap_unchecked() };
+    ($expressions:ident, $buffer:ident, $ast:ident, $fn:ident $(, $other_fn:ident)*) => {
+        while let Some(expr) = next_expression!($expressions, $ast) {
             match expr {
-                Expression::Token(Token::$fn $(| Token::$other_fn)*) => {
-                    let function = expr.unwrap_token_unchecked();
-                    let mut exprs = next_expression!($expressions).into_iter();
-                    let arg1 = match exprs.next() {
-                        Some(Expression::Token(token)) => {
-                            if token.is_value() {
-                                Expression::Token(token)
-                            }
-                            else {
-                                return Err(ParsingError::ExpectedExpression);
-                            }
-                        },
-                        Some(expr) => expr,
-                        None => return Err(ParsingError::ExpectedExpression)
-                    };
-                    let mut arg2: Option<Expression> = None;
-                    if let Token::Log = function {
-                        arg2 = exprs.next();
-                        if exprs.next().is_some() {
-                            return Err(ParsingError::InvalidArgs);
-                        }
-                    }
-                    else if exprs.len() > 1 {
-                        return Err(ParsingError::InvalidArgs);
+                Expression::Token(Token::$fn $(| Token::$other_fn)*, _) => {
+                    let (function, span) = expr.unwrap_token_unchecked();
+                    let (args, group) = arguments(next_expression!($expressions, $ast), span, $ast)?;
+                    let (min, max) = function.arity();
+                    if args.len() < min || args.len() > max {
+                        return Err(ParsingError::InvalidArgs.at(span.to(group)));
                     }
-                    $buffer.push(Expression::Tree(ParseTree {
-                        token: function,
-                        left: match arg1 {
-                            Expression::Token(lit) => Some(Box::new(ParseTree::new(lit))),
-                            Expression::Tree(tree) => Some(Box::new(tree)),
-                        },
-                        right: arg2.map(|expr| match expr {
-                            Expression::Token(lit) => Box::new(ParseTree::new(lit)),
-                            Expression::Tree(tree) => Box::new(tree),
-                        }),
-                    }));
+                    let kind = match (function, args.as_slice()) {
+                        (Token::Not, &[arg]) => NodeKind::Unary(Token::Not, arg),
+                        (function, _) => NodeKind::Call(function, args),
+                    };
+                    $buffer.push(Expression::Tree($ast.push(kind, span.to(group))));
                 },
                 _ => $buffer.push(expr),
             }
@@ -185,63 +246,31 @@ macro_rules! parse_function {
 }
 
 macro_rules! parse_operation {
-    ($expressions:ident, $buffer:ident, $op:ident $(, $other_op:ident)*) => {
-        loop {
-            let expr = next_expression!($expressions);
-            if expr.len() == 0 {
-                break;
-            } else if expr.len() != 1 {
-                return Err(ParsingError::InvalidComma);
-            }
-            let expr = unsafe { expr.into_iter().next().unwrap_unchecked() };
+    ($expressions:ident, $buffer:ident, $ast:ident, $op:ident $(, $other_op:ident)*) => {
+        while let Some(expr) = next_expression!($expressions, $ast) {
             match expr {
-                Expression::Token(Token::$op $(| Token::$other_op)*) => {
-                    let operation = expr.unwrap_token_unchecked();
+                Expression::Token(Token::$op $(| Token::$other_op)*, _) => {
+                    let (operation, span) = expr.unwrap_token_unchecked();
                     let last = match $buffer.pop() {
-                        Some(Expression::Token(token)) => {
-                            if token.is_value() {
-                                Expression::Token(token)
-                            }
-                            else {
-                                return Err(ParsingError::ExpectedExpression);
-                            }
-                        }
-                        Some(expr) => expr,
+                        Some(expr) => expr.into_tree($ast)?,
                         None => {
                             if let Token::Add | Token::Sub = operation {
-                                Expression::Token(Token::Literal(dec!(0)))
+                                let start = Span {
+                                    start: span.start,
+                                    end: span.start,
+                                };
+                                $ast.leaf(Token::Literal(dec!(0)), start)
                             } else {
-                                return Err(ParsingError::ExpectedExpression);
+                                return Err(ParsingError::ExpectedExpression.at(span));
                             }
                         }
                     };
-                    let mut exprs = next_expression!($expressions).into_iter();
-                    let next = match exprs.next() {
-                        Some(Expression::Token(token)) => {
-                            if token.is_value() {
-                                Expression::Token(token)
-                            }
-                            else {
-                                return Err(ParsingError::ExpectedExpression);
-                            }
-                        }
-                        Some(expr) => expr,
-                        None => return Err(ParsingError::ExpectedExpression)
-                    };
-                    if exprs.next().is_some() {
-                        return Err(ParsingError::InvalidComma);
-                    }
-                    $buffer.push(Expression::Tree(ParseTree {
-                        token: operation,
-                        left: match last {
-                            Expression::Token(lit) => Some(Box::new(ParseTree::new(lit))),
-                            Expression::Tree(tree) => Some(Box::new(tree)),
-                        },
-                        right: match next {
-                            Expression::Token(lit) => Some(Box::new(ParseTree::new(lit))),
-                            Expression::Tree(tree) => Some(Box::new(tree)),
-                        },
-                    }));
+                    let next = next_expression!($expressions, $ast)
+                        .ok_or(ParsingError::ExpectedExpression.at(span))?
+                        .into_tree($ast)?;
+                    let span = $ast.node(last).span.to(span).to($ast.node(next).span);
+                    let tree = $ast.push(NodeKind::Binary(operation, last, next), span);
+                    $buffer.push(Expression::Tree(tree));
                 }
                 _ => $buffer.push(expr),
             }
@@ -249,12 +278,38 @@ macro_rules! parse_operation {
     };
 }
 
-fn parse_expressions(expressions: Vec<Expression>) -> Result<ParseTree, ParsingError> {
+/// Parses the expressions read from `source` into a single node.
+fn parse_expressions(
+    expressions: Vec<Expression>,
+    source: Span,
+    ast: &mut Ast,
+) -> Result<NodeId, SpannedError> {
     let mut expressions = expressions.into_iter();
 
-    let mut buffer = Vec::new();
+    let mut buffer: Vec<Expression> = Vec::new();
 
-    parse_factorial!(expressions, buffer);
+    parse_call!(expressions, buffer, ast);
+
+    // `condition ? then : otherwise` binds more loosely than any operation
+    let question = buffer
+        .iter()
+        .position(|expr| matches!(expr, Expression::Token(Token::Question, _)));
+    if let Some(question) = question {
+        return parse_conditional(buffer, question, ast);
+    }
+
+    // A colon outside of a conditional is a division
+    expressions = buffer
+        .into_iter()
+        .map(|expr| match expr {
+            Expression::Token(Token::Colon, span) => Expression::Token(Token::Div, span),
+            expr => expr,
+        })
+        .collect::<Vec<_>>()
+        .into_iter();
+    buffer = Vec::new();
+
+    parse_factorial!(expressions, buffer, ast, Factorial, DoubleFactorial);
 
     expressions = buffer.into_iter();
     buffer = Vec::new();
@@ -262,58 +317,411 @@ fn parse_expressions(expressions: Vec<Expression>) -> Result<ParseTree, ParsingE
     parse_function!(
         expressions,
         buffer,
+        ast,
         Sin,
         Cos,
         Tan,
         Exp,
         Ln,
         Log,
-        Sqrt
+        Sqrt,
+        Transpose,
+        Det,
+        Inv,
+        Rank,
+        Trace,
+        Eye,
+        Solve,
+        Choose,
+        Permute,
+        IsPrime,
+        NextPrime,
+        Factor,
+        Totient,
+        Fib,
+        PowMod,
+        Gamma,
+        LGamma,
+        Beta,
+        If,
+        Diff,
+        Root,
+        Integrate,
+        Sum,
+        Prod,
+        PolyRoots,
+        Plot,
+        Table,
+        Interval
     );
 
     expressions = buffer.into_iter();
     buffer = Vec::new();
 
-    parse_operation!(expressions, buffer, Pow);
+    parse_operation!(expressions, buffer, ast, Pow);
 
     expressions = buffer.into_iter();
     buffer = Vec::new();
 
-    parse_operation!(expressions, buffer, ImplMul);
+    parse_operation!(expressions, buffer, ast, ImplMul);
 
     expressions = buffer.into_iter();
     buffer = Vec::new();
 
-    parse_operation!(expressions, buffer, Mul, Div);
+    parse_operation!(expressions, buffer, ast, Mul, Div);
 
     expressions = buffer.into_iter();
     buffer = Vec::new();
 
-    parse_operation!(expressions, buffer, Add, Sub);
+    parse_operation!(expressions, buffer, ast, Add, Sub, PlusMinus);
 
-    if buffer.len() == 1 {
-        match unsafe { buffer.into_iter().next().unwrap_unchecked() } {
-            Expression::Token(token) => {
-                if token.is_value() {
-                    Ok(ParseTree::new(token))
-                } else {
-                    Err(ParsingError::Unknown)
-                }
+    expressions = buffer.into_iter();
+    buffer = Vec::new();
+
+    parse_operation!(
+        expressions,
+        buffer,
+        ast,
+        Less,
+        LessEqual,
+        Equal,
+        NotEqual,
+        GreaterEqual,
+        Greater
+    );
+
+    expressions = buffer.into_iter();
+    buffer = Vec::new();
+
+    parse_function!(expressions, buffer, ast, Not);
+
+    expressions = buffer.into_iter();
+    buffer = Vec::new();
+
+    parse_operation!(expressions, buffer, ast, And);
+
+    expressions = buffer.into_iter();
+    buffer = Vec::new();
+
+    parse_operation!(expressions, buffer, ast, Or);
+
+    expressions = buffer.into_iter();
+    buffer = Vec::new();
+
+    // Equations are only meaningful as arguments, e.g. solve(x^2 = 2, x)
+    parse_operation!(expressions, buffer, ast, Assign);
+
+    match buffer.len() {
+        0 => Err(ParsingError::BlankInput.at(source)),
+        1 => match unsafe { buffer.into_iter().next().unwrap_unchecked() } {
+            Expression::Token(token, span) if !token.is_value() => {
+                Err(ParsingError::Unknown.at(span))
+            }
+            expr => expr.into_tree(ast),
+        },
+        _ => Err(ParsingError::ExpectedOperation.at(buffer[1].span(ast))),
+    }
+}
+
+/// Parses `expressions`, whose `question`th one is the first `?`, into an
+/// `if` call. The `:` that belongs to it is the first one not taken by a
+/// conditional nested in the middle, so `a ? b : c ? d : e` is
+/// `if(a, b, if(c, d, e))`.
+fn parse_conditional(
+    mut expressions: Vec<Expression>,
+    question: usize,
+    ast: &mut Ast,
+) -> Result<NodeId, SpannedError> {
+    let question_span = expressions[question].span(ast);
+    let mut nested = 0usize;
+    let colon = expressions[question + 1..]
+        .iter()
+        .position(|expr| match expr {
+            Expression::Token(Token::Question, _) => {
+                nested += 1;
+                false
             }
-            Expression::Tree(tree) => Ok(tree),
+            Expression::Token(Token::Colon, _) if nested == 0 => true,
+            Expression::Token(Token::Colon, _) => {
+                nested -= 1;
+                false
+            }
+            _ => false,
+        })
+        .map(|i| question + 1 + i)
+        .ok_or(ParsingError::MissingElse.at(question_span))?;
+    let colon_span = expressions[colon].span(ast);
+    let otherwise = expressions.split_off(colon + 1);
+    expressions.pop();
+    let then = expressions.split_off(question + 1);
+    expressions.pop();
+    let mut args = Vec::with_capacity(3);
+    for (part, around) in [
+        (expressions, question_span),
+        (then, question_span.to(colon_span)),
+        (otherwise, colon_span),
+    ] {
+        let source = match (part.first(), part.last()) {
+            (Some(first), Some(last)) => first.span(ast).to(last.span(ast)),
+            _ => around,
+        };
+        args.push(parse_expressions(part, source, ast)?);
+    }
+    let span = ast.node(args[0]).span.to(ast.node(args[2]).span);
+    Ok(ast.push(NodeKind::Call(Token::If, args), span))
+}
+
+/// Span from the first to the last of `tokens`.
+fn source(tokens: &[(Token, Span)]) -> Span {
+    match (tokens.first(), tokens.last()) {
+        (Some((_, first)), Some((_, last))) => first.to(*last),
+        _ => Span::default(),
+    }
+}
+
+/// Parses tokens read from a source into an expression tree that keeps
+/// their spans, and the span of the first error otherwise.
+fn parse_ast(tokens: Vec<(Token, Span)>) -> Result<Ast, SpannedError> {
+    let (mut nesting, mut conditionals) = (0usize, 0usize);
+    for (token, span) in &tokens {
+        match token {
+            Token::OpenParenthesis | Token::OpenBracket => nesting += 1,
+            Token::CloseParenthesis | Token::CloseBracket => nesting = nesting.saturating_sub(1),
+            // Conditionals are parsed one inside the other
+            Token::Question => conditionals += 1,
+            _ => (),
+        }
+        if nesting > MAX_DEPTH || conditionals > MAX_DEPTH {
+            return Err(ParsingError::TooDeep.at(*span));
         }
-    } else if buffer.len() == 0 {
-        Err(ParsingError::BlankInput)
-    } else {
-        Err(ParsingError::ExpectedOperation)
     }
+    let mut ast = Ast::default();
+    let source = source(&tokens);
+    let expressions = tokens
+        .into_iter()
+        .map(|(token, span)| Expression::Token(token, span))
+        .collect();
+    parse_expressions(expressions, source, &mut ast)?;
+    if ast.depth() > MAX_DEPTH {
+        return Err(ParsingError::TooDeep.at(source));
+    }
+    Ok(ast)
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<ParseTree, ParsingError> {
-    parse_expressions(
-        tokens
-            .into_iter()
-            .map(|token| Expression::Token(token))
-            .collect(),
+/// Tokens without a source, which are given empty spans.
+fn unspanned(tokens: Vec<Token>) -> Vec<(Token, Span)> {
+    tokens
+        .into_iter()
+        .map(|token| (token, Span::default()))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Expression(Ast),
+    /// `name = expression`
+    Assignment(String, Ast),
+    /// `name(parameters) = body`
+    Function(String, Vec<String>, Ast),
+    /// `simplify expression`
+    Simplify(Ast),
+    /// `set name value`
+    Set(String, Ast),
+}
+
+impl Statement {
+    /// The expression the statement evaluates or defines.
+    pub fn ast(&self) -> &Ast {
+        match self {
+            Self::Expression(ast)
+            | Self::Assignment(_, ast)
+            | Self::Function(_, _, ast)
+            | Self::Simplify(ast)
+            | Self::Set(_, ast) => ast,
+        }
+    }
+}
+
+/// Statement read from a source, whose nodes keep where they were read.
+pub struct SpannedStatement {
+    pub statement: Statement,
+    /// Span of the whole statement.
+    pub span: Span,
+}
+
+/// Whether an expression can start with `token`, rather than it continuing
+/// the one before it.
+fn starts_operand(token: &Token) -> bool {
+    !matches!(
+        token,
+        Token::Add
+            | Token::Sub
+            | Token::PlusMinus
+            | Token::Mul
+            | Token::Div
+            | Token::Pow
+            | Token::Less
+            | Token::LessEqual
+            | Token::Equal
+            | Token::NotEqual
+            | Token::GreaterEqual
+            | Token::Greater
+            | Token::And
+            | Token::Or
+            | Token::Factorial
+            | Token::DoubleFactorial
+            | Token::CloseParenthesis
+            | Token::CloseBracket
+            | Token::Comma
+            | Token::Semicolon
+            | Token::Assign
+            | Token::Question
+            | Token::Colon
     )
 }
+
+fn parse_statement(mut tokens: Vec<(Token, Span)>) -> Result<SpannedStatement, SpannedError> {
+    let span = source(&tokens);
+    let spanned = |statement| SpannedStatement { statement, span };
+    // `d/dx expression` is a shorthand for `diff(expression, x)`. Without an
+    // expression after it, as in `d/dt` or `d/dt * 3`, it is a division
+    if let [(Token::Identifier(d), _), (Token::Div, _), (Token::Identifier(dx), dx_span), rest @ ..] =
+        tokens.as_slice()
+    {
+        let operand = match rest {
+            [(Token::ImplMul, _), (token, _), ..] | [(token, _), ..] => starts_operand(token),
+            [] => false,
+        };
+        if d == "d" && dx.len() > 1 && dx.starts_with('d') && operand {
+            // The variable is read from after the `d`
+            let var_span = Span {
+                start: dx_span.start + 1,
+                end: dx_span.end,
+            };
+            let var = Token::Identifier(dx[1..].to_string());
+            let mut rest = tokens.split_off(3);
+            if rest.first().map(|(token, _)| token) == Some(&Token::ImplMul) {
+                rest.remove(0);
+            }
+            let mut ast = parse_ast(rest)?;
+            let expression = ast.root();
+            let var = ast.leaf(var, var_span);
+            ast.push(NodeKind::Call(Token::Diff, vec![expression, var]), span);
+            return Ok(spanned(Statement::Expression(ast)));
+        }
+    }
+    // `set name value` or `set name = value`
+    if let [(Token::Identifier(command), _), (Token::ImplMul, _), (Token::Identifier(name), _), ..] =
+        tokens.as_slice()
+    {
+        if command.eq_ignore_ascii_case("set") {
+            let name = name.to_ascii_lowercase();
+            let mut rest = tokens.split_off(3);
+            if rest.first().map(|(token, _)| token) == Some(&Token::Assign) {
+                rest.remove(0);
+            }
+            return Ok(spanned(Statement::Set(name, parse_ast(rest)?)));
+        }
+    }
+    if let [(Token::Identifier(command), _), ..] = tokens.as_slice() {
+        if command.eq_ignore_ascii_case("simplify") && tokens.len() > 1 {
+            let mut rest = tokens.split_off(1);
+            if rest.first().map(|(token, _)| token) == Some(&Token::ImplMul) {
+                rest.remove(0);
+            }
+            return Ok(spanned(Statement::Simplify(parse_ast(rest)?)));
+        }
+    }
+    let mut depth = 0isize;
+    let assign = tokens.iter().position(|(token, _)| {
+        match token {
+            Token::OpenParenthesis | Token::OpenBracket => depth += 1,
+            Token::CloseParenthesis | Token::CloseBracket => depth -= 1,
+            _ => (),
+        }
+        depth == 0 && *token == Token::Assign
+    });
+    let Some(assign) = assign else {
+        return Ok(spanned(Statement::Expression(parse_ast(tokens)?)));
+    };
+    let body = parse_ast(tokens.split_off(assign + 1))?;
+    tokens.pop();
+    let invalid = ParsingError::InvalidAssignment.at(source(&tokens));
+    let mut target = tokens.into_iter().map(|(token, _)| token);
+    let Some(Token::Identifier(name)) = target.next() else {
+        return Err(invalid);
+    };
+    if target.len() == 0 {
+        return Ok(spanned(Statement::Assignment(name, body)));
+    }
+    if target.next() != Some(Token::OpenParenthesis)
+        || target.next_back() != Some(Token::CloseParenthesis)
+    {
+        return Err(invalid);
+    }
+    let mut params = Vec::new();
+    while let Some(token) = target.next() {
+        match token {
+            Token::Identifier(param) if !params.contains(&param) => params.push(param),
+            _ => return Err(invalid),
+        }
+        match target.next() {
+            Some(Token::Comma) | None => (),
+            _ => return Err(invalid),
+        }
+    }
+    Ok(spanned(Statement::Function(name, params, body)))
+}
+
+/// Parses `;` separated statements read from a source, skipping empty
+/// ones.
+pub fn parse_statements_spanned(
+    tokens: Vec<(Token, Span)>,
+) -> Result<Vec<SpannedStatement>, SpannedError> {
+    tokens
+        .split(|(token, _)| *token == Token::Semicolon)
+        .filter(|tokens| !tokens.is_empty())
+        .map(|tokens| parse_statement(tokens.to_vec()))
+        .collect()
+}
+
+/// Parses `;` separated statements, skipping empty ones.
+pub fn parse_statements(tokens: Vec<Token>) -> Result<Vec<Statement>, ParsingError> {
+    Ok(parse_statements_spanned(unspanned(tokens))
+        .map_err(|(err, _)| err)?
+        .into_iter()
+        .map(|spanned| spanned.statement)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tokenize;
+
+    fn parse(input: &str) -> String {
+        match parse_statements(tokenize(input.to_string()).unwrap()).as_deref() {
+            Ok([Statement::Expression(tree)]) => tree.to_string(),
+            _ => panic!("{input} is not an expression"),
+        }
+    }
+
+    #[test]
+    fn reads_derivatives_only_before_an_expression() {
+        assert_eq!(parse("d/dx x^2"), "diff(x^2, x)");
+        assert_eq!(parse("d/dx(x^3)"), "diff(x^3, x)");
+        assert_eq!(parse("d/dt"), "d / dt");
+        assert_eq!(parse("d/dt * 3"), "d / dt * 3");
+        assert_eq!(parse("d/dt + 1"), "d / dt + 1");
+    }
+
+    #[test]
+    fn reads_conditionals_as_if() {
+        assert_eq!(parse("x > 2 ? 1 : 0"), "if(x > 2, 1, 0)");
+        assert_eq!(parse("a ? b : c ? d : e"), "if(a, b, if(c, d, e))");
+        assert_eq!(parse("a ? b ? c : d : e"), "if(a, if(b, c, d), e)");
+        assert_eq!(parse("(a ? 1 : 2) + 6 : 3"), "if(a, 1, 2) + 6 / 3");
+        let missing = parse_statements(tokenize("a ? b".to_string()).unwrap());
+        assert!(matches!(missing, Err(ParsingError::MissingElse)));
+    }
+}
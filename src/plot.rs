@@ -0,0 +1,474 @@
+use std::fmt::{Display, Write};
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::ComputeError;
+
+/// Size of the terminal chart in characters. Every braille character holds
+/// a 2 by 4 grid of dots.
+const WIDTH: usize = 60;
+const HEIGHT: usize = 16;
+
+/// Number of columns of dots in the terminal chart.
+const COLUMNS: usize = 2 * WIDTH;
+
+/// Bit of the braille pattern for the dot in column `x` and row `y` of a
+/// character.
+const DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Stroke colors of the curves in SVG output, reused when there are more
+/// curves.
+const COLORS: [&str; 6] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b",
+];
+
+/// Space around the plot area of SVG output for labels, in pixels.
+const MARGIN_LEFT: f64 = 64.0;
+const MARGIN_RIGHT: f64 = 16.0;
+const MARGIN_TOP: f64 = 16.0;
+const MARGIN_BOTTOM: f64 = 36.0;
+
+/// A function sampled at evenly spaced points, `None` where it could not be
+/// evaluated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Curve {
+    pub label: String,
+    pub values: Vec<Option<Decimal>>,
+}
+
+/// One or more curves over the same interval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plot {
+    start: Decimal,
+    end: Decimal,
+    curves: Vec<Curve>,
+}
+
+impl Plot {
+    pub fn new(start: Decimal, end: Decimal, curves: Vec<Curve>) -> Self {
+        Self { start, end, curves }
+    }
+
+    /// The `samples` points at which to sample a function over
+    /// `[start, end]`, which must not be wider than the largest number.
+    pub fn abscissae(
+        start: Decimal,
+        end: Decimal,
+        samples: usize,
+    ) -> Result<impl Iterator<Item = Decimal>, ComputeError> {
+        let width = end.checked_sub(start).ok_or(ComputeError::Overflow)?;
+        let step = width / Decimal::from(samples.max(2) - 1);
+        // Only the last point can round past the largest number
+        Ok((0..samples).map(move |i| start.checked_add(step * Decimal::from(i)).unwrap_or(end)))
+    }
+
+    fn defined(&self) -> impl Iterator<Item = f64> + '_ {
+        self.curves
+            .iter()
+            .flat_map(|curve| curve.values.iter().flatten())
+            .filter_map(Decimal::to_f64)
+    }
+
+    fn domain(&self) -> (f64, f64) {
+        (
+            self.start.to_f64().unwrap_or_default(),
+            self.end.to_f64().unwrap_or_default(),
+        )
+    }
+
+    /// Vertical range of the chart. Values far outside the bulk of the
+    /// samples, such as those next to a pole, would flatten everything else
+    /// and are left off the chart.
+    fn range(&self) -> Option<(f64, f64)> {
+        let mut sorted: Vec<_> = self.defined().collect();
+        sorted.sort_by(f64::total_cmp);
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        let low = sorted[sorted.len() / 20];
+        let high = sorted[sorted.len() - 1 - sorted.len() / 20];
+        let spread = high - low;
+        let (bottom, top) = (min.max(low - spread), max.min(high + spread));
+        if bottom == top {
+            Some((bottom - 1.0, top + 1.0))
+        } else {
+            Some((bottom, top))
+        }
+    }
+
+    /// Standalone SVG document of the given size in pixels.
+    pub fn svg(&self, width: usize, height: usize) -> String {
+        let mut svg = String::new();
+        // Writing to a String can not fail
+        let _ = self.write_svg(&mut svg, width as f64, height as f64);
+        svg
+    }
+
+    fn write_svg(&self, svg: &mut String, width: f64, height: f64) -> std::fmt::Result {
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\" font-family=\"sans-serif\" font-size=\"12\">"
+        )?;
+        writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>")?;
+        let (left, top) = (MARGIN_LEFT, MARGIN_TOP);
+        let (right, bottom) = (
+            (width - MARGIN_RIGHT).max(left + 1.0),
+            (height - MARGIN_BOTTOM).max(top + 1.0),
+        );
+        let Some((low, high)) = self.range() else {
+            writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Function is undefined on the whole interval</text>",
+                width / 2.0,
+                height / 2.0
+            )?;
+            return writeln!(svg, "</svg>");
+        };
+        let (start, end) = self.domain();
+        let px = |x: f64| left + (x - start) / (end - start) * (right - left);
+        let py = |y: f64| top + (high - y) / (high - low) * (bottom - top);
+
+        writeln!(
+            svg,
+            "<defs><clipPath id=\"area\"><rect x=\"{left}\" y=\"{top}\" width=\"{}\" height=\"{}\"/></clipPath></defs>",
+            right - left,
+            bottom - top
+        )?;
+
+        // Gridlines with their labels
+        writeln!(svg, "<g stroke=\"#e0e0e0\">")?;
+        let xs = ticks(start.min(end), start.max(end));
+        let ys = ticks(low, high);
+        for x in &xs {
+            let x = px(x.0);
+            writeln!(
+                svg,
+                "<line x1=\"{x:.2}\" y1=\"{top}\" x2=\"{x:.2}\" y2=\"{bottom}\"/>"
+            )?;
+        }
+        for y in &ys {
+            let y = py(y.0);
+            writeln!(
+                svg,
+                "<line x1=\"{left}\" y1=\"{y:.2}\" x2=\"{right}\" y2=\"{y:.2}\"/>"
+            )?;
+        }
+        writeln!(svg, "</g>")?;
+        writeln!(svg, "<g fill=\"#333\">")?;
+        for (x, text) in &xs {
+            writeln!(
+                svg,
+                "<text x=\"{:.2}\" y=\"{}\" text-anchor=\"middle\">{text}</text>",
+                px(*x),
+                bottom + 16.0
+            )?;
+        }
+        for (y, text) in &ys {
+            writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{:.2}\" text-anchor=\"end\" dominant-baseline=\"middle\">{text}</text>",
+                left - 6.0,
+                py(*y)
+            )?;
+        }
+        writeln!(svg, "</g>")?;
+
+        // Axes through the origin when it is in view
+        writeln!(svg, "<g stroke=\"#333\">")?;
+        if (low..=high).contains(&0.0) {
+            let y = py(0.0);
+            writeln!(
+                svg,
+                "<line x1=\"{left}\" y1=\"{y:.2}\" x2=\"{right}\" y2=\"{y:.2}\"/>"
+            )?;
+        }
+        if (start.min(end)..=start.max(end)).contains(&0.0) {
+            let x = px(0.0);
+            writeln!(
+                svg,
+                "<line x1=\"{x:.2}\" y1=\"{top}\" x2=\"{x:.2}\" y2=\"{bottom}\"/>"
+            )?;
+        }
+        writeln!(svg, "</g>")?;
+
+        // Curves, broken where the function is undefined or jumps across
+        // the whole plot as it does at a pole
+        writeln!(
+            svg,
+            "<g clip-path=\"url(#area)\" fill=\"none\" stroke-width=\"2\" stroke-linejoin=\"round\">"
+        )?;
+        let (above, below) = (top - (bottom - top), bottom + (bottom - top));
+        for (curve, color) in self.curves.iter().zip(COLORS.iter().cycle()) {
+            let samples = curve.values.len().max(2) - 1;
+            let mut path = String::new();
+            let mut previous: Option<f64> = None;
+            for (i, value) in curve.values.iter().enumerate() {
+                let Some(y) = value.and_then(|y| y.to_f64()) else {
+                    previous = None;
+                    continue;
+                };
+                let x = px(start + (end - start) * i as f64 / samples as f64);
+                let y = py(y).clamp(above, below);
+                let command = match previous {
+                    Some(p) if (p < top && y > bottom) || (p > bottom && y < top) => 'M',
+                    Some(_) => 'L',
+                    None => 'M',
+                };
+                write!(path, "{command}{x:.2},{y:.2} ")?;
+                previous = Some(y);
+            }
+            writeln!(svg, "<path stroke=\"{color}\" d=\"{}\"/>", path.trim_end())?;
+        }
+        writeln!(svg, "</g>")?;
+        writeln!(
+            svg,
+            "<rect x=\"{left}\" y=\"{top}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#999\"/>",
+            right - left,
+            bottom - top
+        )?;
+
+        // Legend in the top right corner
+        let legend_width = self
+            .curves
+            .iter()
+            .map(|curve| curve.label.chars().count())
+            .max()
+            .unwrap_or_default() as f64
+            * 7.0
+            + 36.0;
+        let x = right - legend_width - 8.0;
+        writeln!(
+            svg,
+            "<rect x=\"{x:.2}\" y=\"{}\" width=\"{legend_width:.2}\" height=\"{}\" fill=\"white\" fill-opacity=\"0.85\" stroke=\"#ccc\"/>",
+            top + 8.0,
+            self.curves.len() as f64 * 18.0 + 8.0
+        )?;
+        for (i, (curve, color)) in self.curves.iter().zip(COLORS.iter().cycle()).enumerate() {
+            let y = top + 8.0 + 13.0 + i as f64 * 18.0;
+            writeln!(
+                svg,
+                "<line x1=\"{:.2}\" y1=\"{y}\" x2=\"{:.2}\" y2=\"{y}\" stroke=\"{color}\" stroke-width=\"2\"/>",
+                x + 6.0,
+                x + 24.0
+            )?;
+            writeln!(
+                svg,
+                "<text x=\"{:.2}\" y=\"{y}\" dominant-baseline=\"middle\">{}</text>",
+                x + 30.0,
+                escape(&curve.label)
+            )?;
+        }
+        writeln!(svg, "</svg>")
+    }
+}
+
+/// Evenly spaced round values covering `[low, high]` with their labels, at
+/// steps of 1, 2 or 5 times a power of ten.
+fn ticks(low: f64, high: f64) -> Vec<(f64, String)> {
+    let raw = (high - low) / 8.0;
+    if !raw.is_finite() || raw <= 0.0 {
+        return Vec::new();
+    }
+    let power = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * power)
+        .find(|&step| step >= raw)
+        .unwrap_or(10.0 * power);
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let first = (low / step).ceil() as i64;
+    let last = (high / step).floor() as i64;
+    (first..=last)
+        .map(|i| {
+            let x = i as f64 * step;
+            (x, format!("{:.*}", decimals, if i == 0 { 0.0 } else { x }))
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn label(x: f64) -> String {
+    let x = Decimal::from_f64(x).unwrap_or_default();
+    x.round_dp(4).normalize().to_string()
+}
+
+impl Display for Plot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((bottom, top)) = self.range() else {
+            return write!(f, "Function is undefined on the whole interval");
+        };
+        let rows = 4 * HEIGHT;
+        let row = |y: f64| {
+            let position = ((top - y) / (top - bottom) * (rows - 1) as f64).round();
+            (0.0..=(rows - 1) as f64)
+                .contains(&position)
+                .then_some(position as usize)
+        };
+        let mut grid = vec![[0u8; WIDTH]; HEIGHT];
+        let mut set = |x: usize, y: usize| grid[y / 4][x / 2] |= DOTS[x % 2][y % 4];
+
+        // Dotted axes through the origin
+        if let Some(y) = row(0.0) {
+            (0..COLUMNS).step_by(2).for_each(|x| set(x, y));
+        }
+        let (start, end) = self.domain();
+        if start.min(end) <= 0.0 && 0.0 <= start.max(end) && start != end {
+            let x = (-start / (end - start) * (COLUMNS - 1) as f64).round() as usize;
+            (0..rows).step_by(2).for_each(|y| set(x, y));
+        }
+
+        // Every column of dots shows the nearest sample. Consecutive ones are
+        // joined by a vertical run of dots so that steep parts stay
+        // connected, undefined points leave a gap.
+        for curve in &self.curves {
+            let samples = curve.values.len();
+            let mut previous = None;
+            for x in 0..COLUMNS {
+                let i = (x * samples.saturating_sub(1) + (COLUMNS - 1) / 2) / (COLUMNS - 1);
+                let current = curve
+                    .values
+                    .get(i)
+                    .copied()
+                    .flatten()
+                    .and_then(|y| y.to_f64())
+                    .and_then(row);
+                if let Some(y) = current {
+                    let (from, to) = match previous {
+                        Some(p) if p < y => (p + 1, y),
+                        Some(p) if p > y => (y, p - 1),
+                        _ => (y, y),
+                    };
+                    (from..=to).for_each(|y| set(x, y));
+                }
+                previous = current;
+            }
+        }
+
+        let (top_label, bottom_label) = (label(top), label(bottom));
+        let margin = top_label.len().max(bottom_label.len());
+        for (i, line) in grid.iter().enumerate() {
+            let (text, tick) = match i {
+                0 => (top_label.as_str(), '┤'),
+                i if i == HEIGHT - 1 => (bottom_label.as_str(), '┤'),
+                _ => ("", '│'),
+            };
+            let dots: String = line
+                .iter()
+                .map(|&bits| char::from_u32(0x2800 + bits as u32).unwrap_or(' '))
+                .collect();
+            writeln!(f, "{text:>margin$} {tick}{dots}")?;
+        }
+        writeln!(f, "{:margin$} └{}", "", "─".repeat(WIDTH))?;
+        let (left, right) = (label(start), label(end));
+        let gap = (WIDTH + 1).saturating_sub(left.len());
+        writeln!(f, "{:margin$} {left}{right:>gap$}", "")?;
+
+        let (min, max) = self
+            .defined()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), y| {
+                (min.min(y), max.max(y))
+            });
+        write!(f, "min = {}, max = {}", label(min), label(max))?;
+        let total: usize = self.curves.iter().map(|curve| curve.values.len()).sum();
+        let gaps = self
+            .curves
+            .iter()
+            .flat_map(|curve| &curve.values)
+            .filter(|y| y.is_none())
+            .count();
+        if gaps > 0 {
+            write!(f, ", undefined at {gaps} of {total} points")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampled(
+        start: i64,
+        end: i64,
+        samples: usize,
+        f: impl Fn(Decimal) -> Option<Decimal>,
+    ) -> Plot {
+        let (start, end) = (Decimal::from(start), Decimal::from(end));
+        let Ok(abscissae) = Plot::abscissae(start, end, samples) else {
+            panic!("interval too wide");
+        };
+        let values = abscissae.map(f).collect();
+        let curve = Curve {
+            label: "f".to_string(),
+            values,
+        };
+        Plot::new(start, end, vec![curve])
+    }
+
+    #[test]
+    fn charts_around_a_pole() {
+        let plot = sampled(-1, 1, 201, |x| Decimal::ONE.checked_div(x));
+        let chart = plot.to_string();
+        let lines: Vec<_> = chart.lines().collect();
+        assert_eq!(lines.len(), HEIGHT + 3);
+        for line in &lines[..HEIGHT] {
+            let dots = line
+                .chars()
+                .filter(|c| ('\u{2800}'..='\u{28ff}').contains(c));
+            assert_eq!(dots.count(), WIDTH);
+        }
+        // The samples next to the pole are left off the chart
+        assert!(lines[0].trim_start().starts_with("27.2727 ┤"));
+        assert!(lines[HEIGHT - 1].trim_start().starts_with("-27.2727 ┤"));
+        assert_eq!(
+            lines[HEIGHT + 2],
+            "min = -100, max = 100, undefined at 1 of 201 points"
+        );
+    }
+
+    #[test]
+    fn draws_svg_with_a_path_per_curve() {
+        let mut plot = sampled(-1, 1, 200, |x| Decimal::ONE.checked_div(x));
+        plot.curves.push(Curve {
+            label: "x < 1 & y > 0".to_string(),
+            values: vec![Some(Decimal::ZERO); 200],
+        });
+        let svg = plot.svg(640, 400);
+        assert!(svg
+            .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"640\" height=\"400\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        let paths: Vec<_> = svg
+            .lines()
+            .filter(|line| line.starts_with("<path"))
+            .collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].contains(COLORS[0]) && paths[1].contains(COLORS[1]));
+        // The jump across the pole starts a new piece of the path
+        assert_eq!(paths[0].matches('M').count(), 2);
+        assert_eq!(paths[1].matches('M').count(), 1);
+        assert!(svg.contains(">x &lt; 1 &amp; y &gt; 0</text>"));
+    }
+
+    #[test]
+    fn charts_constant_and_undefined_functions() {
+        let chart = sampled(0, 1, 11, |_| Some(Decimal::TWO)).to_string();
+        assert!(chart
+            .lines()
+            .next()
+            .unwrap()
+            .trim_start()
+            .starts_with("3 ┤"));
+        assert!(chart.ends_with("min = 2, max = 2"));
+
+        let plot = sampled(-2, -1, 11, |_| None);
+        assert_eq!(
+            plot.to_string(),
+            "Function is undefined on the whole interval"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::complex::Complex;
+use crate::compute::{ComputeError, Settings};
+
+/// Parts of a root smaller than this relative to the root are rounding
+/// noise, so roots of real polynomials come out real or purely imaginary.
+const EPSILON: Decimal = dec!(0.00000000000000000001);
+
+/// Relative size of the residual at which an iterate counts as a root even
+/// when the steps shrink slowly, as they do around multiple roots.
+const RESIDUAL: Decimal = dec!(0.0000000000000000000000001);
+
+/// Relative distance within which iterates may approximate one multiple
+/// root.
+const CLUSTER: Decimal = dec!(0.001);
+
+/// Primitive cube root of unity, (-1 + i√3) / 2.
+const OMEGA: Complex = Complex::new(dec!(-0.5), dec!(0.8660254037844386467637231707));
+
+fn checked<T>(x: Option<T>) -> Result<T, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+fn c(x: Decimal) -> Complex {
+    Complex::real(x)
+}
+
+/// Value and derivative of the monic polynomial with the given lower
+/// coefficients, highest degree first, by Horner's scheme.
+fn evaluate(coefficients: &[Complex], z: Complex) -> Option<(Complex, Complex)> {
+    let mut value = Complex::ONE;
+    let mut derivative = Complex::ZERO;
+    for &a in coefficients {
+        derivative = derivative.checked_mul(z)?.checked_add(value)?;
+        value = value.checked_mul(z)?.checked_add(a)?;
+    }
+    Some((value, derivative))
+}
+
+/// Sum of |a_i| |z|^i, the scale against which the residual is compared.
+fn bound(coefficients: &[Complex], z: Complex) -> Option<Decimal> {
+    let r = z.magnitude();
+    coefficients.iter().try_fold(Decimal::ONE, |sum, a| {
+        sum.checked_mul(r)?.checked_add(a.magnitude())
+    })
+}
+
+/// Roots of z^2 + b z + c, avoiding the cancellation of the textbook formula.
+fn quadratic(b: Complex, c: Complex) -> Option<Vec<Complex>> {
+    let discriminant = b.checked_mul(b)?.checked_sub(c.checked_scale(dec!(4))?)?;
+    let root = discriminant.checked_sqrt()?;
+    let plus = b.checked_add(root)?;
+    let minus = b.checked_sub(root)?;
+    let larger = if plus.magnitude() >= minus.magnitude() {
+        plus
+    } else {
+        minus
+    };
+    let q = larger.checked_scale(dec!(-0.5))?;
+    if q.is_zero() {
+        return Some(vec![Complex::ZERO, Complex::ZERO]);
+    }
+    Some(vec![q, c.checked_div(q)?])
+}
+
+/// Roots of z^3 + a z^2 + b z + c by Cardano's formula.
+fn cubic(a: Complex, b: Complex, c: Complex) -> Option<Vec<Complex>> {
+    let three = Complex::real(Decimal::from(3));
+    let shift = a.checked_div(three)?;
+    // Depressed cubic t^3 + p t + q with z = t - a / 3
+    let p = b.checked_sub(a.checked_mul(shift)?)?;
+    let q = shift
+        .checked_mul(shift)?
+        .checked_mul(shift)?
+        .checked_scale(Decimal::TWO)?
+        .checked_sub(b.checked_mul(shift)?)?
+        .checked_add(c)?;
+    let half_q = q.checked_scale(dec!(-0.5))?;
+    let third_p = p.checked_div(three)?;
+    let discriminant = half_q
+        .checked_mul(half_q)?
+        .checked_add(third_p.checked_mul(third_p)?.checked_mul(third_p)?)?;
+    let root = discriminant.checked_sqrt()?;
+    let plus = half_q.checked_add(root)?;
+    let minus = half_q.checked_sub(root)?;
+    let u = if plus.magnitude() >= minus.magnitude() {
+        plus
+    } else {
+        minus
+    }
+    .checked_cbrt()?;
+    let v = if u.is_zero() {
+        Complex::ZERO
+    } else {
+        third_p.checked_div(u)?.checked_scale(-Decimal::ONE)?
+    };
+    let omega_squared = OMEGA.conj();
+    [
+        (Complex::ONE, Complex::ONE),
+        (OMEGA, omega_squared),
+        (omega_squared, OMEGA),
+    ]
+    .into_iter()
+    .map(|(x, y)| {
+        u.checked_mul(x)?
+            .checked_add(v.checked_mul(y)?)?
+            .checked_sub(shift)
+    })
+    .collect()
+}
+
+/// Roots of z^4 + a z^3 + b z^2 + c z + d by Ferrari's method.
+fn quartic(a: Complex, b: Complex, c: Complex, d: Complex) -> Option<Vec<Complex>> {
+    let shift = a.checked_scale(dec!(0.25))?;
+    let shift_squared = shift.checked_mul(shift)?;
+    // Depressed quartic y^4 + p y^2 + q y + r with z = y - a / 4
+    let p = b.checked_sub(shift_squared.checked_scale(dec!(6))?)?;
+    let q = shift_squared
+        .checked_mul(shift)?
+        .checked_scale(dec!(8))?
+        .checked_sub(b.checked_mul(shift)?.checked_scale(Decimal::TWO)?)?
+        .checked_add(c)?;
+    let r = shift_squared
+        .checked_mul(shift_squared)?
+        .checked_scale(dec!(-3))?
+        .checked_add(b.checked_mul(shift_squared)?)?
+        .checked_sub(c.checked_mul(shift)?)?
+        .checked_add(d)?;
+
+    let ys = if q.is_zero() {
+        // Biquadratic, a quadratic in y^2
+        let mut ys = Vec::new();
+        for z in quadratic(p, r)? {
+            let y = z.checked_sqrt()?;
+            ys.push(y);
+            ys.push(y.checked_scale(-Decimal::ONE)?);
+        }
+        ys
+    } else {
+        // y^4 + p y^2 + q y + r = (y^2 + p / 2 + m)^2 - (s y - q / 2s)^2
+        // with s^2 = 2m for a root m of the resolvent cubic
+        let half_p = p.checked_scale(dec!(0.5))?;
+        let m = cubic(
+            p,
+            half_p.checked_mul(half_p)?.checked_sub(r)?,
+            q.checked_mul(q)?.checked_scale(dec!(-0.125))?,
+        )?
+        .into_iter()
+        .max_by_key(Complex::magnitude)?;
+        let s = m.checked_scale(Decimal::TWO)?.checked_sqrt()?;
+        let offset = half_p.checked_add(m)?;
+        let t = q.checked_div(s.checked_scale(Decimal::TWO)?)?;
+        let mut ys = quadratic(s.checked_scale(-Decimal::ONE)?, offset.checked_add(t)?)?;
+        ys.extend(quadratic(s, offset.checked_sub(t)?)?);
+        ys
+    };
+    ys.into_iter().map(|y| y.checked_sub(shift)).collect()
+}
+
+/// Durand-Kerner iteration, refining all roots simultaneously.
+fn durand_kerner(
+    coefficients: &[Complex],
+    settings: &Settings,
+) -> Result<Vec<Complex>, ComputeError> {
+    let seed = Complex::new(dec!(0.4), dec!(0.9));
+    let mut roots = vec![Complex::ONE];
+    for _ in 1..coefficients.len() {
+        let last = roots[roots.len() - 1];
+        roots.push(checked(last.checked_mul(seed))?);
+    }
+    for _ in 0..settings.max_iterations {
+        let mut converged = true;
+        for k in 0..roots.len() {
+            let z = roots[k];
+            let (value, _) = checked(evaluate(coefficients, z))?;
+            let denominator = roots
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != k)
+                .try_fold(Complex::ONE, |product, (_, &other)| {
+                    product.checked_mul(z.checked_sub(other)?)
+                });
+            let step = match denominator.and_then(|d| value.checked_div(d)) {
+                Some(step) => step,
+                // Two estimates coincide, nudge one of them apart
+                None => {
+                    roots[k] = checked(z.checked_add(Complex::new(EPSILON, EPSILON)))?;
+                    converged = false;
+                    continue;
+                }
+            };
+            roots[k] = checked(z.checked_sub(step))?;
+            let scale = roots[k].magnitude().max(Decimal::ONE);
+            let small_step = step.magnitude() <= settings.tolerance * scale;
+            let small_value =
+                value.magnitude() <= RESIDUAL * checked(bound(coefficients, z))?.max(Decimal::ONE);
+            converged &= small_step || small_value;
+        }
+        if converged {
+            return Ok(roots);
+        }
+    }
+    Err(ComputeError::NoConvergence)
+}
+
+/// Coefficients of the Taylor expansion around `c` up to `count` terms,
+/// p(c), p'(c), p''(c) / 2 and so on, by repeated synthetic division.
+fn taylor(coefficients: &[Complex], c: Complex, count: usize) -> Option<Vec<Complex>> {
+    let mut quotient: Vec<_> = std::iter::once(Complex::ONE)
+        .chain(coefficients.iter().copied())
+        .collect();
+    let mut terms = Vec::new();
+    for _ in 0..count {
+        for i in 1..quotient.len() {
+            quotient[i] = quotient[i].checked_add(quotient[i - 1].checked_mul(c)?)?;
+        }
+        terms.push(quotient.pop()?);
+    }
+    Some(terms)
+}
+
+/// Iterations converge only to within about the n-th root of the precision
+/// of an n-fold root, leaving a small cluster around it. The mean of such a
+/// cluster is far more accurate, so clusters at which the leading Taylor
+/// coefficients vanish are replaced by their mean.
+fn merge_clusters(coefficients: &[Complex], roots: &mut [Complex]) -> Option<()> {
+    let mut merged = vec![false; roots.len()];
+    for i in 0..roots.len() {
+        if merged[i] {
+            continue;
+        }
+        let radius = CLUSTER * roots[i].magnitude().max(Decimal::ONE);
+        let cluster: Vec<_> = (i..roots.len())
+            .filter(|&j| {
+                !merged[j]
+                    && roots[j]
+                        .checked_sub(roots[i])
+                        .is_some_and(|d| d.magnitude() <= radius)
+            })
+            .collect();
+        if cluster.len() < 2 {
+            continue;
+        }
+        let sum = cluster
+            .iter()
+            .try_fold(Complex::ZERO, |sum, &j| sum.checked_add(roots[j]))?;
+        let count = cluster.len();
+        let mut mean = sum.checked_div(Complex::real(Decimal::from(count)))?;
+        // The k-fold root is a simple root of the (k - 1)-th derivative
+        for _ in 0..10 {
+            let terms = taylor(coefficients, mean, count + 1)?;
+            let Some(step) =
+                terms[count - 1].checked_div(terms[count].checked_scale(Decimal::from(count))?)
+            else {
+                break;
+            };
+            mean = mean.checked_sub(step)?;
+            if step.magnitude() <= EPSILON * mean.magnitude() {
+                break;
+            }
+        }
+        let scale = bound(coefficients, mean)?;
+        let loose = RESIDUAL.sqrt()? * scale;
+        let terms = taylor(coefficients, mean, count)?;
+        let multiple = terms[0].magnitude() <= RESIDUAL * scale
+            && terms[1..].iter().all(|term| term.magnitude() <= loose);
+        if multiple {
+            for j in cluster {
+                roots[j] = mean;
+                merged[j] = true;
+            }
+        }
+    }
+    Some(())
+}
+
+/// A few Newton steps on the full polynomial, each kept only if it lowers
+/// the residual.
+fn polish(coefficients: &[Complex], mut z: Complex) -> Complex {
+    for _ in 0..10 {
+        let Some((value, derivative)) = evaluate(coefficients, z) else {
+            break;
+        };
+        let Some(next) = value
+            .checked_div(derivative)
+            .and_then(|step| z.checked_sub(step))
+        else {
+            break;
+        };
+        match evaluate(coefficients, next) {
+            Some((next_value, _)) if next_value.magnitude() < value.magnitude() => z = next,
+            _ => break,
+        }
+    }
+    z
+}
+
+/// Number of digits before the decimal point, so that
+/// 10^(magnitude - 1) <= |x| < 10^magnitude.
+fn magnitude(x: Decimal) -> i64 {
+    let digits = x.mantissa().unsigned_abs().checked_ilog10().unwrap_or(0) as i64 + 1;
+    digits - x.scale() as i64
+}
+
+/// x * 10^exponent, with results below the precision rounding to zero.
+fn shift(x: Decimal, exponent: i64) -> Option<Decimal> {
+    let power = |e: i64| Decimal::TEN.checked_powi(e);
+    match exponent {
+        e if e >= 0 => x.checked_mul(power(e)?),
+        e if e < -28 => Some(Decimal::ZERO),
+        e => x.checked_div(power(-e)?),
+    }
+}
+
+/// All complex roots of the polynomial with the given coefficients, highest
+/// degree first. Degrees up to four are solved in closed form, higher ones
+/// iteratively.
+pub fn roots(coefficients: &[Decimal], settings: &Settings) -> Result<Vec<Complex>, ComputeError> {
+    let start = coefficients
+        .iter()
+        .position(|a| !a.is_zero())
+        .ok_or(ComputeError::ZeroPolynomial)?;
+    let coefficients = &coefficients[start..];
+    // Trailing zero coefficients are roots at zero
+    let end = coefficients
+        .iter()
+        .rposition(|a| !a.is_zero())
+        .unwrap_or_default();
+    let zeros = coefficients.len() - 1 - end;
+    let leading = coefficients[0];
+    let monic = coefficients[1..=end]
+        .iter()
+        .map(|a| checked(a.checked_div(leading)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Substitute z = 10^k y so that the coefficients are at most about one
+    // and the roots lie near the unit circle
+    let k = monic
+        .iter()
+        .zip(1..)
+        .filter(|(a, _)| !a.is_zero())
+        .map(|(a, i)| -(1 - magnitude(*a)).div_euclid(i))
+        .max()
+        .unwrap_or_default();
+    let scaled = monic
+        .iter()
+        .zip(1..)
+        .map(|(a, i)| checked(shift(*a, -k * i)).map(c))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let roots = match scaled.as_slice() {
+        [] => Vec::new(),
+        [a] => vec![Complex::real(-a.re)],
+        [b, c] => checked(quadratic(*b, *c))?,
+        [a, b, c] => checked(cubic(*a, *b, *c))?,
+        [a, b, c, d] => checked(quartic(*a, *b, *c, *d))?,
+        _ => durand_kerner(&scaled, settings)?,
+    };
+
+    let mut roots: Vec<_> = roots
+        .into_iter()
+        .map(|root| polish(&scaled, root))
+        .collect();
+    checked(merge_clusters(&scaled, &mut roots))?;
+    for root in roots.iter_mut() {
+        let size = root.magnitude();
+        if root.im.abs() <= EPSILON * size {
+            root.im = Decimal::ZERO;
+        }
+        if root.re.abs() <= EPSILON * size {
+            root.re = Decimal::ZERO;
+        }
+    }
+    // Complex roots of a real polynomial come in conjugate pairs, which
+    // should also print as such
+    let upper: Vec<_> = roots
+        .iter()
+        .copied()
+        .filter(|z| z.im > Decimal::ZERO)
+        .collect();
+    for root in roots.iter_mut().filter(|z| z.im < Decimal::ZERO) {
+        let distance = |z: &Complex| z.checked_sub(*root).map_or(Decimal::MAX, |d| d.magnitude());
+        let partner = upper.iter().map(Complex::conj).min_by_key(distance);
+        if let Some(partner) = partner {
+            if distance(&partner) <= CLUSTER * root.magnitude() {
+                *root = partner;
+            }
+        }
+    }
+
+    let mut result = vec![Complex::ZERO; zeros];
+    for root in roots {
+        result.push(Complex::new(
+            checked(shift(root.re, k))?.normalize(),
+            checked(shift(root.im, k))?.normalize(),
+        ));
+    }
+    // Real roots first in ascending order, then the complex ones
+    result.sort_by_key(|z| (!z.im.is_zero(), z.re, z.im.abs(), -z.im));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(z: Complex, re: Decimal, im: Decimal) -> bool {
+        let tolerance = dec!(0.000000000000000001) * re.abs().max(im.abs()).max(Decimal::ONE);
+        (z.re - re).abs() <= tolerance && (z.im - im).abs() <= tolerance
+    }
+
+    /// Roots of `coefficients`, which must be close to `expected` in order.
+    fn check(coefficients: &[Decimal], expected: &[(Decimal, Decimal)]) {
+        let Ok(roots) = roots(coefficients, &Settings::default()) else {
+            panic!("no roots for {coefficients:?}");
+        };
+        assert_eq!(roots.len(), expected.len(), "{roots:?}");
+        for (root, (re, im)) in roots.iter().zip(expected) {
+            assert!(close(*root, *re, *im), "{roots:?}");
+        }
+    }
+
+    #[test]
+    fn finds_known_roots() {
+        let sqrt_3 = dec!(1.7320508075688772935274463415);
+        check(
+            &[dec!(1), dec!(-3), dec!(2)],
+            &[(dec!(1), dec!(0)), (dec!(2), dec!(0))],
+        );
+        check(
+            &[dec!(1), dec!(0), dec!(1)],
+            &[(dec!(0), dec!(1)), (dec!(0), dec!(-1))],
+        );
+        check(
+            &[dec!(1), dec!(0), dec!(0), dec!(-8)],
+            &[(dec!(2), dec!(0)), (dec!(-1), sqrt_3), (dec!(-1), -sqrt_3)],
+        );
+        check(
+            &[dec!(1), dec!(0), dec!(0)],
+            &[(dec!(0), dec!(0)), (dec!(0), dec!(0))],
+        );
+        // (x - 1)(x - 2)(x - 3)(x - 4)(x - 5)
+        check(
+            &[
+                dec!(1),
+                dec!(-15),
+                dec!(85),
+                dec!(-225),
+                dec!(274),
+                dec!(-120),
+            ],
+            &[1, 2, 3, 4, 5].map(|x| (Decimal::from(x), Decimal::ZERO)),
+        );
+        assert!(matches!(
+            roots(&[Decimal::ZERO, Decimal::ZERO], &Settings::default()),
+            Err(ComputeError::ZeroPolynomial)
+        ));
+    }
+
+    #[test]
+    fn handles_the_largest_numbers() {
+        check(
+            &[Decimal::ONE, -Decimal::MAX],
+            &[(Decimal::MAX, Decimal::ZERO)],
+        );
+        check(
+            &[Decimal::MAX, Decimal::MAX],
+            &[(-Decimal::ONE, Decimal::ZERO)],
+        );
+        for coefficients in [
+            [Decimal::ONE, Decimal::MAX, Decimal::MAX],
+            [Decimal::MAX, Decimal::ZERO, Decimal::ONE],
+            [Decimal::ONE, Decimal::ZERO, Decimal::MIN],
+        ] {
+            // Either roots or an error, never a panic
+            let _ = roots(&coefficients, &Settings::default());
+        }
+    }
+}
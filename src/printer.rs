@@ -0,0 +1,147 @@
+use std::fmt::Display;
+
+use crate::ast::{Ast, NodeId, NodeKind};
+use crate::tokenizer::Token;
+
+// Binding strength of the printed forms, mirroring the order of the parser
+// passes. Higher binds tighter.
+const EQUATION: u8 = 0;
+const OR: u8 = 1;
+const AND: u8 = 2;
+const NOT: u8 = 3;
+const COMPARISON: u8 = 4;
+const SUM: u8 = 5;
+const PRODUCT: u8 = 6;
+const IMPLICIT_PRODUCT: u8 = 7;
+const POWER: u8 = 8;
+const FACTORIAL: u8 = 9;
+const ATOM: u8 = 10;
+
+fn precedence(token: &Token) -> u8 {
+    match token {
+        Token::Assign => EQUATION,
+        Token::Or => OR,
+        Token::And => AND,
+        Token::Less
+        | Token::LessEqual
+        | Token::Equal
+        | Token::NotEqual
+        | Token::GreaterEqual
+        | Token::Greater => COMPARISON,
+        Token::Add | Token::Sub | Token::PlusMinus => SUM,
+        Token::Mul | Token::ImplMul | Token::Div => PRODUCT,
+        Token::Pow => POWER,
+        _ => ATOM,
+    }
+}
+
+/// `-x` is stored as `0 - x`.
+fn negated(ast: &Ast, id: NodeId) -> Option<NodeId> {
+    match ast.node(id).kind {
+        NodeKind::Binary(Token::Sub, left, right)
+            if ast.literal(left).is_some_and(|x| x.is_zero()) =>
+        {
+            Some(right)
+        }
+        _ => None,
+    }
+}
+
+/// Renders the node `id` as an operand that must bind at least as tightly
+/// as `min`. Operands starting with a minus sign are always parenthesised
+/// unless they come first, since the parser only accepts a unary minus at
+/// the start of an expression.
+fn operand(ast: &Ast, id: NodeId, min: u8, first: bool) -> String {
+    let (text, strength) = render(ast, id);
+    if strength < min || (!first && text.starts_with('-')) {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+fn negation(ast: &Ast, id: NodeId) -> (String, u8) {
+    (format!("-{}", operand(ast, id, PRODUCT, false)), SUM)
+}
+
+fn arguments(ast: &Ast, args: &[NodeId]) -> String {
+    args.iter()
+        .map(|arg| render(ast, *arg).0)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Text of the node `id` together with the binding strength of its
+/// outermost operator.
+fn render(ast: &Ast, id: NodeId) -> (String, u8) {
+    if let Some(x) = negated(ast, id) {
+        return negation(ast, x);
+    }
+    match &ast.node(id).kind {
+        NodeKind::Literal(Token::Literal(x)) if x.is_sign_negative() => {
+            (x.normalize().to_string(), SUM)
+        }
+        NodeKind::Literal(Token::Literal(x)) => (x.normalize().to_string(), ATOM),
+        NodeKind::Literal(token) => (token.to_string(), ATOM),
+        NodeKind::Identifier(name) => (name.clone(), ATOM),
+        NodeKind::Unary(token @ (Token::Factorial | Token::DoubleFactorial), arg) => (
+            format!("{}{token}", operand(ast, *arg, ATOM, false)),
+            FACTORIAL,
+        ),
+        NodeKind::Unary(token, arg) => (
+            format!("{token} {}", operand(ast, *arg, COMPARISON, false)),
+            NOT,
+        ),
+        NodeKind::Binary(token @ (Token::Mul | Token::ImplMul | Token::Div), left, right) => {
+            let (left, right) = (*left, *right);
+            // (-a) * b is printed as -(a * b)
+            let positive = match (ast.literal(left), negated(ast, left)) {
+                (_, Some(x)) => Some(ast.subtree(x)),
+                (Some(x), None) if x.is_sign_negative() => Some(Ast::new(Token::Literal(-x))),
+                _ => None,
+            };
+            if let Some(positive) = positive {
+                let product = Ast::binary(token.clone(), positive, ast.subtree(right));
+                return negation(&product, product.root());
+            }
+            if let (Token::Mul | Token::ImplMul, Some(_)) = (token, ast.literal(left)) {
+                // 2x, 3sin(x) and 2(x + 1)
+                let factor = operand(ast, right, POWER, false);
+                if factor.starts_with(|c: char| c.is_alphabetic() || c == '(') {
+                    return (format!("{}{factor}", render(ast, left).0), IMPLICIT_PRODUCT);
+                }
+            }
+            binary(ast, token, left, right)
+        }
+        NodeKind::Binary(Token::Pow, left, right) => (
+            format!(
+                "{}^{}",
+                operand(ast, *left, POWER + 1, true),
+                operand(ast, *right, POWER + 1, false)
+            ),
+            POWER,
+        ),
+        NodeKind::Binary(token, left, right) => binary(ast, token, *left, *right),
+        NodeKind::Call(Token::Matrix, args) => (format!("[{}]", arguments(ast, args)), ATOM),
+        NodeKind::Call(token, args) => (format!("{token}({})", arguments(ast, args)), ATOM),
+    }
+}
+
+/// Left associative infix operator.
+fn binary(ast: &Ast, token: &Token, left: NodeId, right: NodeId) -> (String, u8) {
+    let strength = precedence(token);
+    (
+        format!(
+            "{} {token} {}",
+            operand(ast, left, strength, true),
+            operand(ast, right, strength + 1, false)
+        ),
+        strength,
+    )
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", render(self, self.root()).0)
+    }
+}
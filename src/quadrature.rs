@@ -0,0 +1,219 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::compute::{ComputeError, Settings};
+
+/// Positive abscissae of the 15 point Kronrod rule on [-1, 1] besides the
+/// center. Every second one is also a node of the embedded 7 point Gauss
+/// rule.
+const NODES: [Decimal; 7] = [
+    dec!(0.9914553711208126392068546975),
+    dec!(0.9491079123427585245261896840),
+    dec!(0.8648644233597690727897127886),
+    dec!(0.7415311855993944398638647733),
+    dec!(0.5860872354676911302941448383),
+    dec!(0.4058451513773971669066064121),
+    dec!(0.2077849550078984676006894038),
+];
+
+/// Kronrod weights for `NODES` followed by the weight of the center.
+const KRONROD_WEIGHTS: [Decimal; 8] = [
+    dec!(0.0229353220105292249637320081),
+    dec!(0.0630920926299785532907006632),
+    dec!(0.1047900103222501838398763225),
+    dec!(0.1406532597155259187451895905),
+    dec!(0.1690047266392679028265834266),
+    dec!(0.1903505780647854099132564024),
+    dec!(0.2044329400752988924141619992),
+    dec!(0.2094821410847278280129991749),
+];
+
+/// Gauss weights for `NODES[1]`, `NODES[3]`, `NODES[5]` and the center.
+const GAUSS_WEIGHTS: [Decimal; 4] = [
+    dec!(0.1294849661688696932706114327),
+    dec!(0.2797053914892766679014677714),
+    dec!(0.3818300505051189449503697755),
+    dec!(0.4179591836734693877551020408),
+];
+
+fn checked(x: Option<Decimal>) -> Result<Decimal, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+/// Integral over `[a, b]` together with an estimate of its error.
+struct Panel {
+    a: Decimal,
+    b: Decimal,
+    integral: Decimal,
+    error: Decimal,
+}
+
+fn kronrod(
+    f: &mut impl FnMut(Decimal) -> Result<Decimal, ComputeError>,
+    a: Decimal,
+    b: Decimal,
+) -> Result<Panel, ComputeError> {
+    // The center is an offset from `a` as `a + b` overflows for panels near
+    // the largest number
+    let half = checked(b.checked_sub(a))? / Decimal::TWO;
+    let center = checked(a.checked_add(half))?;
+    let f_center = f(center)?;
+    let mut values = vec![(f_center, KRONROD_WEIGHTS[7])];
+    let mut kronrod = checked(f_center.checked_mul(KRONROD_WEIGHTS[7]))?;
+    let mut gauss = checked(f_center.checked_mul(GAUSS_WEIGHTS[3]))?;
+    for (i, node) in NODES.into_iter().enumerate() {
+        let dx = half * node;
+        let left = f(checked(center.checked_sub(dx))?)?;
+        let right = f(checked(center.checked_add(dx))?)?;
+        let pair = checked(left.checked_add(right))?;
+        kronrod = checked(
+            pair.checked_mul(KRONROD_WEIGHTS[i])
+                .and_then(|x| x.checked_add(kronrod)),
+        )?;
+        if i % 2 == 1 {
+            gauss = checked(
+                pair.checked_mul(GAUSS_WEIGHTS[i / 2])
+                    .and_then(|x| x.checked_add(gauss)),
+            )?;
+        }
+        values.push((pair / Decimal::TWO, KRONROD_WEIGHTS[i] * Decimal::TWO));
+    }
+
+    // Rescales |kronrod - gauss| by how much f varies over the panel, which
+    // is the estimate used by QUADPACK
+    let mean = kronrod / Decimal::TWO;
+    let spread = values.iter().try_fold(Decimal::ZERO, |sum, &(y, w)| {
+        checked(
+            y.checked_sub(mean)
+                .and_then(|d| d.abs().checked_mul(w))
+                .and_then(|d| d.checked_add(sum)),
+        )
+    })?;
+    let spread = checked(spread.checked_mul(half.abs()))?;
+    let mut error = checked(kronrod.checked_sub(gauss).and_then(|d| d.checked_mul(half)))?.abs();
+    if !spread.is_zero() && !error.is_zero() {
+        let ratio = checked(
+            error
+                .checked_mul(dec!(200))
+                .and_then(|x| x.checked_div(spread)),
+        )?;
+        error = if ratio >= Decimal::ONE {
+            spread
+        } else {
+            spread * ratio * checked(ratio.sqrt())?
+        };
+    }
+    Ok(Panel {
+        a,
+        b,
+        integral: checked(kronrod.checked_mul(half))?,
+        error,
+    })
+}
+
+/// Globally adaptive Gauss-Kronrod quadrature. The panel with the largest
+/// error estimate is halved until the total estimate meets the tolerance,
+/// at most `settings.max_iterations` times.
+pub fn integrate(
+    mut f: impl FnMut(Decimal) -> Result<Decimal, ComputeError>,
+    a: Decimal,
+    b: Decimal,
+    settings: &Settings,
+) -> Result<Decimal, ComputeError> {
+    if a == b {
+        return Ok(Decimal::ZERO);
+    }
+    let mut panels = vec![kronrod(&mut f, a, b)?];
+    let mut splits = 0;
+    loop {
+        let integral = panels.iter().try_fold(Decimal::ZERO, |sum, panel| {
+            checked(sum.checked_add(panel.integral))
+        })?;
+        let error = panels.iter().try_fold(Decimal::ZERO, |sum, panel| {
+            checked(sum.checked_add(panel.error))
+        })?;
+        let scale = integral.abs().max(Decimal::ONE);
+        if error
+            <= settings
+                .tolerance
+                .checked_mul(scale)
+                .unwrap_or(Decimal::MAX)
+        {
+            return Ok(integral);
+        }
+        if splits == settings.max_iterations {
+            return Err(ComputeError::NoConvergence);
+        }
+        splits += 1;
+
+        let worst = (0..panels.len())
+            .max_by_key(|&i| panels[i].error)
+            .unwrap_or_default();
+        let panel = panels.swap_remove(worst);
+        let middle = checked(
+            panel
+                .b
+                .checked_sub(panel.a)
+                .and_then(|width| panel.a.checked_add(width / Decimal::TWO)),
+        )?;
+        if middle == panel.a || middle == panel.b {
+            return Err(ComputeError::NoConvergence);
+        }
+        panels.push(kronrod(&mut f, panel.a, middle)?);
+        panels.push(kronrod(&mut f, middle, panel.b)?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(x: Decimal, expected: Decimal) -> bool {
+        (x - expected).abs() <= dec!(0.0000000000000000001) * expected.abs().max(Decimal::ONE)
+    }
+
+    #[test]
+    fn integrates_known_functions() {
+        let settings = Settings::default();
+        let x = integrate(
+            |x| checked(x.checked_sin()),
+            Decimal::ZERO,
+            Decimal::PI,
+            &settings,
+        );
+        // The sine of rust_decimal is only good to about 11 places
+        assert!(matches!(x, Ok(x) if (x - Decimal::TWO).abs() < dec!(0.0000000001)));
+        let x = integrate(|x| Ok(x * x), Decimal::ZERO, dec!(3), &settings);
+        assert!(matches!(x, Ok(x) if close(x, dec!(9))));
+        let x = integrate(|x| Ok(x * x), dec!(3), Decimal::ZERO, &settings);
+        assert!(matches!(x, Ok(x) if close(x, dec!(-9))));
+        let x = integrate(|x| Ok(x * x * x), Decimal::ONE, Decimal::TWO, &settings);
+        assert!(matches!(x, Ok(x) if close(x, dec!(3.75))));
+    }
+
+    #[test]
+    fn overflows_near_the_largest_number() {
+        let settings = Settings::default();
+        let x = integrate(|_| Ok(Decimal::ONE), Decimal::MIN, Decimal::MAX, &settings);
+        assert!(matches!(x, Err(ComputeError::Overflow)));
+        let x = integrate(|_| Ok(Decimal::MAX), Decimal::ZERO, dec!(3), &settings);
+        assert!(matches!(x, Err(ComputeError::Overflow)));
+        let x = integrate(
+            |_| Ok(Decimal::ONE),
+            Decimal::MAX - Decimal::ONE,
+            Decimal::MAX,
+            &settings,
+        );
+        assert!(matches!(x, Ok(x) if close(x, Decimal::ONE)));
+        // Panels are split without adding their bounds
+        let a = dec!(40000000000000000000000000000);
+        let x = integrate(
+            |x| checked((x - a).sqrt()).map(|y| y / dec!(100000000000000000000)),
+            a,
+            dec!(41000000000000000000000000000),
+            &settings,
+        );
+        let expected = dec!(210818510677891955466.59290296);
+        assert!(matches!(x, Ok(x) if close(x, expected)));
+    }
+}
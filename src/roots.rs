@@ -0,0 +1,220 @@
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+
+use crate::compute::{ComputeError, Settings};
+
+/// The search for a sign change stops widening beyond this distance, which
+/// keeps every later difference of two points representable.
+const MAX_WIDTH: Decimal = dec!(100000000000000000000);
+
+/// Whether a step of size `step` near `x` is below the tolerance.
+fn converged(step: Decimal, x: Decimal, tolerance: Decimal) -> bool {
+    let scale = x.abs().max(Decimal::ONE);
+    step.abs() <= tolerance.checked_mul(scale).unwrap_or(Decimal::MAX)
+}
+
+/// Newton's method, `f` returns the value of the function and of its
+/// derivative at a point.
+pub fn newton(
+    mut f: impl FnMut(Decimal) -> Result<(Decimal, Decimal), ComputeError>,
+    guess: Decimal,
+    settings: &Settings,
+) -> Result<Decimal, ComputeError> {
+    let mut x = guess;
+    for _ in 0..settings.max_iterations {
+        let (y, dy) = f(x)?;
+        if y.is_zero() {
+            return Ok(x);
+        }
+        let step = y.checked_div(dy).ok_or(ComputeError::NoConvergence)?;
+        x = x.checked_sub(step).ok_or(ComputeError::NoConvergence)?;
+        if converged(step, x, settings.tolerance) {
+            return Ok(x);
+        }
+    }
+    Err(ComputeError::NoConvergence)
+}
+
+/// Finds two points on either side of `guess`, or both on one side, where
+/// `f` has opposite signs. Points where `f` is undefined are skipped, the
+/// first error is only reported when `f` is undefined everywhere.
+fn bracket(
+    f: &mut impl FnMut(Decimal) -> Result<Decimal, ComputeError>,
+    guess: Decimal,
+    settings: &Settings,
+) -> Result<[(Decimal, Decimal); 2], ComputeError> {
+    let mut error = None;
+    let mut sample = |x: Decimal| match f(x) {
+        Ok(y) => {
+            error = Some(None);
+            Some(y)
+        }
+        Err(err) => {
+            error.get_or_insert(Some(err));
+            None
+        }
+    };
+    let start = (guess, sample(guess));
+    let mut sides = [start, start];
+    let mut width = guess.abs().max(Decimal::ONE) * dec!(0.01);
+    for _ in 0..settings.max_iterations {
+        for (side, direction) in sides.iter_mut().zip([Decimal::ONE, -Decimal::ONE]) {
+            // A side that reaches the end of the representable numbers
+            // stops widening
+            let Some(x) = guess.checked_add(direction * width) else {
+                continue;
+            };
+            let y = sample(x);
+            if let ((previous, Some(py)), Some(y)) = (*side, y) {
+                if py.is_sign_negative() != y.is_sign_negative() || y.is_zero() {
+                    return Ok([(previous, py), (x, y)]);
+                }
+            }
+            *side = (x, y);
+        }
+        width *= Decimal::TWO;
+        if width > MAX_WIDTH {
+            break;
+        }
+    }
+    Err(error.flatten().unwrap_or(ComputeError::NoConvergence))
+}
+
+fn inverse_quadratic(
+    (a, fa): (Decimal, Decimal),
+    (b, fb): (Decimal, Decimal),
+    (c, fc): (Decimal, Decimal),
+) -> Option<Decimal> {
+    // x y1 y2 / ((y0 - y1) (y0 - y2))
+    let term = |x: Decimal, y0: Decimal, y1: Decimal, y2: Decimal| {
+        x.checked_mul(y1.checked_div(y0.checked_sub(y1)?)?)?
+            .checked_mul(y2.checked_div(y0.checked_sub(y2)?)?)
+    };
+    term(a, fa, fb, fc)?
+        .checked_add(term(b, fb, fa, fc)?)?
+        .checked_add(term(c, fc, fa, fb)?)
+}
+
+fn secant((a, fa): (Decimal, Decimal), (b, fb): (Decimal, Decimal)) -> Option<Decimal> {
+    b.checked_sub(fb.checked_mul((b - a).checked_div(fb.checked_sub(fa)?)?)?)
+}
+
+/// Brent's method on an interval found around `guess`, combining
+/// bisection with interpolation.
+pub fn brent(
+    mut f: impl FnMut(Decimal) -> Result<Decimal, ComputeError>,
+    guess: Decimal,
+    settings: &Settings,
+) -> Result<Decimal, ComputeError> {
+    let [(mut a, mut fa), (mut b, mut fb)] = bracket(&mut f, guess, settings)?;
+    // A sign change across a pole shrinks to the pole while |f| grows
+    let bound = fa.abs().min(fb.abs());
+    if fa.abs() < fb.abs() {
+        (a, fa, b, fb) = (b, fb, a, fa);
+    }
+    let (mut c, mut fc) = (a, fa);
+    let mut d = c;
+    let mut bisected = true;
+    for _ in 0..settings.max_iterations {
+        if fb.is_zero() || converged(b - a, b, settings.tolerance) {
+            return if fb.abs() <= bound {
+                Ok(b)
+            } else {
+                Err(ComputeError::NoConvergence)
+            };
+        }
+        let interpolated = if fa != fc && fb != fc {
+            inverse_quadratic((a, fa), (b, fb), (c, fc))
+        } else {
+            secant((a, fa), (b, fb))
+        };
+        let delta = settings
+            .tolerance
+            .checked_mul(b.abs().max(Decimal::ONE))
+            .unwrap_or(Decimal::MAX);
+        // Offsets from `a` are used as `a` and `b` may be close to the
+        // largest number while the bracket is narrow
+        let quarter = a + (b - a) / Decimal::from(4);
+        // Interpolation must land between (3a + b) / 4 and b and shrink the
+        // interval faster than the bisection steps would
+        let previous_step = if bisected { b - c } else { c - d }.abs();
+        let s = match interpolated {
+            Some(s)
+                if s > quarter.min(b)
+                    && s < quarter.max(b)
+                    && (s - b).abs() < previous_step / Decimal::TWO
+                    && previous_step >= delta =>
+            {
+                bisected = false;
+                s
+            }
+            _ => {
+                bisected = true;
+                a + (b - a) / Decimal::TWO
+            }
+        };
+        let fs = f(s)?;
+        d = c;
+        (c, fc) = (b, fb);
+        if fa.is_sign_negative() != fs.is_sign_negative() {
+            (b, fb) = (s, fs);
+        } else {
+            (a, fa) = (s, fs);
+        }
+        if fa.abs() < fb.abs() {
+            (a, fa, b, fb) = (b, fb, a, fa);
+        }
+    }
+    Err(ComputeError::NoConvergence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQRT_2: Decimal = dec!(1.4142135623730950488016887242);
+
+    fn close(x: Decimal, expected: Decimal) -> bool {
+        (x - expected).abs() <= dec!(0.0000000000000000001) * expected.abs().max(Decimal::ONE)
+    }
+
+    #[test]
+    fn finds_known_roots() {
+        let settings = Settings::default();
+        let x = newton(
+            |x| Ok((x * x - Decimal::TWO, Decimal::TWO * x)),
+            Decimal::ONE,
+            &settings,
+        );
+        assert!(matches!(x, Ok(x) if close(x, SQRT_2)));
+        let x = brent(|x| Ok(x * x - Decimal::TWO), Decimal::ONE, &settings);
+        assert!(matches!(x, Ok(x) if close(x, SQRT_2)));
+        let x = brent(
+            |x| Ok(x * x * x - x - Decimal::TWO),
+            Decimal::ZERO,
+            &settings,
+        );
+        assert!(matches!(x, Ok(x) if close(x, dec!(1.5213797068045675696))));
+    }
+
+    #[test]
+    fn stays_within_range_near_the_largest_number() {
+        let settings = Settings::default();
+        let x = newton(
+            |x| Ok((x - Decimal::ONE, Decimal::ONE)),
+            Decimal::MAX,
+            &settings,
+        );
+        assert!(matches!(x, Ok(x) if x == Decimal::ONE));
+        let root = Decimal::MAX - Decimal::TEN;
+        let x = brent(|x| Ok(x - root), Decimal::MAX, &settings);
+        assert!(matches!(x, Ok(x) if close(x, root)));
+        let x = brent(
+            |x| x.checked_sub(Decimal::MAX).ok_or(ComputeError::Overflow),
+            Decimal::MIN,
+            &settings,
+        );
+        assert!(x.map_or(true, |x| close(x, Decimal::MAX)));
+        assert!(brent(|_| Ok(Decimal::ONE), Decimal::MAX, &settings).is_err());
+    }
+}
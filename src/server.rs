@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::batch;
+use crate::compute::{self, Cancel, Environment};
+use crate::json;
+
+/// Largest request body that is accepted.
+const MAX_BODY: usize = 1 << 20;
+
+/// Time a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most connections handled at once, more are answered as busy.
+const MAX_CONNECTIONS: usize = 256;
+
+/// Most evaluations running at once, including abandoned ones that have
+/// not noticed their cancellation yet.
+const MAX_EVALUATIONS: usize = 16;
+
+/// Named sessions, and those an evaluation is using.
+#[derive(Default)]
+struct Sessions {
+    saved: HashMap<String, Environment>,
+    busy: HashSet<String>,
+}
+
+/// Place counted against a limit, given back when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn claim(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self(Arc::clone(count)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub struct Server {
+    /// Variables and functions of every named session.
+    sessions: Mutex<Sessions>,
+    /// Number of evaluation threads still running.
+    evaluations: Arc<AtomicUsize>,
+    /// Time after which an evaluation is abandoned.
+    timeout: Duration,
+    /// Number of threads a parallel batch is spread over.
+    jobs: usize,
+}
+
+struct Response {
+    status: u16,
+    body: serde_json::Value,
+}
+
+impl Response {
+    fn ok(body: serde_json::Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, kind: &str, message: &str) -> Self {
+        let error = json::error(kind, message.to_string(), serde_json::Value::Null);
+        Self {
+            status,
+            body: json!({ "error": error }),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Reads the request line and body, ignoring every header except
+/// Content-Length.
+fn read_request(stream: &TcpStream) -> Result<(String, String, Vec<u8>), Response> {
+    let bad = || Response::error(400, "bad_request", "Malformed HTTP request");
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|_| bad())?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(bad());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(|_| bad())?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| bad())?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(Response::error(
+            413,
+            "too_large",
+            "Request body is too large",
+        ));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|_| bad())?;
+    Ok((method, path, body))
+}
+
+fn write_response(mut stream: &TcpStream, response: Response) -> std::io::Result<()> {
+    let body = response.body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        response.status,
+        reason(response.status),
+        body.len()
+    )?;
+    stream.flush()
+}
+
+impl Server {
+    pub fn new(timeout: Duration, jobs: usize) -> Self {
+        Self {
+            sessions: Mutex::default(),
+            evaluations: Arc::default(),
+            timeout,
+            jobs,
+        }
+    }
+
+    /// Evaluates `inputs` in order within the environment of `session`, or
+    /// a fresh one. The session only keeps the changes of an evaluation
+    /// that finished in time, one that did not is cancelled. A session is
+    /// used by one evaluation at a time, others fail while it is busy.
+    /// Parallel inputs are evaluated independently of each other on `jobs`
+    /// threads and leave the session as it was.
+    fn evaluate(
+        &self,
+        session: Option<&str>,
+        inputs: Vec<String>,
+        parallel: bool,
+    ) -> Result<Vec<serde_json::Value>, Response> {
+        let busy = || Response::error(503, "busy", "Too many evaluations in progress");
+        let slot = Slot::claim(&self.evaluations, MAX_EVALUATIONS).ok_or_else(busy)?;
+        let mut env = match session {
+            Some(name) => self.claim_session(name)?,
+            None => Environment::default(),
+        };
+        let cancel = Cancel::default();
+        env.cancel = cancel.clone();
+        // The stack is sized for the depth set when the request starts
+        let depth = env.settings.max_depth;
+        env.max_depth = Some(depth);
+        let jobs = self.jobs;
+        let (sender, receiver) = mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .stack_size(compute::stack_size(depth))
+            .spawn(move || {
+                let _slot = slot;
+                let evaluate =
+                    |input: &str, env: &mut Environment| json::evaluate(input, env).into();
+                let results = match parallel {
+                    true => batch::evaluate(&env.context(), &env.cancel, &inputs, jobs, evaluate),
+                    false => inputs
+                        .iter()
+                        .map(|input| evaluate(input, &mut env))
+                        .collect(),
+                };
+                let _ = sender.send((results, env));
+            });
+        let finished = match spawned {
+            Ok(_) => receiver.recv_timeout(self.timeout).ok(),
+            Err(_) => None,
+        };
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(name) = session {
+            sessions.busy.remove(name);
+        }
+        match (spawned.is_ok(), finished) {
+            (true, Some((results, env))) => {
+                if let Some(name) = session {
+                    sessions.saved.insert(name.to_string(), env);
+                }
+                Ok(results)
+            }
+            (true, None) => {
+                cancel.cancel();
+                Err(Response::error(503, "timeout", "Evaluation took too long"))
+            }
+            (false, _) => Err(busy()),
+        }
+    }
+
+    /// A copy of the environment of the session `name` for an evaluation,
+    /// which must give it back by removing the name from the busy ones.
+    fn claim_session(&self, name: &str) -> Result<Environment, Response> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        if !sessions.busy.insert(name.to_string()) {
+            return Err(Response::error(
+                409,
+                "session_busy",
+                "Session is in use by another request",
+            ));
+        }
+        Ok(sessions.saved.get(name).cloned().unwrap_or_default())
+    }
+
+    fn respond(&self, method: &str, path: &str, body: &[u8]) -> Response {
+        let endpoint = path.split('?').next().unwrap_or_default();
+        if !matches!(endpoint, "/eval" | "/batch") {
+            return Response::error(404, "not_found", "Unknown endpoint");
+        }
+        if method != "POST" {
+            return Response::error(405, "method_not_allowed", "Use POST");
+        }
+        let Ok(serde_json::Value::Object(request)) = serde_json::from_slice(body) else {
+            return Response::error(400, "invalid_request", "Body must be a JSON object");
+        };
+        let session = request.get("session").and_then(|x| x.as_str());
+        let parallel = request.get("parallel").and_then(|x| x.as_bool());
+        let inputs = match (endpoint, request.get("input"), request.get("inputs")) {
+            ("/eval", Some(serde_json::Value::String(input)), _) => vec![input.clone()],
+            ("/batch", _, Some(serde_json::Value::Array(inputs))) => {
+                match inputs
+                    .iter()
+                    .map(|x| x.as_str().map(str::to_string))
+                    .collect()
+                {
+                    Some(inputs) => inputs,
+                    None => {
+                        return Response::error(400, "invalid_request", "Inputs must be strings")
+                    }
+                }
+            }
+            ("/eval", _, _) => return Response::error(400, "invalid_request", "Missing input"),
+            _ => return Response::error(400, "invalid_request", "Missing inputs"),
+        };
+        let parallel = endpoint == "/batch" && parallel.unwrap_or_default();
+        let mut results = match self.evaluate(session, inputs, parallel) {
+            Ok(results) => results,
+            Err(response) => return response,
+        };
+        match endpoint {
+            "/eval" => Response::ok(results.pop().unwrap_or_default()),
+            _ => Response::ok(json!({ "results": results })),
+        }
+    }
+
+    fn handle(&self, stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+        let response = match read_request(&stream) {
+            Ok((method, path, body)) => self.respond(&method, &path, &body),
+            Err(response) => response,
+        };
+        let _ = write_response(&stream, response);
+    }
+}
+
+/// Serves `POST /eval` and `POST /batch` on localhost until the process is
+/// stopped, one thread per connection up to `MAX_CONNECTIONS`.
+pub fn serve(port: u16, timeout: Duration, jobs: usize) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    let server = Arc::new(Server::new(timeout, jobs));
+    let connections = Arc::default();
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let Some(slot) = Slot::claim(&connections, MAX_CONNECTIONS) else {
+            let _ = stream.set_write_timeout(Some(READ_TIMEOUT));
+            let busy = Response::error(503, "busy", "Too many connections");
+            let _ = write_response(&stream, busy);
+            continue;
+        };
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            let _slot = slot;
+            server.handle(stream);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Input that takes far longer than the tests wait for.
+    const SLOW: &str = "factor(100000000000031 * 100000000000067)";
+
+    fn status(result: Result<Vec<serde_json::Value>, Response>) -> u16 {
+        result.err().map_or(200, |response| response.status)
+    }
+
+    /// Waits for `condition` for at most a few seconds.
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while !condition() {
+            if start.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn stops_evaluations_that_take_too_long() {
+        let server = Server::new(Duration::from_millis(100), 1);
+        let result = server.evaluate(None, vec![SLOW.to_string()], false);
+        assert_eq!(status(result), 503);
+        assert!(eventually(
+            || server.evaluations.load(Ordering::Acquire) == 0
+        ));
+    }
+
+    #[test]
+    fn uses_a_session_for_one_request_at_a_time() {
+        let server = Server::new(Duration::from_millis(500), 1);
+        let inputs = |input: &str| vec![input.to_string()];
+        assert_eq!(
+            status(server.evaluate(Some("a"), inputs("x = 1"), false)),
+            200
+        );
+        std::thread::scope(|scope| {
+            let slow = scope.spawn(|| server.evaluate(Some("a"), inputs(SLOW), false));
+            let busy = || server.sessions.lock().unwrap().busy.contains("a");
+            assert!(eventually(busy));
+            assert_eq!(
+                status(server.evaluate(Some("a"), inputs("x = 2"), false)),
+                409
+            );
+            assert_eq!(
+                status(server.evaluate(Some("b"), inputs("x = 2"), false)),
+                200
+            );
+            assert_eq!(status(slow.join().unwrap()), 503);
+        });
+        let results = server.evaluate(Some("a"), inputs("x"), false);
+        assert_eq!(results.ok().unwrap()[0]["formatted"], "1");
+    }
+}
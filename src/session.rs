@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{Environment, Function, Settings};
+use crate::output;
+use crate::value::Value;
+
+/// Version of the saved format, written before every session or tree.
+/// Files of an older version must stay loadable, `decode` converts them
+/// when the format changes.
+const VERSION: u32 = 1;
+
+/// Start of everything saved in the binary format.
+const MAGIC: &[u8; 4] = b"calc";
+
+pub enum SessionError {
+    Io(std::io::Error),
+    /// The contents are not something that was saved.
+    Invalid(String),
+    /// Saved by a newer version of the calculator.
+    NewerVersion(u32),
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Invalid(message) => write!(f, "Not a saved session: {message}"),
+            Self::NewerVersion(version) => {
+                write!(f, "Saved in format version {version}, newer than {VERSION}")
+            }
+        }
+    }
+}
+
+fn invalid(err: impl Display) -> SessionError {
+    SessionError::Invalid(err.to_string())
+}
+
+/// What `save` keeps of an environment. Fields missing from a file are
+/// left empty.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Session {
+    ans: Option<Value>,
+    variables: BTreeMap<String, Value>,
+    functions: BTreeMap<String, Function>,
+    history: Vec<String>,
+    settings: Settings,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tagged<T> {
+    version: u32,
+    data: T,
+}
+
+/// `data` tagged with the format version, as JSON or in the binary format.
+pub fn encode<T: Serialize>(data: &T, json: bool) -> Result<Vec<u8>, SessionError> {
+    let tagged = Tagged {
+        version: VERSION,
+        data,
+    };
+    match json {
+        true => serde_json::to_vec_pretty(&tagged).map_err(invalid),
+        false => {
+            let mut bytes = MAGIC.to_vec();
+            bincode::serialize_into(&mut bytes, &tagged).map_err(invalid)?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Reads what `encode` wrote in either format.
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SessionError> {
+    let binary = bytes.strip_prefix(MAGIC);
+    let version = match binary {
+        Some(bytes) => bincode::deserialize(bytes).map_err(invalid)?,
+        None => {
+            serde_json::from_slice::<Header>(bytes)
+                .map_err(invalid)?
+                .version
+        }
+    };
+    // Only the first version exists so far, older ones are converted here
+    match version {
+        0 => return Err(invalid("unknown version 0")),
+        1..=VERSION => (),
+        _ => return Err(SessionError::NewerVersion(version)),
+    }
+    let tagged: Tagged<T> = match binary {
+        Some(bytes) => bincode::deserialize(bytes).map_err(invalid)?,
+        None => serde_json::from_slice(bytes).map_err(invalid)?,
+    };
+    Ok(tagged.data)
+}
+
+/// Recognizes `save file.ext` and `load file.ext`, giving the command and
+/// the path.
+pub fn command(input: &str) -> Option<(&str, &str)> {
+    let (command, path) = input.trim().split_once(char::is_whitespace)?;
+    let path = path.trim();
+    (matches!(command, "save" | "load") && output::is_path(path)).then_some((command, path))
+}
+
+/// Writes the variables, functions, history and settings of `env` to
+/// `path`, as JSON if it ends in `.json` and in the binary format
+/// otherwise.
+pub fn save(path: &str, env: &Environment) -> Result<(), SessionError> {
+    let session = Session {
+        ans: env.ans.clone(),
+        variables: env
+            .variables
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        functions: env
+            .functions
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        history: env.history.clone(),
+        settings: env.settings.clone(),
+    };
+    let json = Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    std::fs::write(path, encode(&session, json)?).map_err(SessionError::Io)
+}
+
+/// Replaces the session of `env` by the one saved at `path`.
+pub fn load(path: &str, env: &mut Environment) -> Result<(), SessionError> {
+    let session: Session = decode(&std::fs::read(path).map_err(SessionError::Io)?)?;
+    // Matrices and trees are checked as they are read, except for the size
+    // the settings allow
+    session.settings.check().map_err(invalid)?;
+    for value in session.ans.iter().chain(session.variables.values()) {
+        if let Value::Matrix(matrix) = value {
+            matrix.check(&session.settings).map_err(invalid)?;
+        }
+    }
+    env.ans = session.ans;
+    env.variables = Arc::new(session.variables.into_iter().collect());
+    env.functions = Arc::new(session.functions.into_iter().collect());
+    env.history = session.history;
+    env.settings = session.settings;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::execute;
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn run(input: &str, env: &mut Environment) -> Option<String> {
+        let statements = parse_statements(tokenize(input.to_string()).ok()?).ok()?;
+        let mut result = None;
+        for statement in statements {
+            result = execute(&statement, env)
+                .ok()?
+                .map(|value| value.to_string());
+        }
+        result
+    }
+
+    fn path(name: &str) -> String {
+        let name = format!("calculator-{}-{name}", std::process::id());
+        std::env::temp_dir()
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn saved_sessions_load_back() {
+        let mut env = Environment::default();
+        env.settings.sigma = true;
+        run("u = 2.0(1); m = [[1, 2], [3, 4]]; f(x) = x^2 + 1", &mut env);
+        for name in ["session.json", "session.calc"] {
+            let path = path(name);
+            assert!(save(&path, &env).is_ok());
+            let mut loaded = Environment::default();
+            let result = load(&path, &mut loaded);
+            std::fs::remove_file(&path).ok();
+            assert!(result.is_ok(), "{name} does not load");
+            assert!(loaded.settings.sigma);
+            assert_eq!(run("f(3) + trace(m)", &mut loaded).as_deref(), Some("15"));
+            assert_eq!(run("u - u", &mut loaded).as_deref(), Some("0"));
+            assert_eq!(
+                run("u - 2.0(1)", &mut loaded).as_deref(),
+                Some("0.00 ± 0.14")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_sessions() {
+        let matrix = |rows: usize, cols: usize, data: &[u32]| {
+            format!(r#"{{"Matrix": {{"rows": {rows}, "cols": {cols}, "data": {data:?}}}}}"#)
+        };
+        let uncertain =
+            |terms: &str| format!(r#"{{"Uncertain": {{"value": "1", "terms": {terms}}}}}"#);
+        let session = |x: String, settings: &str| {
+            format!(
+                r#"{{"version": 1, "data": {{"variables": {{"x": {x}}}, "settings": {settings}}}}}"#
+            )
+        };
+        let default = serde_json::to_string(&Settings::default()).unwrap();
+        let small = Settings {
+            max_elements: 3,
+            ..Settings::default()
+        };
+        let small = serde_json::to_string(&small).unwrap();
+        for (name, contents) in [
+            ("empty", session(matrix(0, 0, &[]), &default)),
+            ("short", session(matrix(2, 2, &[1, 2, 3]), &default)),
+            ("large", session(matrix(2, 2, &[1, 2, 3, 4]), &small)),
+            (
+                "unordered",
+                session(uncertain(r#"[[2, "0.1"], [1, "0.1"]]"#), &default),
+            ),
+            (
+                "repeated",
+                session(uncertain(r#"[[1, "0.1"], [1, "0.1"]]"#), &default),
+            ),
+            (
+                "last",
+                session(
+                    uncertain(&format!(r#"[[{}, "0.1"]]"#, usize::MAX)),
+                    &default,
+                ),
+            ),
+        ] {
+            let path = path(&format!("{name}.json"));
+            std::fs::write(&path, contents).unwrap();
+            let mut env = Environment::default();
+            let result = load(&path, &mut env);
+            std::fs::remove_file(&path).ok();
+            assert!(
+                matches!(result, Err(SessionError::Invalid(_))),
+                "{name} loads"
+            );
+            assert!(env.variables.is_empty());
+        }
+    }
+}
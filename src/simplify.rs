@@ -0,0 +1,667 @@
+use rust_decimal::prelude::*;
+
+use crate::ast::{Ast, NodeId, NodeKind};
+use crate::compute::{compute_tree, ComputeError, Environment};
+use crate::optimize;
+use crate::tokenizer::Token;
+use crate::value::Value;
+
+/// Integer powers of sums are multiplied out up to this exponent.
+const MAX_EXPANSION: i64 = 8;
+
+fn overflow<T>(x: Option<T>) -> Result<T, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+fn gcd(mut a: Decimal, mut b: Decimal) -> Decimal {
+    while !b.is_zero() {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+/// Exact fraction with a positive denominator, kept in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rational {
+    numerator: Decimal,
+    denominator: Decimal,
+}
+
+impl Rational {
+    const ZERO: Self = Self::integer(Decimal::ZERO);
+    const ONE: Self = Self::integer(Decimal::ONE);
+
+    const fn integer(x: Decimal) -> Self {
+        Self {
+            numerator: x,
+            denominator: Decimal::ONE,
+        }
+    }
+
+    fn new(numerator: Decimal, denominator: Decimal) -> Result<Self, ComputeError> {
+        if denominator.is_zero() {
+            return Err(ComputeError::DivByZero);
+        }
+        let g = gcd(numerator, denominator);
+        let sign = if denominator.is_sign_negative() {
+            -Decimal::ONE
+        } else {
+            Decimal::ONE
+        };
+        Ok(Self {
+            numerator: (numerator / g * sign).normalize(),
+            denominator: (denominator / g * sign).normalize(),
+        })
+    }
+
+    /// 1.25 becomes 125 / 100 and then 5 / 4.
+    fn from_decimal(x: Decimal) -> Result<Self, ComputeError> {
+        let numerator = Decimal::from_i128_with_scale(x.mantissa(), 0);
+        let denominator = overflow(Decimal::TEN.checked_powi(x.scale() as i64))?;
+        Self::new(numerator, denominator)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    fn is_integer(&self) -> bool {
+        self.denominator == Decimal::ONE
+    }
+
+    fn is_negative(&self) -> bool {
+        self.numerator.is_sign_negative()
+    }
+
+    fn abs(&self) -> Self {
+        Self {
+            numerator: self.numerator.abs(),
+            denominator: self.denominator,
+        }
+    }
+
+    fn approximate(&self) -> Decimal {
+        self.numerator
+            .checked_div(self.denominator)
+            .unwrap_or_default()
+    }
+
+    fn checked_add(&self, other: &Self) -> Result<Self, ComputeError> {
+        let left = overflow(self.numerator.checked_mul(other.denominator))?;
+        let right = overflow(other.numerator.checked_mul(self.denominator))?;
+        Self::new(
+            overflow(left.checked_add(right))?,
+            overflow(self.denominator.checked_mul(other.denominator))?,
+        )
+    }
+
+    fn checked_mul(&self, other: &Self) -> Result<Self, ComputeError> {
+        Self::new(
+            overflow(self.numerator.checked_mul(other.numerator))?,
+            overflow(self.denominator.checked_mul(other.denominator))?,
+        )
+    }
+
+    fn checked_powi(&self, n: i64) -> Result<Self, ComputeError> {
+        let (numerator, denominator) = if n < 0 {
+            (self.denominator, self.numerator)
+        } else {
+            (self.numerator, self.denominator)
+        };
+        Self::new(
+            overflow(numerator.checked_powi(n.abs()))?,
+            overflow(denominator.checked_powi(n.abs()))?,
+        )
+    }
+
+    fn tree(&self) -> Ast {
+        let numerator = Ast::new(Token::Literal(self.numerator));
+        if self.is_integer() {
+            return numerator;
+        }
+        let denominator = Ast::new(Token::Literal(self.denominator));
+        Ast::binary(Token::Div, numerator, denominator)
+    }
+}
+
+/// `coefficient * base^exponent * ...` with the factors sorted by their
+/// printed form, so equal products compare equal.
+#[derive(Debug, Clone)]
+struct Term {
+    coefficient: Rational,
+    factors: Vec<(Ast, Rational)>,
+}
+
+impl Term {
+    fn constant(x: Rational) -> Self {
+        Self {
+            coefficient: x,
+            factors: Vec::new(),
+        }
+    }
+
+    fn atom(base: Ast) -> Self {
+        Self::power(base, Rational::ONE)
+    }
+
+    fn power(base: Ast, exponent: Rational) -> Self {
+        Self {
+            coefficient: Rational::ONE,
+            factors: vec![(base, exponent)],
+        }
+    }
+
+    /// Sum of the exponents of the factors that are not constants.
+    fn degree(&self) -> Decimal {
+        self.factors
+            .iter()
+            .filter(|(base, _)| !is_constant(base))
+            .map(|(_, exponent)| exponent.approximate())
+            .sum()
+    }
+
+    /// Whether a factor may fail to evaluate for some values of the
+    /// variables, so the term has to be kept even with a zero coefficient.
+    fn can_fail(&self, env: &Environment) -> bool {
+        self.factors.iter().any(|(base, exponent)| {
+            can_fail(base, env)
+                || (exponent.is_negative() || !exponent.is_integer()) && !is_positive(base, env)
+        })
+    }
+
+    /// Whether raising every factor to `n` gives the same values, and the
+    /// same errors: `1 / (1 / x)` is not `x` when `x` is 0.
+    fn can_raise(&self, n: i64, env: &Environment) -> bool {
+        if self.coefficient.is_zero() && n < 0 {
+            return false;
+        }
+        self.factors.iter().all(|(base, exponent)| {
+            is_positive(base, env)
+                || exponent.is_integer() && (n > 0 || exponent.numerator > Decimal::ZERO)
+        })
+    }
+
+    fn key(&self) -> String {
+        self.factors
+            .iter()
+            .map(|(base, exponent)| format!("{base}^{}", exponent.approximate()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn checked_mul(&self, other: &Self, env: &Environment) -> Result<Self, ComputeError> {
+        Self {
+            coefficient: self.coefficient.checked_mul(&other.coefficient)?,
+            factors: [self.factors.as_slice(), &other.factors].concat(),
+        }
+        .normalize(env)
+    }
+
+    /// Adds up the powers of each base, drops factors with a zero exponent
+    /// that can not fail, moves integer powers of numbers into the
+    /// coefficient and sorts the remaining factors.
+    fn normalize(mut self, env: &Environment) -> Result<Self, ComputeError> {
+        let mut bases: Vec<(Ast, Vec<Rational>)> = Vec::new();
+        for (base, exponent) in self.factors {
+            match bases.iter_mut().find(|(other, _)| *other == base) {
+                Some((_, exponents)) => exponents.push(exponent),
+                None => bases.push((base, vec![exponent])),
+            }
+        }
+        let mut powers = Vec::with_capacity(bases.len());
+        for (base, exponents) in bases {
+            // All powers at once, else the negative ones apart from the
+            // others, else each on its own
+            let parts = match combine(&base, &exponents, env)? {
+                Some(_) => vec![exponents],
+                None => {
+                    let (negative, other): (Vec<_>, Vec<_>) =
+                        exponents.into_iter().partition(Rational::is_negative);
+                    vec![other, negative]
+                }
+            };
+            for part in parts.into_iter().filter(|part| !part.is_empty()) {
+                match combine(&base, &part, env)? {
+                    Some(exponent) => powers.push((base.clone(), exponent)),
+                    None => {
+                        powers.extend(part.into_iter().map(|exponent| (base.clone(), exponent)))
+                    }
+                }
+            }
+        }
+        let mut factors = Vec::with_capacity(powers.len());
+        for (base, exponent) in powers {
+            match (base.literal(base.root()), exponent.numerator.to_i64()) {
+                _ if exponent.is_zero() && !can_fail(&base, env) => (),
+                (Some(x), Some(n)) if exponent.is_integer() && !x.is_zero() => {
+                    let power = Rational::from_decimal(x)?.checked_powi(n)?;
+                    self.coefficient = self.coefficient.checked_mul(&power)?;
+                }
+                _ => factors.push((base, exponent)),
+            }
+        }
+        factors.sort_by_cached_key(|(base, exponent)| (base.to_string(), exponent.approximate()));
+        self.factors = factors;
+        Ok(self)
+    }
+
+    fn checked_powi(&self, n: i64, env: &Environment) -> Result<Self, ComputeError> {
+        let factors = self
+            .factors
+            .iter()
+            .map(|(base, exponent)| {
+                Ok((
+                    base.clone(),
+                    exponent.checked_mul(&Rational::integer(n.into()))?,
+                ))
+            })
+            .collect::<Result<_, ComputeError>>()?;
+        Self {
+            coefficient: self.coefficient.checked_powi(n)?,
+            factors,
+        }
+        .normalize(env)
+    }
+
+    /// The term without its sign.
+    fn tree(&self) -> Ast {
+        let coefficient = self.coefficient.abs();
+        let mut numerator = Vec::new();
+        let mut denominator = Vec::new();
+        if coefficient.numerator != Decimal::ONE || self.factors.is_empty() {
+            numerator.push(Ast::new(Token::Literal(coefficient.numerator)));
+        }
+        if !coefficient.is_integer() {
+            denominator.push(Ast::new(Token::Literal(coefficient.denominator)));
+        }
+        for (base, exponent) in &self.factors {
+            let (side, exponent) = if exponent.is_negative() {
+                (&mut denominator, exponent.abs())
+            } else {
+                (&mut numerator, *exponent)
+            };
+            side.push(if exponent == Rational::ONE {
+                base.clone()
+            } else {
+                Ast::binary(Token::Pow, base.clone(), exponent.tree())
+            });
+        }
+        let product = |factors: Vec<Ast>| {
+            factors
+                .into_iter()
+                .reduce(|product, factor| Ast::binary(Token::Mul, product, factor))
+        };
+        let numerator =
+            product(numerator).unwrap_or_else(|| Ast::new(Token::Literal(Decimal::ONE)));
+        match product(denominator) {
+            Some(denominator) => Ast::binary(Token::Div, numerator, denominator),
+            None => numerator,
+        }
+    }
+}
+
+/// Sum of terms with pairwise different factors and non zero coefficients,
+/// except for terms that cancelled out but can fail, which are kept with a
+/// zero coefficient so that evaluating the sum still fails.
+#[derive(Debug, Clone, Default)]
+struct Sum(Vec<Term>);
+
+impl Sum {
+    fn constant(x: Rational) -> Self {
+        match x.is_zero() {
+            true => Self::default(),
+            false => Self::term(Term::constant(x)),
+        }
+    }
+
+    fn term(term: Term) -> Self {
+        Self(vec![term])
+    }
+
+    fn as_constant(&self) -> Option<Rational> {
+        match self.0.as_slice() {
+            [] => Some(Rational::ZERO),
+            [term] if term.factors.is_empty() => Some(term.coefficient),
+            _ => None,
+        }
+    }
+
+    fn can_fail(&self, env: &Environment) -> bool {
+        self.0.iter().any(|term| term.can_fail(env))
+    }
+
+    /// Adds a term, combining it with a like term if there is one.
+    fn plus(mut self, term: Term, env: &Environment) -> Result<Self, ComputeError> {
+        match self
+            .0
+            .iter()
+            .position(|other| other.factors == term.factors)
+        {
+            Some(i) => {
+                let sum = self.0[i].coefficient.checked_add(&term.coefficient)?;
+                if sum.is_zero() && !term.can_fail(env) {
+                    self.0.remove(i);
+                } else {
+                    self.0[i].coefficient = sum;
+                }
+            }
+            None if term.coefficient.is_zero() && !term.can_fail(env) => (),
+            None => self.0.push(term),
+        }
+        Ok(self)
+    }
+
+    fn checked_add(self, other: Self, env: &Environment) -> Result<Self, ComputeError> {
+        other
+            .0
+            .into_iter()
+            .try_fold(self, |sum, term| sum.plus(term, env))
+    }
+
+    fn checked_neg(self, env: &Environment) -> Result<Self, ComputeError> {
+        self.checked_mul(&Self::constant(Rational::integer(-Decimal::ONE)), env)
+    }
+
+    fn checked_mul(&self, other: &Self, env: &Environment) -> Result<Self, ComputeError> {
+        // Zero is the empty sum, multiplying by it keeps the terms that can
+        // fail with a zero coefficient
+        let zero = [Term::constant(Rational::ZERO)];
+        let terms = |sum: &Self| match sum.0.is_empty() {
+            true => zero.to_vec(),
+            false => sum.0.clone(),
+        };
+        let mut result = Self::default();
+        for left in &terms(self) {
+            for right in &terms(other) {
+                result = result.plus(left.checked_mul(right, env)?, env)?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn checked_pow(self, exponent: Self, env: &Environment) -> Result<Self, ComputeError> {
+        let Some(n) = exponent.as_constant() else {
+            return Ok(Self::term(Term::atom(Ast::binary(
+                Token::Pow,
+                self.tree(),
+                exponent.tree(),
+            ))));
+        };
+        let integer = if n.is_integer() {
+            n.numerator.to_i64()
+        } else {
+            None
+        };
+        match (self.0.as_slice(), integer) {
+            (_, Some(0)) if !self.can_fail(env) => Ok(Self::constant(Rational::ONE)),
+            (_, Some(0)) => Ok(Self::term(Term::power(self.tree(), n))),
+            (_, Some(1)) => Ok(self),
+            ([], _) if !n.is_negative() => Ok(Self::default()),
+            // 0^n is left for the evaluation to report
+            ([], _) => Ok(Self::term(Term::power(self.tree(), n))),
+            ([term], Some(n)) if term.can_raise(n, env) => {
+                Ok(Self::term(term.checked_powi(n, env)?))
+            }
+            ([term], None) if term.factors.is_empty() => {
+                // 4^0.5 is folded, 2^0.5 is kept
+                let base = self.tree();
+                let tree = fold(Ast::binary(Token::Pow, base.clone(), n.tree()), env);
+                match tree.literal(tree.root()) {
+                    Some(x) => Ok(Self::constant(Rational::from_decimal(x)?)),
+                    None => Ok(Self::term(Term::power(base, n))),
+                }
+            }
+            ([_, _, ..], Some(2..=MAX_EXPANSION)) => {
+                let mut result = self.clone();
+                for _ in 1..integer.unwrap_or_default() {
+                    result = result.checked_mul(&self, env)?;
+                }
+                Ok(result)
+            }
+            _ => Ok(Self::term(Term::power(self.tree(), n))),
+        }
+    }
+
+    fn tree(mut self) -> Ast {
+        // Highest degree first, numbers last
+        self.0.sort_by(|a, b| {
+            b.degree()
+                .cmp(&a.degree())
+                .then(b.factors.len().min(1).cmp(&a.factors.len().min(1)))
+                .then_with(|| a.key().cmp(&b.key()))
+        });
+        let mut result: Option<Ast> = None;
+        for term in self.0 {
+            let negative = term.coefficient.is_negative();
+            let tree = term.tree();
+            result = Some(match (result, negative) {
+                (None, false) => tree,
+                (None, true) => {
+                    Ast::binary(Token::Sub, Ast::new(Token::Literal(Decimal::ZERO)), tree)
+                }
+                (Some(sum), false) => Ast::binary(Token::Add, sum, tree),
+                (Some(sum), true) => Ast::binary(Token::Sub, sum, tree),
+            });
+        }
+        result.unwrap_or_else(|| Ast::new(Token::Literal(Decimal::ZERO)))
+    }
+}
+
+/// Whether the whole of `tree` is constant.
+fn is_constant(tree: &Ast) -> bool {
+    optimize::is_constant(tree, tree.root())
+}
+
+/// Replaces a function of constants by its value when that value is exact,
+/// `sqrt(4)` becomes `2` while `sqrt(2)` is kept.
+fn fold(tree: Ast, env: &Environment) -> Ast {
+    if !is_constant(&tree) {
+        return tree;
+    }
+    match compute_tree(&tree, &mut env.detached()) {
+        Ok(Value::Number(x)) if x.is_integer() => Ast::new(Token::Literal(x.normalize())),
+        Ok(Value::Bool(true)) => Ast::new(Token::True),
+        Ok(Value::Bool(false)) => Ast::new(Token::False),
+        _ => tree,
+    }
+}
+
+/// The sum of powers of `base` if their product has the same values and
+/// the same errors: `x^2 * x^3` is `x^5` but `x^2 / x` fails where `x` is 0
+/// and is kept, as is `x * x^0.5` which differs from `x^1.5` for negative
+/// `x`.
+fn combine(
+    base: &Ast,
+    exponents: &[Rational],
+    env: &Environment,
+) -> Result<Option<Rational>, ComputeError> {
+    let sum = exponents
+        .iter()
+        .try_fold(Rational::ZERO, |sum, exponent| sum.checked_add(exponent))?;
+    let same = exponents.iter().all(Rational::is_integer)
+        && sum.is_negative() == exponents.iter().any(Rational::is_negative);
+    Ok((is_positive(base, env) || same).then_some(sum))
+}
+
+/// Whether `tree` is a constant that evaluates to a positive number.
+fn is_positive(tree: &Ast, env: &Environment) -> bool {
+    is_constant(tree)
+        && matches!(
+            compute_tree(tree, &mut env.detached()),
+            Ok(Value::Number(x)) if x > Decimal::ZERO
+        )
+}
+
+/// Whether evaluating `tree` may fail for some values of the variables,
+/// which simplifying must then not drop.
+fn can_fail(tree: &Ast, env: &Environment) -> bool {
+    can_fail_at(tree, tree.root(), env)
+}
+
+/// Whether the node `id` may fail. Anything but arithmetic, `sin` and
+/// `cos` is assumed to fail somewhere.
+fn can_fail_at(ast: &Ast, id: NodeId, env: &Environment) -> bool {
+    if optimize::is_constant(ast, id) {
+        return compute_tree(&ast.subtree(id), &mut env.detached()).is_err();
+    }
+    let kind = &ast.node(id).kind;
+    let children = || {
+        kind.children()
+            .into_iter()
+            .any(|child| can_fail_at(ast, child, env))
+    };
+    match kind {
+        NodeKind::Identifier(_) => false,
+        NodeKind::Binary(Token::Add | Token::Sub | Token::Mul | Token::ImplMul, _, _)
+        | NodeKind::Call(Token::Sin | Token::Cos, _) => children(),
+        NodeKind::Binary(Token::Div, _, right)
+            if ast.literal(*right).is_some_and(|x| !x.is_zero()) =>
+        {
+            children()
+        }
+        NodeKind::Binary(Token::Pow, _, right)
+            if ast
+                .literal(*right)
+                .is_some_and(|x| x.is_integer() && !x.is_sign_negative()) =>
+        {
+            children()
+        }
+        _ => true,
+    }
+}
+
+/// Simplifies the children of a node that is not an arithmetic operation.
+fn simplify_children(ast: &Ast, id: NodeId, env: &Environment) -> Result<Ast, ComputeError> {
+    let kind = &ast.node(id).kind;
+    let children = kind
+        .children()
+        .into_iter()
+        .map(|child| expand(ast, child, env).map(Sum::tree))
+        .collect::<Result<_, _>>()?;
+    Ok(Ast::compose(kind, children))
+}
+
+fn expand(ast: &Ast, id: NodeId, env: &Environment) -> Result<Sum, ComputeError> {
+    env.check()?;
+    let (token, left, right) = match &ast.node(id).kind {
+        NodeKind::Literal(Token::Literal(x)) => {
+            return Ok(Sum::constant(Rational::from_decimal(*x)?))
+        }
+        NodeKind::Literal(_) | NodeKind::Identifier(_) => {
+            return Ok(Sum::term(Term::atom(ast.subtree(id))))
+        }
+        NodeKind::Binary(token, left, right) => (token, *left, *right),
+        _ => {
+            let tree = fold(simplify_children(ast, id, env)?, env);
+            return Ok(Sum::term(Term::atom(tree)));
+        }
+    };
+    match token {
+        Token::Add => expand(ast, left, env)?.checked_add(expand(ast, right, env)?, env),
+        Token::Sub => {
+            let right = expand(ast, right, env)?.checked_neg(env)?;
+            expand(ast, left, env)?.checked_add(right, env)
+        }
+        Token::Mul | Token::ImplMul => {
+            expand(ast, left, env)?.checked_mul(&expand(ast, right, env)?, env)
+        }
+        Token::Div => {
+            let numerator = expand(ast, left, env)?;
+            let denominator = expand(ast, right, env)?;
+            if denominator.as_constant() == Some(Rational::ZERO) {
+                // Kept so that evaluating it still reports the division
+                return Ok(Sum::term(Term::atom(Ast::binary(
+                    Token::Div,
+                    numerator.tree(),
+                    denominator.tree(),
+                ))));
+            }
+            let minus_one = Sum::constant(Rational::integer(-Decimal::ONE));
+            let inverse = denominator.checked_pow(minus_one, env)?;
+            numerator.checked_mul(&inverse, env)
+        }
+        Token::Pow => expand(ast, left, env)?.checked_pow(expand(ast, right, env)?, env),
+        _ => {
+            let tree = fold(simplify_children(ast, id, env)?, env);
+            Ok(Sum::term(Term::atom(tree)))
+        }
+    }
+}
+
+/// Folds constants, drops identities such as `x * 1` and `x + 0`, combines
+/// like terms and collects powers of the same base. The result has the
+/// values of `tree` and fails where it fails: `0 * ln(x)` and `x / x` are
+/// not dropped. Constants are evaluated with the settings and within the
+/// limits of `env`.
+pub fn simplify(tree: &Ast, env: &Environment) -> Result<Ast, ComputeError> {
+    Ok(expand(tree, tree.root(), env)?.tree())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::parser::{parse_statements, Statement};
+    use crate::tokenizer::tokenize;
+
+    fn value(tree: &Ast, x: Decimal) -> Option<Value> {
+        let mut env = Environment::default();
+        env.with_variable("x", Value::Number(x), |env| compute_tree(tree, env).ok())
+    }
+
+    /// Simplifies `input` to `expected`, which must evaluate like `input`
+    /// and fail where it does.
+    fn check(input: &str, expected: &str) {
+        let statements = parse_statements(tokenize(input.to_string()).unwrap());
+        let Ok([Statement::Expression(tree)]) = statements.as_deref() else {
+            panic!("{input} is not an expression");
+        };
+        let Ok(simplified) = simplify(tree, &Environment::default()) else {
+            panic!("{input} does not simplify");
+        };
+        assert_eq!(simplified.to_string(), expected);
+        for x in [dec!(-2), dec!(-1), dec!(0), dec!(0.5), dec!(1), dec!(3)] {
+            assert_eq!(value(tree, x), value(&simplified, x), "{input} at {x}");
+        }
+    }
+
+    #[test]
+    fn combines_like_terms() {
+        check("x + x", "2x");
+        check("2 * x + 3 * x - 5 * x", "0");
+        check("(x + 1)^2 - x^2", "2x + 1");
+        check("x^3 * x^2", "x^5");
+        check("1 / x^2 / x", "1 / x^3");
+    }
+
+    #[test]
+    fn keeps_what_can_fail() {
+        check("0 * (1 / 0)", "0(1 / 0)");
+        check("ln(x) - ln(x)", "0ln(x)");
+        check("x / x", "x / x");
+        check("x^2 / x", "x^2 / x");
+        check("1 / (1 / x)", "1 / (1 / x)");
+        check("x^0.5 * x^0.5", "x^(1 / 2) * x^(1 / 2)");
+        check("ln(x)^0", "ln(x)^0");
+    }
+
+    #[test]
+    fn folds_with_the_settings_and_limits_of_the_environment() {
+        let tree = |input: &str| match parse_statements(tokenize(input.to_string()).unwrap()) {
+            Ok(mut statements) => statements.remove(0).ast().clone(),
+            Err(_) => panic!("{input} does not parse"),
+        };
+        let mut env = Environment::default();
+        env.settings.degrees = true;
+        let simplified = simplify(&tree("x + sin(90)"), &env).ok();
+        assert_eq!(
+            simplified.map(|tree| tree.to_string()).as_deref(),
+            Some("x + 1")
+        );
+
+        env.cancel.cancel();
+        let cancelled = simplify(&tree("x * x + x"), &env);
+        assert!(matches!(cancelled, Err(ComputeError::Interrupted)));
+    }
+}
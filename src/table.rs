@@ -0,0 +1,146 @@
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::value::Value;
+
+/// Values of an expression over a range of its variable. A row that could
+/// not be evaluated keeps the error message in place of its value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    variable: String,
+    label: String,
+    rows: Vec<(Decimal, Result<Value, String>)>,
+}
+
+impl Table {
+    pub fn new(
+        variable: String,
+        label: String,
+        rows: Vec<(Decimal, Result<Value, String>)>,
+    ) -> Self {
+        Self {
+            variable,
+            label,
+            rows,
+        }
+    }
+
+    pub fn round_dp_with_strategy(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+        let rows = self
+            .rows
+            .iter()
+            .map(|(x, y)| {
+                (
+                    x.round_dp_with_strategy(dp, strategy),
+                    y.as_ref()
+                        .map(|y| y.round_dp_with_strategy(dp, strategy))
+                        .map_err(String::clone),
+                )
+            })
+            .collect();
+        Self::new(self.variable.clone(), self.label.clone(), rows)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.rows.iter().map(|(x, y)| {
+            let y = match y {
+                Ok(y) => y.to_string(),
+                Err(err) => format!("Math error: {err}"),
+            };
+            (x.to_string(), y)
+        })
+    }
+
+    /// The table as delimiter separated values with a header row, quoting
+    /// fields as CSV does where needed.
+    pub fn delimited(&self, delimiter: char) -> String {
+        let field = |text: &str| {
+            if text.contains([delimiter, '"', '\n']) {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            } else {
+                text.to_string()
+            }
+        };
+        let mut output = format!(
+            "{}{delimiter}{}\n",
+            field(&self.variable),
+            field(&self.label)
+        );
+        for (x, y) in self.cells() {
+            output.push_str(&format!("{}{delimiter}{}\n", field(&x), field(&y)));
+        }
+        output
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells: Vec<_> = self.cells().collect();
+        let width = cells
+            .iter()
+            .map(|(x, _)| x.chars().count())
+            .chain([self.variable.chars().count()])
+            .max()
+            .unwrap_or_default();
+        let value_width = cells
+            .iter()
+            .map(|(_, y)| y.chars().count())
+            .chain([self.label.chars().count()])
+            .max()
+            .unwrap_or_default();
+        write!(f, "{:>width$} │ {}", self.variable, self.label)?;
+        write!(f, "\n{}─┼─{}", "─".repeat(width), "─".repeat(value_width))?;
+        for (x, y) in cells {
+            write!(f, "\n{x:>width$} │ {y}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::{execute, Environment};
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn table(input: &str) -> Option<Table> {
+        let statements = parse_statements(tokenize(input.to_string()).ok()?).ok()?;
+        match execute(statements.first()?, &mut Environment::default()).ok()? {
+            Some(Value::Table(table)) => Some(table),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn keeps_failing_rows_in_place() {
+        let table = table("table(1/x, x, -1, 1, 1)").expect("a table");
+        assert_eq!(
+            table.to_string(),
+            " x │ 1 / x\n\
+             ───┼─────────────────────────────\n\
+             -1 │ -1\n \
+             0 │ Math error: Division by zero\n \
+             1 │ 1"
+        );
+    }
+
+    #[test]
+    fn quotes_delimited_fields() {
+        let rows = vec![
+            (Decimal::ONE, Ok(Value::Number(Decimal::TWO))),
+            (Decimal::TWO, Err("Not real, \"really\"".to_string())),
+        ];
+        let table = Table::new("x".to_string(), "f(x, 1)".to_string(), rows);
+        assert_eq!(
+            table.delimited(','),
+            "x,\"f(x, 1)\"\n1,2\n2,\"Math error: Not real, \"\"really\"\"\"\n"
+        );
+        assert_eq!(
+            table.delimited('\t'),
+            "x\tf(x, 1)\n1\t2\n2\t\"Math error: Not real, \"\"really\"\"\"\n"
+        );
+    }
+}
@@ -1,13 +1,29 @@
+use std::fmt::Display;
+
 use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ast::Span;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Token {
     Add,
     Sub,
+    PlusMinus,
     ImplMul,
     Mul,
     Div,
     Pow,
+    Less,
+    LessEqual,
+    Equal,
+    NotEqual,
+    GreaterEqual,
+    Greater,
+    And,
+    Or,
+    Not,
+    If,
     Sin,
     Cos,
     Tan,
@@ -15,14 +31,59 @@ pub enum Token {
     Ln,
     Log,
     Sqrt,
+    Transpose,
+    Det,
+    Inv,
+    Rank,
+    Trace,
+    Eye,
+    Solve,
+    Choose,
+    Permute,
+    IsPrime,
+    NextPrime,
+    Factor,
+    Totient,
+    Fib,
+    PowMod,
+    Gamma,
+    LGamma,
+    Beta,
+    Diff,
+    Root,
+    Integrate,
+    Sum,
+    Prod,
+    PolyRoots,
+    Plot,
+    Table,
+    Interval,
     Factorial,
+    DoubleFactorial,
     OpenParenthesis,
     CloseParenthesis,
+    OpenBracket,
+    CloseBracket,
     Comma,
+    Semicolon,
+    Assign,
+    /// `?` of `condition ? then : otherwise`.
+    Question,
+    /// `:` of a conditional, or a division outside of one.
+    Colon,
+    Matrix,
+    Identifier(String),
+    Call(String),
     Literal(Decimal),
+    /// Measurement written as `9.81(2)`, the value and its standard
+    /// deviation. It is the product `9.81 * 2` unless the `sigma` setting is
+    /// on.
+    Measurement(Decimal, Decimal),
     Ans,
     PI,
     E,
+    True,
+    False,
 }
 
 impl TryFrom<char> for Token {
@@ -32,14 +93,23 @@ impl TryFrom<char> for Token {
         Ok(match value {
             '+' => Self::Add,
             '-' => Self::Sub,
+            '±' => Self::PlusMinus,
             '*' => Self::Mul,
-            ':' | '/' => Self::Div,
+            '/' => Self::Div,
+            '?' => Self::Question,
+            ':' => Self::Colon,
             '^' => Self::Pow,
+            '<' => Self::Less,
+            '>' => Self::Greater,
             '√' => Self::Sqrt,
             '!' => Self::Factorial,
             '(' => Self::OpenParenthesis,
             ')' => Self::CloseParenthesis,
+            '[' => Self::OpenBracket,
+            ']' => Self::CloseBracket,
             ',' => Self::Comma,
+            ';' => Self::Semicolon,
+            '=' => Self::Assign,
             _ => return Err(()),
         })
     }
@@ -48,23 +118,55 @@ impl TryFrom<char> for Token {
 impl TryFrom<String> for Token {
     type Error = ();
 
-    fn try_from(mut value: String) -> Result<Self, ()> {
-        if value.chars().next().ok_or(())?.is_digit(10) {
+    fn try_from(value: String) -> Result<Self, ()> {
+        if value.chars().next().ok_or(())?.is_ascii_digit() {
             Ok(Self::Literal(value.parse::<Decimal>().map_err(|_| ())?))
         } else {
-            value.make_ascii_lowercase();
-            Ok(match value.as_str() {
+            Ok(match value.to_ascii_lowercase().as_str() {
                 "ans" => Self::Ans,
                 "pi" | "π" => Self::PI,
                 "e" => Self::E,
+                "true" => Self::True,
+                "false" => Self::False,
+                "and" => Self::And,
+                "or" => Self::Or,
+                "not" => Self::Not,
+                "if" => Self::If,
                 "sin" => Self::Sin,
                 "cos" => Self::Cos,
                 "tan" => Self::Tan,
                 "exp" => Self::Exp,
                 "ln" => Self::Ln,
-                "Log" => Self::Log,
+                "log" => Self::Log,
                 "sqrt" => Self::Sqrt,
-                _ => return Err(())
+                "transpose" => Self::Transpose,
+                "det" => Self::Det,
+                "inv" => Self::Inv,
+                "rank" => Self::Rank,
+                "trace" => Self::Trace,
+                "eye" => Self::Eye,
+                "solve" => Self::Solve,
+                "ncr" | "choose" => Self::Choose,
+                "npr" => Self::Permute,
+                "isprime" => Self::IsPrime,
+                "nextprime" => Self::NextPrime,
+                "factor" => Self::Factor,
+                "totient" => Self::Totient,
+                "fib" => Self::Fib,
+                "powmod" => Self::PowMod,
+                "gamma" => Self::Gamma,
+                "lgamma" => Self::LGamma,
+                "beta" => Self::Beta,
+                "diff" => Self::Diff,
+                "root" => Self::Root,
+                "integrate" => Self::Integrate,
+                "sum" => Self::Sum,
+                "prod" | "product" => Self::Prod,
+                "polyroots" => Self::PolyRoots,
+                "plot" => Self::Plot,
+                "table" => Self::Table,
+                "interval" => Self::Interval,
+                _ => Self::Identifier(value),
             })
         }
     }
@@ -72,70 +174,271 @@ impl TryFrom<String> for Token {
 
 impl Token {
     pub fn is_value(&self) -> bool {
+        matches!(
+            self,
+            Token::Literal(_)
+                | Token::Measurement(..)
+                | Token::PI
+                | Token::E
+                | Token::Ans
+                | Token::True
+                | Token::False
+                | Token::Identifier(_)
+        )
+    }
+
+    /// Minimum and maximum number of arguments accepted by a function token.
+    pub fn arity(&self) -> (usize, usize) {
         match self {
-            Token::Literal(_) | Token::PI | Token::E | Token::Ans => true,
-            _ => false,
+            Token::Log => (1, 2),
+            Token::Choose | Token::Permute | Token::Beta | Token::Interval => (2, 2),
+            Token::PowMod | Token::If => (3, 3),
+            Token::Solve | Token::Diff | Token::Root => (2, 3),
+            Token::Integrate | Token::Sum | Token::Prod => (4, 4),
+            Token::PolyRoots => (1, usize::MAX),
+            Token::Plot => (4, usize::MAX),
+            Token::Table => (5, 5),
+            _ => (1, 1),
         }
     }
 }
 
-pub fn tokenize(source: String) -> Option<Vec<Token>> {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut iterator = source.chars().peekable();
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add => write!(f, "+"),
+            Self::Sub => write!(f, "-"),
+            Self::PlusMinus => write!(f, "±"),
+            Self::ImplMul | Self::Mul => write!(f, "*"),
+            Self::Div => write!(f, "/"),
+            Self::Pow => write!(f, "^"),
+            Self::Less => write!(f, "<"),
+            Self::LessEqual => write!(f, "<="),
+            Self::Equal => write!(f, "=="),
+            Self::NotEqual => write!(f, "!="),
+            Self::GreaterEqual => write!(f, ">="),
+            Self::Greater => write!(f, ">"),
+            Self::And => write!(f, "and"),
+            Self::Or => write!(f, "or"),
+            Self::Not => write!(f, "not"),
+            Self::If => write!(f, "if"),
+            Self::Sin => write!(f, "sin"),
+            Self::Cos => write!(f, "cos"),
+            Self::Tan => write!(f, "tan"),
+            Self::Exp => write!(f, "exp"),
+            Self::Ln => write!(f, "ln"),
+            Self::Log => write!(f, "log"),
+            Self::Sqrt => write!(f, "sqrt"),
+            Self::Transpose => write!(f, "transpose"),
+            Self::Det => write!(f, "det"),
+            Self::Inv => write!(f, "inv"),
+            Self::Rank => write!(f, "rank"),
+            Self::Trace => write!(f, "trace"),
+            Self::Eye => write!(f, "eye"),
+            Self::Solve => write!(f, "solve"),
+            Self::Choose => write!(f, "nCr"),
+            Self::Permute => write!(f, "nPr"),
+            Self::IsPrime => write!(f, "isprime"),
+            Self::NextPrime => write!(f, "nextprime"),
+            Self::Factor => write!(f, "factor"),
+            Self::Totient => write!(f, "totient"),
+            Self::Fib => write!(f, "fib"),
+            Self::PowMod => write!(f, "powmod"),
+            Self::Gamma => write!(f, "gamma"),
+            Self::LGamma => write!(f, "lgamma"),
+            Self::Beta => write!(f, "beta"),
+            Self::Diff => write!(f, "diff"),
+            Self::Root => write!(f, "root"),
+            Self::Integrate => write!(f, "integrate"),
+            Self::Sum => write!(f, "sum"),
+            Self::Prod => write!(f, "prod"),
+            Self::PolyRoots => write!(f, "polyroots"),
+            Self::Plot => write!(f, "plot"),
+            Self::Table => write!(f, "table"),
+            Self::Interval => write!(f, "interval"),
+            Self::Factorial => write!(f, "!"),
+            Self::DoubleFactorial => write!(f, "!!"),
+            Self::OpenParenthesis => write!(f, "("),
+            Self::CloseParenthesis => write!(f, ")"),
+            Self::OpenBracket | Self::Matrix => write!(f, "["),
+            Self::CloseBracket => write!(f, "]"),
+            Self::Comma => write!(f, ","),
+            Self::Semicolon => write!(f, ";"),
+            Self::Assign => write!(f, "="),
+            Self::Question => write!(f, "?"),
+            Self::Colon => write!(f, ":"),
+            Self::Identifier(name) | Self::Call(name) => write!(f, "{name}"),
+            Self::Literal(x) => write!(f, "{x}"),
+            Self::Measurement(x, sigma) => {
+                let digits = sigma * Decimal::TEN.powi(x.scale().into());
+                write!(f, "{x}({})", digits.normalize())
+            }
+            Self::Ans => write!(f, "ans"),
+            Self::PI => write!(f, "pi"),
+            Self::E => write!(f, "e"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+        }
+    }
+}
+
+/// Number of parentheses and brackets left open, input continues on the next
+/// line while this is positive.
+pub fn open_groups(tokens: &[Token]) -> isize {
+    tokens.iter().fold(0, |depth, token| match token {
+        Token::OpenParenthesis | Token::OpenBracket => depth + 1,
+        Token::CloseParenthesis | Token::CloseBracket => depth - 1,
+        _ => depth,
+    })
+}
+
+/// Splits `source` into tokens, or gives the byte offset of the first
+/// character that does not start a valid token.
+pub fn tokenize(source: String) -> Result<Vec<Token>, usize> {
+    let tokens = tokenize_spanned(&source)?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Like `tokenize`, together with the bytes every token was read from.
+/// Implicit multiplications get an empty span where they were inserted.
+pub fn tokenize_spanned(source: &str) -> Result<Vec<(Token, Span)>, usize> {
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
+    let mut iterator = source.char_indices().peekable();
+    let next_char = |iterator: &mut std::iter::Peekable<std::str::CharIndices>| {
+        iterator.next().map(|(_, c)| c).unwrap_or_default()
+    };
+    let span = |start: usize, iterator: &mut std::iter::Peekable<std::str::CharIndices>| Span {
+        start,
+        end: iterator.peek().map_or(source.len(), |&(end, _)| end),
+    };
+    let implied = |start: usize| (Token::ImplMul, Span { start, end: start });
 
-    while let Some(c) = iterator.next() {
+    while let Some((start, c)) = iterator.next() {
         if c.is_whitespace() {
             continue;
+        } else if c == '#' {
+            // Comment until the end of the line
+            for (_, d) in iterator.by_ref() {
+                if d == '\n' {
+                    break;
+                }
+            }
         } else if c.is_alphabetic() {
             let mut literal = String::with_capacity(3);
             literal.push(c);
-            while let Some(&d) = iterator.peek() {
+            while let Some(&(_, d)) = iterator.peek() {
                 if d.is_alphabetic() {
-                    literal.push(iterator.next().unwrap_or_default());
+                    literal.push(next_char(&mut iterator));
                 } else {
                     break;
                 }
             }
-            let token = literal.try_into().ok()?;
-            if let Some(prev) = tokens.last() {
-                if prev.is_value() {
-                    tokens.push(Token::ImplMul);
+            let token = literal.try_into().map_err(|_| start)?;
+            if let Some((prev, _)) = tokens.last() {
+                if prev.is_value() && !matches!(token, Token::And | Token::Or) {
+                    tokens.push(implied(start));
                 }
             }
-            tokens.push(token);
-        } else if c.is_digit(10) {
+            tokens.push((token, span(start, &mut iterator)));
+        } else if c.is_ascii_digit() {
             let mut literal = String::new();
             literal.push(c);
             let mut dot_appeared = false;
-            while let Some(&d) = iterator.peek() {
+            while let Some(&(_, d)) = iterator.peek() {
                 if d == '.' {
                     if dot_appeared {
-                        return None;
+                        return Err(start);
                     } else {
                         dot_appeared = true;
                     }
-                    literal.push(iterator.next().unwrap_or_default());
+                    literal.push(next_char(&mut iterator));
                     continue;
                 }
-                if !d.is_digit(10) {
+                if !d.is_ascii_digit() {
                     break;
                 }
-                literal.push(iterator.next().unwrap_or_default());
+                literal.push(next_char(&mut iterator));
+            }
+            // 9.81(2) is 9.81 with a standard deviation of 0.02 in the last
+            // digits, whether it is one or a product is up to the settings
+            let mut lookahead = iterator.clone().map(|(_, d)| d);
+            if dot_appeared && lookahead.next() == Some('(') {
+                let digits: String = lookahead
+                    .by_ref()
+                    .take_while(|d| d.is_ascii_digit())
+                    .collect();
+                let closed = iterator.clone().nth(digits.len() + 1).map(|(_, d)| d) == Some(')');
+                let value: Option<Decimal> = literal.parse().ok();
+                let sigma = value.zip(digits.parse().ok()).and_then(|(value, last)| {
+                    Decimal::try_from_i128_with_scale(last, value.scale()).ok()
+                });
+                if let (Some(value), Some(sigma), true) = (value, sigma, closed) {
+                    iterator.nth(digits.len() + 1);
+                    tokens.push((Token::Measurement(value, sigma), span(start, &mut iterator)));
+                    continue;
+                }
             }
-            let token = literal.try_into().ok()?;
-            tokens.push(token);
+            let token = literal.try_into().map_err(|_| start)?;
+            tokens.push((token, span(start, &mut iterator)));
         } else {
-            let token = c.try_into().ok()?;
-            if let Token::OpenParenthesis | Token::Sqrt = token {
-                if let Some(prev) = tokens.last() {
-                    if prev.is_value() {
-                        tokens.push(Token::ImplMul);
+            // +/- is an ASCII spelling of ±
+            if c == '+' && iterator.clone().map(|(_, d)| d).take(2).eq(['/', '-']) {
+                iterator.nth(1);
+                tokens.push((Token::PlusMinus, span(start, &mut iterator)));
+                continue;
+            }
+            let token = match (c, iterator.peek().map(|&(_, d)| d)) {
+                ('!', Some('!')) => Token::DoubleFactorial,
+                ('!', Some('=')) => Token::NotEqual,
+                ('=', Some('=')) => Token::Equal,
+                ('<', Some('=')) => Token::LessEqual,
+                ('>', Some('=')) => Token::GreaterEqual,
+                _ => c.try_into().map_err(|_| start)?,
+            };
+            if let Token::DoubleFactorial
+            | Token::NotEqual
+            | Token::Equal
+            | Token::LessEqual
+            | Token::GreaterEqual = token
+            {
+                iterator.next();
+            }
+            if let Token::OpenParenthesis | Token::OpenBracket | Token::Sqrt = token {
+                if let Some((prev, _)) = tokens.last() {
+                    // An identifier followed by a parenthesis is a function call
+                    let call = matches!(
+                        (&token, prev),
+                        (Token::OpenParenthesis, Token::Identifier(_))
+                    );
+                    if prev.is_value() && !call {
+                        tokens.push(implied(start));
                     }
                 }
             }
-            tokens.push(token);
+            tokens.push((token, span(start, &mut iterator)));
         }
     }
 
-    Some(tokens)
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_statements;
+
+    #[test]
+    fn skips_comments_and_continues_open_groups() {
+        let tokens = |source: &str| tokenize(source.to_string()).unwrap();
+        assert_eq!(tokens("1 + 2 # 3 + 4"), tokens("1 + 2"));
+        assert_eq!(tokens("x # (\n+ 1"), tokens("x + 1"));
+        // A statement continues on the next line while a group is open
+        let first = "m = [[1, 2], # first row\n";
+        assert_eq!(open_groups(&tokens(first)), 1);
+        let input = format!("{first}[3, 4]]; n = 2;\ndet(m) * n");
+        assert_eq!(open_groups(&tokens(&input)), 0);
+        let statements = parse_statements(tokens(&input));
+        assert!(matches!(statements.as_deref(), Ok([_, _, _])));
+        assert_eq!(open_groups(&tokens("(1))")), -1);
+    }
 }
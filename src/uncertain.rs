@@ -0,0 +1,262 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::compute::ComputeError;
+
+/// Identifies the independent measurements, so that a quantity used twice
+/// is correlated with itself.
+static SOURCES: AtomicUsize = AtomicUsize::new(0);
+
+/// Value with a standard deviation, propagated to first order. The error is
+/// kept as the contribution of every independent measurement it depends
+/// on, which makes `x - x` exactly zero.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Saved")]
+pub struct Uncertain {
+    pub value: Decimal,
+    /// `(source, ∂value/∂source · σ_source)` sorted by source.
+    terms: Vec<(usize, Decimal)>,
+}
+
+/// Uncertain value as read from a saved session, whose terms must have
+/// distinct sources in increasing order.
+#[derive(Deserialize)]
+struct Saved {
+    value: Decimal,
+    terms: Vec<(usize, Decimal)>,
+}
+
+impl TryFrom<Saved> for Uncertain {
+    type Error = String;
+
+    fn try_from(saved: Saved) -> Result<Self, String> {
+        if saved.terms.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err("measurements out of order".to_string());
+        }
+        // Measurements made after loading must not share a source with the
+        // loaded ones
+        if let Some((source, _)) = saved.terms.last() {
+            let next = source.checked_add(1).ok_or("too many measurements")?;
+            SOURCES.fetch_max(next, Ordering::Relaxed);
+        }
+        Ok(Self {
+            value: saved.value,
+            terms: saved.terms,
+        })
+    }
+}
+
+fn checked(x: Option<Decimal>) -> Result<Decimal, ComputeError> {
+    x.ok_or(ComputeError::Overflow)
+}
+
+/// Rounds `x` to a multiple of `10^-dp`, where `dp` may be negative.
+fn round_at(x: Decimal, dp: i32) -> Decimal {
+    let strategy = RoundingStrategy::MidpointAwayFromZero;
+    if dp >= 0 {
+        let mut x = x.round_dp_with_strategy(dp as u32, strategy);
+        // Keep trailing zeros, they are significant
+        x.rescale(dp as u32);
+        x
+    } else {
+        let unit = Decimal::TEN.powi(-dp as i64);
+        (x / unit).round_dp_with_strategy(0, strategy) * unit
+    }
+}
+
+impl Uncertain {
+    /// A new independent measurement.
+    pub fn new(value: Decimal, sigma: Decimal) -> Self {
+        let terms = match sigma.is_zero() {
+            true => Vec::new(),
+            false => vec![(SOURCES.fetch_add(1, Ordering::Relaxed), sigma.abs())],
+        };
+        Self { value, terms }
+    }
+
+    pub fn exact(value: Decimal) -> Self {
+        Self::new(value, Decimal::ZERO)
+    }
+
+    pub fn sigma(&self) -> Result<Decimal, ComputeError> {
+        let variance = self.terms.iter().try_fold(Decimal::ZERO, |sum, (_, x)| {
+            checked(x.checked_mul(*x).and_then(|x| x.checked_add(sum)))
+        })?;
+        checked(variance.sqrt())
+    }
+
+    /// `a x + b y` to first order, where the value is computed separately.
+    fn combine(
+        value: Decimal,
+        (x, a): (&Self, Decimal),
+        (y, b): (&Self, Decimal),
+    ) -> Result<Self, ComputeError> {
+        let mut terms = Vec::with_capacity(x.terms.len() + y.terms.len());
+        let (mut i, mut j) = (0, 0);
+        while i < x.terms.len() || j < y.terms.len() {
+            let (source, term) = match (x.terms.get(i), y.terms.get(j)) {
+                (Some(&(s, u)), Some(&(t, _))) if s < t => {
+                    i += 1;
+                    (s, checked(u.checked_mul(a))?)
+                }
+                (Some(&(s, _)), Some(&(t, v))) if t < s => {
+                    j += 1;
+                    (t, checked(v.checked_mul(b))?)
+                }
+                (Some(&(s, u)), Some(&(_, v))) => {
+                    i += 1;
+                    j += 1;
+                    let term = u.checked_mul(a).zip(v.checked_mul(b));
+                    (s, checked(term.and_then(|(u, v)| u.checked_add(v)))?)
+                }
+                (Some(&(s, u)), None) => {
+                    i += 1;
+                    (s, checked(u.checked_mul(a))?)
+                }
+                (None, Some(&(t, v))) => {
+                    j += 1;
+                    (t, checked(v.checked_mul(b))?)
+                }
+                (None, None) => unreachable!(),
+            };
+            if !term.is_zero() {
+                terms.push((source, term));
+            }
+        }
+        Ok(Self { value, terms })
+    }
+
+    /// `f(self)` given its value and the slope of `f` at `self`.
+    pub fn map(&self, value: Decimal, slope: Decimal) -> Result<Self, ComputeError> {
+        let mut terms = Vec::with_capacity(self.terms.len());
+        for &(source, term) in &self.terms {
+            let term = checked(term.checked_mul(slope))?;
+            if !term.is_zero() {
+                terms.push((source, term));
+            }
+        }
+        Ok(Self { value, terms })
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self, ComputeError> {
+        let value = checked(self.value.checked_add(other.value))?;
+        Self::combine(value, (self, Decimal::ONE), (other, Decimal::ONE))
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self, ComputeError> {
+        let value = checked(self.value.checked_sub(other.value))?;
+        Self::combine(value, (self, Decimal::ONE), (other, -Decimal::ONE))
+    }
+
+    pub fn checked_mul(&self, other: &Self) -> Result<Self, ComputeError> {
+        let value = checked(self.value.checked_mul(other.value))?;
+        Self::combine(value, (self, other.value), (other, self.value))
+    }
+
+    pub fn checked_div(&self, other: &Self) -> Result<Self, ComputeError> {
+        if other.value.is_zero() {
+            return Err(ComputeError::DivByZero);
+        }
+        let value = checked(self.value.checked_div(other.value))?;
+        let a = checked(Decimal::ONE.checked_div(other.value))?;
+        let b = checked(value.checked_div(other.value))?;
+        Self::combine(value, (self, a), (other, -b))
+    }
+
+    /// `self^other`, the exponent only contributes if it is uncertain
+    /// since ln of a negative base is undefined.
+    pub fn checked_pow(&self, other: &Self) -> Result<Self, ComputeError> {
+        let (x, y) = (self.value, other.value);
+        let value = checked(x.checked_powd(y))?;
+        let a = match x.is_zero() {
+            true if y == Decimal::ONE => Decimal::ONE,
+            true => Decimal::ZERO,
+            false => checked(y.checked_mul(value).and_then(|slope| slope.checked_div(x)))?,
+        };
+        let b = match other.terms.is_empty() {
+            true => Decimal::ZERO,
+            false => {
+                let ln = x.checked_ln().ok_or(ComputeError::NotReal)?;
+                checked(value.checked_mul(ln))?
+            }
+        };
+        Self::combine(value, (self, a), (other, b))
+    }
+}
+
+/// `value ± σ` with σ to two significant figures and the value to the
+/// same decimal place.
+impl Display for Uncertain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sigma = self.sigma().unwrap_or(Decimal::MAX);
+        if sigma.is_zero() {
+            return write!(f, "{}", self.value.normalize());
+        }
+        // Decimal place of the second significant digit of σ
+        let mut dp: i32 = 1;
+        let mut scaled = sigma;
+        while scaled < Decimal::ONE && dp < 27 {
+            scaled *= Decimal::TEN;
+            dp += 1;
+        }
+        while scaled >= Decimal::TEN {
+            scaled /= Decimal::TEN;
+            dp -= 1;
+        }
+        write!(f, "{} ± {}", round_at(self.value, dp), round_at(sigma, dp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::{execute, Environment};
+    use crate::parser::parse_statements;
+    use crate::tokenizer::tokenize;
+
+    fn run(input: &str, env: &mut Environment) -> Option<String> {
+        let statements = parse_statements(tokenize(input.to_string()).ok()?).ok()?;
+        let mut result = None;
+        for statement in statements {
+            result = execute(&statement, env)
+                .ok()?
+                .map(|value| value.to_string());
+        }
+        result
+    }
+
+    #[test]
+    fn reads_measurements_only_with_sigma() {
+        let mut env = Environment::default();
+        assert_eq!(run("1.5(2)", &mut env).as_deref(), Some("3.0"));
+        env.settings.sigma = true;
+        assert_eq!(run("1.5(2)", &mut env).as_deref(), Some("1.50 ± 0.20"));
+        assert_eq!(
+            run("9.81(2) * 3.0(1)", &mut env).as_deref(),
+            Some("29.43 ± 0.98")
+        );
+        assert_eq!(run("15(2)", &mut env).as_deref(), Some("30"));
+    }
+
+    #[test]
+    fn correlates_a_measurement_with_itself() {
+        let mut env = Environment::default();
+        env.settings.sigma = true;
+        assert_eq!(run("x = 2.0(1); x - x", &mut env).as_deref(), Some("0"));
+        assert_eq!(
+            run("x = 2.0(1); y = 2.0(1); x - y", &mut env).as_deref(),
+            Some("0.00 ± 0.14")
+        );
+    }
+
+    #[test]
+    fn compares_by_the_central_value() {
+        let mut env = Environment::default();
+        env.settings.sigma = true;
+        assert_eq!(run("9.81(2) < 10", &mut env).as_deref(), Some("true"));
+        assert_eq!(run("9.81(2) == 9.81", &mut env).as_deref(), Some("true"));
+        assert_eq!(run("2.0(1) >= 2.1(1)", &mut env).as_deref(), Some("false"));
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::Display;
+
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ast::Ast;
+use crate::complex::Complex;
+use crate::interval::Interval;
+use crate::matrix::Matrix;
+use crate::plot::Plot;
+use crate::table::Table;
+use crate::uncertain::Uncertain;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Number(Decimal),
+    Bool(bool),
+    Matrix(Matrix),
+    /// Prime factorization as `(prime, exponent)` pairs.
+    Factorization(Vec<(u128, u32)>),
+    /// Symbolic result such as a derivative.
+    Expression(Ast),
+    Complex(Complex),
+    /// Range of values known to contain the exact result.
+    Interval(Interval),
+    /// Measurement with a standard deviation.
+    Uncertain(Uncertain),
+    /// Several results, such as all roots of a polynomial.
+    List(Vec<Value>),
+    Plot(Plot),
+    Table(Table),
+}
+
+impl Value {
+    pub fn round_dp_with_strategy(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+        match self {
+            Self::Number(x) => Self::Number(x.round_dp_with_strategy(dp, strategy)),
+            Self::Matrix(matrix) => Self::Matrix(matrix.round_dp_with_strategy(dp, strategy)),
+            Self::Complex(z) => Self::Complex(z.round_dp_with_strategy(dp, strategy)),
+            // Intervals are always rounded outward
+            Self::Interval(x) => Self::Interval(x.round_dp(dp)),
+            Self::Table(table) => Self::Table(table.round_dp_with_strategy(dp, strategy)),
+            Self::List(values) => Self::List(
+                values
+                    .iter()
+                    .map(|x| x.round_dp_with_strategy(dp, strategy))
+                    .collect(),
+            ),
+            // Shown to the significant figures of its standard deviation
+            Self::Uncertain(_)
+            | Self::Bool(_)
+            | Self::Factorization(_)
+            | Self::Expression(_)
+            | Self::Plot(_) => self.clone(),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(x) => write!(f, "{x}"),
+            Self::Bool(x) => write!(f, "{x}"),
+            Self::Matrix(matrix) => write!(f, "{matrix}"),
+            Self::Expression(tree) => write!(f, "{tree}"),
+            Self::Complex(z) => write!(f, "{z}"),
+            Self::Interval(x) if f.alternate() => write!(f, "{x:#}"),
+            Self::Interval(x) => write!(f, "{x}"),
+            Self::Uncertain(x) => write!(f, "{x}"),
+            Self::Plot(plot) => write!(f, "{plot}"),
+            Self::Table(table) => write!(f, "{table}"),
+            Self::List(values) => {
+                write!(f, "[")?;
+                for (i, x) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match f.alternate() {
+                        true => write!(f, "{x:#}")?,
+                        false => write!(f, "{x}")?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Self::Factorization(factors) if factors.is_empty() => write!(f, "1"),
+            Self::Factorization(factors) => {
+                for (i, (p, e)) in factors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " * ")?;
+                    }
+                    match e {
+                        1 => write!(f, "{p}")?,
+                        _ => write!(f, "{p}^{e}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}